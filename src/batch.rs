@@ -1,28 +1,162 @@
 // src/batch.rs
 use anyhow::{Context, Result};
+use gdal::Dataset;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::fs;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use rayon::prelude::*;
-use rayon::ThreadPoolBuilder;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
 
 use crate::utils::cache::RasterCache;
+use crate::utils::mosaic::{self, OverlapRule};
+use crate::utils::manifest::{self, Manifest};
+use crate::utils::memory::{self, format_bytes, GdalCacheShare, MemoryShares};
+use crate::utils::provenance::timestamp_now;
+use crate::utils::report::{BatchSummary, OperationReport, OperationStatus, ProcessStats, ReportWriter};
+use crate::utils::retry::{OnError, RetryPolicy};
+use crate::processing::scheduler::Scheduler;
+use crate::utils::{product, stac};
+use crate::utils::output::OutputFormat;
+use crate::utils::template::{self, Variables};
 use std::collections::HashSet;
 
-use crate::processing::indices::{BSI, EVI, MSAVI2, NDI, NDSI, NDWI, OSAVI, SAVI};
+use crate::processing::composite::{Composite, CompositeRule};
+use crate::processing::timeseries;
+use crate::processing::change::ChangeDetection;
+use crate::processing::phenology::{self, Smoothing};
+use crate::processing::gapfill::{self, FillMethod};
+use crate::utils::output;
+use crate::processing::classify::{self, ClassOutput, Classification, RasterValues};
+use crate::processing::render::{self, Colormap, RenderOutput, Renderer};
+use crate::processing::mask::Mask;
+use crate::processing::indices::{self, BSI, EVI, MSAVI2, NDI, NDSI, NDWI, OSAVI, SAVI};
+use crate::processing::parallel::{IndexCalculator, SMALL_RASTER_SIDE};
+use crate::processing::timeseries::StackInput;
 use crate::processing::ParallelProcessor;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 pub struct BatchConfig {
     #[serde(default)]
     pub global: GlobalParams,
+    #[serde(default)]
     pub operations: Vec<Operation>,
+    /// Scenes the templates are expanded over
+    pub scenes: Option<Scenes>,
+    /// Operations with `{variable}` placeholders, repeated for every scene
+    #[serde(default)]
+    #[schemars(with = "Vec<Operation>")]
+    pub templates: Vec<Value>,
+}
+
+/// Scenes of templated operations: explicit variables, or the variables captured from the
+/// files matching a pattern such as `data/{tile}/S2_{date}_B04.tif`
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum Scenes {
+    List(Vec<Variables>),
+    Pattern { pattern: String },
+}
+
+impl Scenes {
+    /// Variables of every scene, pattern matches sorted and deduplicated
+    pub fn resolve(&self) -> Result<Vec<Variables>> {
+        match self {
+            Scenes::List(scenes) => Ok(scenes.clone()),
+            Scenes::Pattern { pattern } => {
+                let glob_pattern = template::glob_pattern(pattern)?;
+                let mut scenes = std::collections::BTreeSet::new();
+                for path in glob::glob(&glob_pattern)
+                    .with_context(|| format!("Invalid scene pattern '{}'", pattern))?
+                {
+                    let path = path?;
+                    if let Some(variables) = template::capture(pattern, &path.to_string_lossy())? {
+                        scenes.insert(variables);
+                    }
+                }
+                if scenes.is_empty() {
+                    return Err(anyhow::anyhow!("No files match the scene pattern '{}'", pattern));
+                }
+                Ok(scenes.into_iter().collect())
+            }
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Read a JSON, YAML (`.yaml`, `.yml`) or TOML (`.toml`) batch configuration
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read batch configuration {}", path.display()))?;
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let value: Value = match extension.as_str() {
+            "yaml" | "yml" => serde_yaml::from_str(&content).map_err(anyhow::Error::from),
+            "toml" => toml::from_str(&content).map_err(anyhow::Error::from),
+            _ => serde_json::from_str(&content).map_err(anyhow::Error::from),
+        }
+        .with_context(|| format!("Failed to parse batch configuration {}", path.display()))?;
+        Self::from_value(value)
+            .with_context(|| format!("Invalid batch configuration {}", path.display()))
+    }
+
+    /// Configuration from its JSON form; errors name the section or operation at fault
+    pub fn from_value(value: Value) -> Result<Self> {
+        if let Some(global) = value.get("global") {
+            serde_json::from_value::<GlobalParams>(global.clone()).context("In 'global'")?;
+        }
+        if let Some(operations) = value.get("operations").and_then(Value::as_array) {
+            for (i, op) in operations.iter().enumerate() {
+                serde_json::from_value::<Operation>(op.clone())
+                    .with_context(|| format!("In operation {}", i + 1))?;
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Append the templates, with their placeholders filled in, to the operations once per scene
+    pub fn expand_templates(&mut self) -> Result<()> {
+        if self.templates.is_empty() {
+            return Ok(());
+        }
+        let scenes = self
+            .scenes
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Batch templates need a 'scenes' section"))?
+            .resolve()?;
+
+        for scene in &scenes {
+            for (t, template) in self.templates.iter().enumerate() {
+                let name = || {
+                    let variables: Vec<String> =
+                        scene.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                    format!("Template {} for scene {}", t + 1, variables.join(", "))
+                };
+                let value = template::substitute_value(template, scene).with_context(name)?;
+                let operation: Operation = serde_json::from_value(value).with_context(name)?;
+                self.operations.push(operation);
+            }
+        }
+        println!(
+            "Expanded {} template(s) over {} scene(s)",
+            self.templates.len(),
+            scenes.len()
+        );
+        Ok(())
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, Default)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default)]
 pub struct GlobalParams {
     #[serde(default = "default_compress")]
     pub compress: String,
@@ -38,6 +172,52 @@ pub struct GlobalParams {
     pub tiled: bool,
     #[serde(default)]
     pub threads: Option<usize>,
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default)]
+    pub mosaic_rule: Option<String>,
+    /// Sentinel-2 (.SAFE or .zip) or Landsat product that band names ("B04") or roles ("nir") refer to
+    #[serde(default)]
+    pub product: Option<String>,
+    /// Local STAC item whose assets are referred to by key or common name ("nir", "swir16")
+    #[serde(default)]
+    pub stac_item: Option<String>,
+    #[serde(default)]
+    pub resolution: Option<u32>,
+    /// Apply the scale/offset found in band, Sentinel-2 or Landsat metadata (default: true)
+    #[serde(default)]
+    pub auto_scale: Option<bool>,
+    /// Mask applied to every operation without its own
+    #[serde(default)]
+    pub mask: Option<MaskParams>,
+    /// Write a STAC item next to every output
+    #[serde(default)]
+    pub write_stac: bool,
+    /// Manifest of incremental runs (default: `<config>.manifest.json` next to the configuration)
+    #[serde(default)]
+    pub manifest: Option<String>,
+    /// After a failed operation: "continue" (default) or "fail_fast" to start no further operation
+    #[serde(default)]
+    pub on_error: Option<String>,
+    /// Retries of a failed operation
+    #[serde(default)]
+    pub retries: u32,
+    /// Seconds before the first retry, doubling before each next one (default: 2)
+    #[serde(default)]
+    pub retry_delay: Option<f64>,
+    /// Block reader threads shared by all operations (default: one per core, at least 4)
+    #[serde(default)]
+    pub io_threads: Option<usize>,
+    /// Input blocks read ahead of processing across all operations, in MB (default: 1024)
+    #[serde(default)]
+    pub max_inflight_mb: Option<u64>,
+    /// Datasets kept open in the shared cache (default: no limit)
+    #[serde(default)]
+    pub max_open_datasets: Option<usize>,
+    /// Memory of the whole run, e.g. "12G" (plain numbers are MB): sizes GDAL's cache, the
+    /// read-ahead and blocks, and how many operations run at once
+    #[serde(default)]
+    pub max_memory: Option<String>,
 }
 
 fn default_compress() -> String {
@@ -56,53 +236,89 @@ fn default_true() -> bool {
     true
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Operation types are matched without case, `Change` is read as `change`
+fn lowercase<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    String::deserialize(deserializer).map(|value| value.to_lowercase())
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 pub struct Operation {
-    #[serde(rename = "type")]
+    /// Name other operations use to refer to this one's output ("@id")
+    pub id: Option<String>,
+    #[serde(rename = "type", deserialize_with = "lowercase")]
     pub op_type: String,
+    #[schemars(schema_with = "params_schema")]
     pub params: Value,
     pub output: String,
+    /// Operations to run before this one, besides those whose output it refers to
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Keep the output in memory for the operations using it instead of writing it to disk
+    #[serde(default)]
+    pub intermediate: bool,
     pub float: Option<bool>,
     pub scale_factor: Option<i32>,
     pub input_scale_factor: Option<f32>,
     pub compress: Option<String>,
     pub compress_level: Option<u8>,
     pub tiled: Option<bool>,
+    pub format: Option<String>,
+    pub mosaic_rule: Option<String>,
+    pub product: Option<String>,
+    pub stac_item: Option<String>,
+    pub resolution: Option<u32>,
+    pub auto_scale: Option<bool>,
+    /// Class map of the result: break/rule string or JSON classification
+    pub classify: Option<Value>,
+    pub class_output: Option<String>,
+    /// Output band to classify (1-based)
+    pub class_band: Option<usize>,
+    /// RGBA rendering (and quicklook) of the result
+    pub render: Option<RenderParams>,
+    pub mask: Option<MaskParams>,
+    pub write_stac: Option<bool>,
+    pub on_error: Option<String>,
+    pub retries: Option<u32>,
+    pub retry_delay: Option<f64>,
+    /// Keys that are not options, ignored but reported by validation
+    #[serde(flatten, skip_serializing)]
+    #[schemars(skip)]
+    pub unknown: BTreeMap<String, Value>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct EviParams {
     pub a: String,
     pub b: String,
     pub c: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct NdiParams {
     pub a: String,
     pub b: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct SaviParams {
     pub a: String,
     pub b: String,
     pub l: Option<f32>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct NdwiParams {
     pub a: String,
     pub b: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct NdsiParams {
     pub a: String,
     pub b: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct BsiParams {
     pub s: String,
     pub r: String,
@@ -110,202 +326,1049 @@ pub struct BsiParams {
     pub b: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct MsaviParams {
     pub a: String,
     pub b: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct OsaviParams {
     pub a: String,
     pub b: String,
 }
 
-pub fn process_batch(config_path: &PathBuf) -> Result<()> {
-    let config_content = fs::read_to_string(config_path)?;
-    let config: BatchConfig = serde_json::from_str(&config_content)?;
+/// Values of an existing raster, e.g. another operation's output
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct RasterParams {
+    pub input: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct CompositeParams {
+    pub index: String,
+    #[serde(default = "default_composite_rule")]
+    pub rule: String,
+    /// Band parameters of each acquisition, in the format of the index operation
+    pub scenes: Vec<Value>,
+    #[serde(default)]
+    pub cloud: Vec<String>,
+    pub l: Option<f32>,
+    #[serde(default)]
+    pub source_band: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ClassifyParams {
+    pub input: String,
+    /// Break/rule string or JSON classification
+    pub rules: Value,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+pub struct RenderParams {
+    #[serde(default = "default_colormap")]
+    pub colormap: String,
+    /// Values mapped to the ends of the colormap
+    pub range: Option<[f32; 2]>,
+    /// RGBA GeoTIFF when rendering another operation's result (default: <output>_rgba.tif)
+    pub output: Option<String>,
+    /// Output band to render (1-based)
+    #[serde(default = "default_render_band")]
+    pub band: usize,
+    pub quicklook: Option<String>,
+    #[serde(default = "default_quicklook_size")]
+    pub quicklook_size: usize,
+}
+
+/// Cloud/shadow/snow mask applied to every output
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+pub struct MaskParams {
+    /// Mask band: a path or a product band name ("SCL", "QA_PIXEL")
+    pub input: String,
+    /// scl, qa_pixel, modis, classes or bits (default: guessed from the input name)
+    #[serde(rename = "type")]
+    pub mask_type: Option<String>,
+    /// Class/bit names or numbers (default: cloud, shadow, snow)
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct RenderOpParams {
+    pub input: String,
+    #[serde(flatten)]
+    pub render: RenderParams,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct TimeseriesParams {
+    pub inputs: Vec<String>,
+    #[serde(default = "default_timeseries_stats")]
+    pub stats: Vec<String>,
+    #[serde(default)]
+    pub dates: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ChangeParams {
+    pub index: String,
+    /// Band parameters of the pre and post acquisitions, in the format of the index operation
+    pub pre: Value,
+    pub post: Value,
+    pub l: Option<f32>,
+    #[serde(default)]
+    pub thresholds: Vec<f32>,
+    pub class_output: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct PhenologyParams {
+    pub inputs: Vec<String>,
+    #[serde(default = "default_phenology_method")]
+    pub method: String,
+    #[serde(default = "default_phenology_window")]
+    pub window: usize,
+    #[serde(default = "default_phenology_order")]
+    pub order: usize,
+    #[serde(default = "default_phenology_threshold")]
+    pub threshold: f32,
+    #[serde(default)]
+    pub dates: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct GapfillParams {
+    pub inputs: Vec<String>,
+    #[serde(default = "default_gapfill_method")]
+    pub method: String,
+    #[serde(default = "default_gapfill_harmonics")]
+    pub harmonics: usize,
+    #[serde(default = "default_gapfill_lambda")]
+    pub lambda: f64,
+    pub interval: Option<u32>,
+    #[serde(default)]
+    pub per_date: bool,
+    #[serde(default)]
+    pub dates: Vec<String>,
+}
+
+fn default_colormap() -> String {
+    "RdYlGn".to_string()
+}
+
+fn default_render_band() -> usize {
+    1
+}
+
+fn default_quicklook_size() -> usize {
+    render::DEFAULT_QUICKLOOK_SIZE
+}
+
+fn default_gapfill_method() -> String {
+    "linear".to_string()
+}
+
+fn default_gapfill_harmonics() -> usize {
+    2
+}
+
+fn default_gapfill_lambda() -> f64 {
+    100.0
+}
+
+fn default_phenology_method() -> String {
+    "savgol".to_string()
+}
+
+fn default_phenology_window() -> usize {
+    7
+}
+
+fn default_phenology_order() -> usize {
+    2
+}
+
+fn default_phenology_threshold() -> f32 {
+    0.5
+}
+
+fn default_timeseries_stats() -> Vec<String> {
+    ["mean", "median", "count", "slope", "sen_slope", "mk_p"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn default_composite_rule() -> String {
+    "max".to_string()
+}
+
+/// Product or STAC item of an operation, the operation's own before the global one
+fn operation_product<'a>(op: &'a Operation, global: &'a GlobalParams) -> Option<&'a str> {
+    op.stac_item
+        .as_deref()
+        .or(op.product.as_deref())
+        .or(global.stac_item.as_deref())
+        .or(global.product.as_deref())
+}
+
+/// Output band (0-based) the class map of an operation is made of
+fn class_band(op: &Operation) -> Result<usize> {
+    match op.class_band.unwrap_or(1) {
+        0 => Err(anyhow::anyhow!("class_band is 1-based")),
+        band => Ok(band - 1),
+    }
+}
+
+/// RGBA rendering of band `params.band` written to `path`
+fn build_render_output(params: &RenderParams, path: &str) -> Result<RenderOutput> {
+    let renderer = Renderer::new(
+        Colormap::parse(&params.colormap)?,
+        params.range.map(|[min, max]| (min, max)),
+    )?;
+    if params.band == 0 {
+        return Err(anyhow::anyhow!("band is 1-based"));
+    }
+    RenderOutput::new(renderer, path, params.band - 1)
+        .with_quicklook(params.quicklook.clone(), params.quicklook_size)
+}
+
+/// Ordered input paths of an index operation's params (same order as the index calculator)
+fn index_band_paths(index: &str, params: &Value) -> Result<Vec<String>> {
+    let paths = match index.to_lowercase().as_str() {
+        "ndi" => {
+            let p: NdiParams = serde_json::from_value(params.clone())?;
+            vec![p.a, p.b]
+        }
+        "evi" => {
+            let p: EviParams = serde_json::from_value(params.clone())?;
+            vec![p.a, p.b, p.c]
+        }
+        "savi" => {
+            let p: SaviParams = serde_json::from_value(params.clone())?;
+            vec![p.a, p.b]
+        }
+        "ndwi" => {
+            let p: NdwiParams = serde_json::from_value(params.clone())?;
+            vec![p.a, p.b]
+        }
+        "ndsi" => {
+            let p: NdsiParams = serde_json::from_value(params.clone())?;
+            vec![p.a, p.b]
+        }
+        "bsi" => {
+            let p: BsiParams = serde_json::from_value(params.clone())?;
+            vec![p.s, p.r, p.n, p.b]
+        }
+        "msavi2" => {
+            let p: MsaviParams = serde_json::from_value(params.clone())?;
+            vec![p.a, p.b]
+        }
+        "osavi" => {
+            let p: OsaviParams = serde_json::from_value(params.clone())?;
+            vec![p.a, p.b]
+        }
+        "raster" => {
+            let p: RasterParams = serde_json::from_value(params.clone())?;
+            vec![p.input]
+        }
+        _ => return Err(anyhow::anyhow!("Unknown index type: {}", index)),
+    };
+    Ok(paths)
+}
 
+/// Calculator of a change or composite: an index, or `"raster"` for the values of existing
+/// rasters such as other operations' outputs, which must share scale and nodata
+fn stack_calculator(
+    index: &str,
+    soil_factor: Option<f32>,
+    paths: &[String],
+    mosaic_rule: OverlapRule,
+    cache: &RasterCache,
+) -> Result<Box<dyn IndexCalculator>> {
+    if index.to_lowercase() != "raster" {
+        return indices::from_name(index, soil_factor);
+    }
+    let resolved = paths
+        .iter()
+        .map(|path| mosaic::resolve_input(path, mosaic_rule, cache))
+        .collect::<Result<Vec<_>>>()?;
+    let encoding = StackInput::from_path(&resolved[0])?;
+    for path in &resolved[1..] {
+        if StackInput::from_path(path)? != encoding {
+            return Err(anyhow::anyhow!(
+                "{} and {} differ in scale, offset or nodata",
+                resolved[0],
+                path
+            ));
+        }
+    }
+    Ok(Box::new(RasterValues::new(encoding, "Raster")))
+}
 
+/// All input paths of a change detection: pre bands followed by post bands
+fn change_paths(p: &ChangeParams) -> Result<Vec<String>> {
+    let mut paths = index_band_paths(&p.index, &p.pre)?;
+    paths.extend(index_band_paths(&p.index, &p.post)?);
+    Ok(paths)
+}
 
-    // Get thread count from config or use default calculation
-    let thread_count = config.global.threads.unwrap_or_else(|| {
-        std::cmp::max(4, (num_cpus::get() as f32 * 0.6) as usize)
+/// All input paths of a composite: scene bands in order, followed by the cloud bands
+fn composite_paths(p: &CompositeParams) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for scene in &p.scenes {
+        paths.extend(index_band_paths(&p.index, scene)?);
+    }
+    paths.extend(p.cloud.iter().cloned());
+    Ok(paths)
+}
+
+/// Operations referring to another operation's output: `"@ndvi_pre"` in their params
+const OUTPUT_REFERENCE: &str = "@";
+
+/// Dependencies between batch operations, by operation index
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchGraph {
+    pub dependencies: Vec<Vec<usize>>,
+    pub dependents: Vec<Vec<usize>>,
+    /// Operations in an order where each comes after its dependencies
+    pub order: Vec<usize>,
+}
+
+impl BatchGraph {
+    /// Resolve `@id` references in the params (and masks) of `operations` to the referenced
+    /// outputs, move intermediate outputs to `scratch_dir` (the run's directory in `/vsimem/`,
+    /// see [`RasterCache::scratch_dir`]), and order the operations topologically
+    pub fn build(operations: &mut [Operation], scratch_dir: &str) -> Result<Self> {
+        let mut ids = HashMap::new();
+        for (i, op) in operations.iter().enumerate() {
+            if let Some(id) = &op.id {
+                if ids.insert(id.clone(), i).is_some() {
+                    return Err(anyhow::anyhow!("Duplicate operation id '{}'", id));
+                }
+            }
+        }
+
+        for (i, op) in operations.iter_mut().enumerate() {
+            if op.intermediate {
+                let file_name = Path::new(&op.output)
+                    .file_name()
+                    .map_or("output.tif".into(), |n| n.to_string_lossy());
+                op.output = format!("{}/{}_{}", scratch_dir, i + 1, file_name);
+            }
+        }
+        let outputs: Vec<String> = operations.iter().map(|op| op.output.clone()).collect();
+
+        let mut dependencies = vec![Vec::new(); operations.len()];
+        for (i, op) in operations.iter_mut().enumerate() {
+            let mut referenced = Vec::new();
+            resolve_references(&mut op.params, &ids, &outputs, &mut referenced)
+                .with_context(|| format!("Operation {}", i + 1))?;
+            if let Some(mask) = &mut op.mask {
+                let mut input = Value::String(mask.input.clone());
+                resolve_references(&mut input, &ids, &outputs, &mut referenced)
+                    .with_context(|| format!("Mask of operation {}", i + 1))?;
+                mask.input = input.as_str().unwrap_or_default().to_string();
+            }
+            for id in &op.depends_on {
+                let dependency = ids.get(id).ok_or_else(|| {
+                    anyhow::anyhow!("Operation {} depends on unknown operation '{}'", i + 1, id)
+                })?;
+                referenced.push(*dependency);
+            }
+            referenced.sort_unstable();
+            referenced.dedup();
+            dependencies[i] = referenced;
+        }
+
+        let mut dependents = vec![Vec::new(); operations.len()];
+        for (i, deps) in dependencies.iter().enumerate() {
+            for &dependency in deps {
+                dependents[dependency].push(i);
+            }
+        }
+
+        // An unused intermediate would only be held in memory until the run ends
+        if let Some(i) = (0..operations.len()).find(|&i| operations[i].intermediate && dependents[i].is_empty()) {
+            return Err(anyhow::anyhow!(
+                "Intermediate operation {} is not used by any other operation",
+                operations[i].id.clone().unwrap_or_else(|| format!("#{}", i + 1))
+            ));
+        }
+
+        // Kahn's algorithm, anything left over is part of a cycle
+        let mut remaining: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+        let mut ready: Vec<usize> = (0..operations.len()).filter(|&i| remaining[i] == 0).collect();
+        let mut order = Vec::with_capacity(operations.len());
+        while let Some(i) = ready.pop() {
+            order.push(i);
+            for &next in &dependents[i] {
+                remaining[next] -= 1;
+                if remaining[next] == 0 {
+                    ready.push(next);
+                }
+            }
+        }
+        if order.len() < operations.len() {
+            let cycle: Vec<String> = (0..operations.len())
+                .filter(|&i| remaining[i] > 0)
+                .map(|i| operations[i].id.clone().unwrap_or_else(|| format!("#{}", i + 1)))
+                .collect();
+            return Err(anyhow::anyhow!(
+                "Dependency cycle between operations: {}",
+                cycle.join(", ")
+            ));
+        }
+
+        Ok(Self {
+            dependencies,
+            dependents,
+            order,
+        })
+    }
+
+    /// Operations without dependencies
+    pub fn roots(&self) -> Vec<usize> {
+        self.order
+            .iter()
+            .copied()
+            .filter(|&i| self.dependencies[i].is_empty())
+            .collect()
+    }
+}
+
+/// Replace `"@id"` strings anywhere in `value` by the output of operation `id`
+fn resolve_references(
+    value: &mut Value,
+    ids: &HashMap<String, usize>,
+    outputs: &[String],
+    referenced: &mut Vec<usize>,
+) -> Result<()> {
+    match value {
+        Value::String(text) => {
+            if let Some(id) = text.strip_prefix(OUTPUT_REFERENCE) {
+                let i = *ids
+                    .get(id)
+                    .ok_or_else(|| anyhow::anyhow!("refers to unknown operation '{}'", id))?;
+                referenced.push(i);
+                *text = outputs[i].clone();
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                resolve_references(value, ids, outputs, referenced)?;
+            }
+        }
+        Value::Object(map) => {
+            for value in map.values_mut() {
+                resolve_references(value, ids, outputs, referenced)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Shared state of a batch run: operations start as soon as their dependencies finished
+struct BatchRun<'a> {
+    config: &'a BatchConfig,
+    graph: &'a BatchGraph,
+    cache: &'a Arc<RasterCache>,
+    scheduler: &'a Arc<Scheduler>,
+    mosaic_rule: OverlapRule,
+    errors: Mutex<Vec<String>>,
+    /// Operations to run, the others are up to date
+    run: &'a [bool],
+    report: Option<&'a ReportWriter>,
+    /// Outcome of each finished operation
+    statuses: Mutex<Vec<Option<OperationStatus>>>,
+    /// Unfinished dependencies of each operation
+    waiting: Vec<AtomicUsize>,
+    /// Unfinished dependents of each operation, intermediates are dropped at zero
+    users: Vec<AtomicUsize>,
+    failed: Vec<AtomicBool>,
+    /// Set once an operation failed with `on_error: fail_fast`
+    abort: AtomicBool,
+    /// Most operations running at once
+    max_running: usize,
+    /// Operations running, and those ready to start once one finished
+    slots: Mutex<(usize, VecDeque<usize>)>,
+    /// Operations with a final status
+    completed: AtomicUsize,
+    /// Retries to start later and the end of the run, see [`BatchRun::dispatch_retries`]
+    events: (flume::Sender<RunEvent>, flume::Receiver<RunEvent>),
+}
+
+/// An operation being attempted, carried over to its retries
+struct Attempt {
+    i: usize,
+    /// 0-based attempt number
+    number: u32,
+    started: Instant,
+    started_at: String,
+    cache_usage: (usize, usize),
+    /// Size and time of the outputs before the first attempt
+    before: Vec<Option<(u64, SystemTime)>>,
+}
+
+enum RunEvent {
+    /// Attempt again once the instant is reached
+    Retry(Instant, Attempt),
+    /// Every operation completed, or one panicked
+    Done,
+}
+
+/// Ends the dispatch of retries when an operation panics, so the scope re-raises the panic
+struct PanicGuard<'a>(&'a flume::Sender<RunEvent>);
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let _ = self.0.send(RunEvent::Done);
+        }
+    }
+}
+
+impl<'a> BatchRun<'a> {
+    fn new(
+        config: &'a BatchConfig,
+        graph: &'a BatchGraph,
+        cache: &'a Arc<RasterCache>,
+        scheduler: &'a Arc<Scheduler>,
+        mosaic_rule: OverlapRule,
+        run: &'a [bool],
+        report: Option<&'a ReportWriter>,
+    ) -> Self {
+        Self {
+            config,
+            graph,
+            cache,
+            scheduler,
+            mosaic_rule,
+            errors: Mutex::new(Vec::new()),
+            run,
+            report,
+            statuses: Mutex::new(vec![None; config.operations.len()]),
+            waiting: graph.dependencies.iter().map(|d| AtomicUsize::new(d.len())).collect(),
+            users: graph.dependents.iter().map(|d| AtomicUsize::new(d.len())).collect(),
+            failed: graph.dependencies.iter().map(|_| AtomicBool::new(false)).collect(),
+            abort: AtomicBool::new(false),
+            max_running: usize::MAX,
+            slots: Mutex::new((0, VecDeque::new())),
+            completed: AtomicUsize::new(0),
+            events: flume::unbounded(),
+        }
+    }
+
+    /// Run at most `max` operations at once, the others wait without holding a pool thread
+    fn limit_running(mut self, max: usize) -> Self {
+        self.max_running = max.max(1);
+        self
+    }
+
+    /// Start operation `i`, or queue it while `max_running` operations run
+    fn spawn<'s>(&'s self, scope: &rayon::Scope<'s>, i: usize) {
+        let mut slots = self.slots.lock().unwrap();
+        if slots.0 < self.max_running {
+            slots.0 += 1;
+            drop(slots);
+            self.start(scope, i);
+        } else {
+            slots.1.push_back(i);
+        }
+    }
+
+    /// Hand the slot of a finished operation to the next one queued
+    fn finish<'s>(&'s self, scope: &rayon::Scope<'s>) {
+        let mut slots = self.slots.lock().unwrap();
+        match slots.1.pop_front() {
+            Some(next) => {
+                drop(slots);
+                self.start(scope, next);
+            }
+            None => slots.0 -= 1,
+        }
+    }
+
+    /// Run operation `i` on the pool, or settle it at once when it is up to date, skipped or cancelled
+    fn start<'s>(&'s self, scope: &rayon::Scope<'s>, i: usize) {
+        scope.spawn(move |scope| {
+            let _guard = PanicGuard(&self.events.0);
+            let op = &self.config.operations[i];
+            let attempt = Attempt {
+                i,
+                number: 0,
+                started: Instant::now(),
+                started_at: timestamp_now(),
+                cache_usage: (0, 0),
+                before: Vec::new(),
+            };
+
+            let failed_dependency = self.graph.dependencies[i]
+                .iter()
+                .find(|&&d| self.failed[d].load(Ordering::SeqCst));
+            let (status, errors) = match failed_dependency {
+                _ if self.run[i] && self.abort.load(Ordering::SeqCst) => {
+                    (OperationStatus::Cancelled, Vec::new())
+                }
+                None if !self.run[i] => {
+                    println!(
+                        "[{}/{}] Up to date: {} -> {}",
+                        i + 1,
+                        self.config.operations.len(),
+                        op.op_type,
+                        op.output
+                    );
+                    (OperationStatus::UpToDate, Vec::new())
+                }
+                Some(&d) => {
+                    let error = format!("Skipped operation {}: operation {} it depends on failed", i + 1, d + 1);
+                    self.errors.lock().unwrap().push(error.clone());
+                    (OperationStatus::Skipped, vec![error])
+                }
+                None => {
+                    let before = operation_outputs(op, &self.config.global)
+                        .iter()
+                        .map(|path| manifest::file_stamp(path))
+                        .collect();
+                    let attempt = Attempt {
+                        cache_usage: self.cache_usage(op),
+                        before,
+                        ..attempt
+                    };
+                    return self.attempt(scope, attempt);
+                }
+            };
+            self.complete(scope, &attempt, status, errors, &ProcessStats::default());
+        });
+    }
+
+    /// Make one attempt at an operation. A transient failure with retries left is handed to
+    /// [`BatchRun::dispatch_retries`] to start again after the delay, without holding this thread.
+    fn attempt<'s>(&'s self, scope: &rayon::Scope<'s>, attempt: Attempt) {
+        let i = attempt.i;
+        let op = &self.config.operations[i];
+        let stats = Arc::new(ProcessStats::default());
+        let error = match run_operation(i, op, self.config, self.cache, self.scheduler, self.mosaic_rule, &stats) {
+            Ok(()) => return self.complete(scope, &attempt, OperationStatus::Succeeded, Vec::new(), &stats),
+            Err(e) => e,
+        };
+
+        let outputs = operation_outputs(op, &self.config.global);
+        remove_partial_outputs(&outputs, &attempt.before, self.cache);
+        // Validated before the run
+        let (on_error, retry) = failure_policy(op, &self.config.global).unwrap_or_default();
+        if let Some(delay) = retry.next_delay(attempt.number, &error) {
+            eprintln!(
+                "Operation {} failed (attempt {} of {}): {:#}, retrying in {:.1}s",
+                i + 1,
+                attempt.number + 1,
+                retry.retries + 1,
+                error,
+                delay.as_secs_f64()
+            );
+            let retry = Attempt {
+                number: attempt.number + 1,
+                ..attempt
+            };
+            let _ = self.events.0.send(RunEvent::Retry(Instant::now() + delay, retry));
+            return;
+        }
+
+        let mut errors = self.errors.lock().unwrap();
+        errors.push(format!("Operation {} failed: {:#}", i + 1, error));
+        if on_error == OnError::FailFast && !self.abort.swap(true, Ordering::SeqCst) {
+            errors.push(format!(
+                "Stopped the batch after operation {} failed (on_error: fail_fast)",
+                i + 1
+            ));
+        }
+        drop(errors);
+        let causes = error.chain().map(|cause| cause.to_string()).collect();
+        self.complete(scope, &attempt, OperationStatus::Failed, causes, &stats);
+    }
+
+    /// Record the outcome of an operation, then start the dependents it was the last dependency of
+    fn complete<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        attempt: &Attempt,
+        status: OperationStatus,
+        errors: Vec<String>,
+        stats: &ProcessStats,
+    ) {
+        let i = attempt.i;
+        let op = &self.config.operations[i];
+        if matches!(status, OperationStatus::Failed | OperationStatus::Skipped | OperationStatus::Cancelled) {
+            self.failed[i].store(true, Ordering::SeqCst);
+        }
+        self.statuses.lock().unwrap()[i] = Some(status);
+
+        if let Some(report) = self.report {
+            let ran = matches!(status, OperationStatus::Succeeded | OperationStatus::Failed);
+            let operation = OperationReport {
+                number: i + 1,
+                id: op.id.clone(),
+                op_type: op.op_type.clone(),
+                output: op.output.clone(),
+                status,
+                attempts: if ran { attempt.number + 1 } else { 0 },
+                errors,
+                started: attempt.started_at.clone(),
+                wall_seconds: attempt.started.elapsed().as_secs_f64(),
+                read_seconds: stats.read_seconds(),
+                compute_seconds: stats.compute_seconds(),
+                write_seconds: stats.write_seconds(),
+                bytes_read: stats.bytes_read(),
+                pixels: stats.pixels(),
+                cache_hits: attempt.cache_usage.0,
+                cache_misses: attempt.cache_usage.1,
+                bands: stats.bands(),
+            };
+            if let Err(e) = report.operation(operation) {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+
+        for &dependency in &self.graph.dependencies[i] {
+            if self.users[dependency].fetch_sub(1, Ordering::SeqCst) == 1 {
+                self.release(dependency);
+            }
+        }
+        self.finish(scope);
+        for &next in &self.graph.dependents[i] {
+            if self.waiting[next].fetch_sub(1, Ordering::SeqCst) == 1 {
+                self.spawn(scope, next);
+            }
+        }
+        if self.completed.fetch_add(1, Ordering::SeqCst) + 1 == self.config.operations.len() {
+            let _ = self.events.0.send(RunEvent::Done);
+        }
+    }
+
+    /// Start the roots, then start each retry once its delay passed until every operation
+    /// completed. Runs on the thread that started the batch, so waits hold no pool thread.
+    fn dispatch_retries<'s>(&'s self, scope: &rayon::Scope<'s>) {
+        for i in self.graph.roots() {
+            self.spawn(scope, i);
+        }
+        let mut due: Vec<(Instant, Attempt)> = Vec::new();
+        while self.completed.load(Ordering::SeqCst) < self.config.operations.len() {
+            let next = due.iter().map(|(at, _)| *at).min();
+            let event = match next {
+                Some(at) => self.events.1.recv_deadline(at),
+                None => self.events.1.recv().map_err(|_| flume::RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(RunEvent::Retry(at, attempt)) => due.push((at, attempt)),
+                Ok(RunEvent::Done) | Err(flume::RecvTimeoutError::Disconnected) => break,
+                Err(flume::RecvTimeoutError::Timeout) => {}
+            }
+
+            let now = Instant::now();
+            let mut k = 0;
+            while k < due.len() {
+                if due[k].0 <= now {
+                    let (_, attempt) = due.swap_remove(k);
+                    scope.spawn(move |scope| {
+                        let _guard = PanicGuard(&self.events.0);
+                        self.attempt(scope, attempt)
+                    });
+                } else {
+                    k += 1;
+                }
+            }
+        }
+    }
+
+    /// Inputs of an operation already open in the shared cache, and those not yet
+    fn cache_usage(&self, op: &Operation) -> (usize, usize) {
+        let settings = Settings::merge(op, &self.config.global);
+        let mosaic_rule = settings
+            .mosaic_rule
+            .as_deref()
+            .and_then(|name| OverlapRule::from_name(name).ok())
+            .unwrap_or(self.mosaic_rule);
+        let inputs = operation_inputs(op).unwrap_or_default();
+        let hits = inputs
+            .iter()
+            .filter_map(|input| product::qualify_input(input, settings.product.as_deref(), settings.resolution, self.cache).ok())
+            .filter_map(|input| mosaic::resolve_input(&input, mosaic_rule, self.cache).ok())
+            .filter(|path| self.cache.contains(path))
+            .count();
+        (hits, inputs.len() - hits)
+    }
+
+    /// Free an intermediate output once every operation using it finished
+    fn release(&self, i: usize) {
+        let op = &self.config.operations[i];
+        if op.intermediate {
+            self.cache.remove(&op.output);
+            let _ = gdal::vsi::unlink_mem_file(&op.output);
+        }
+    }
+}
+
+/// Fingerprint of every operation, from its type, params and merged settings, the size and
+/// time of its input files and the fingerprints of the operations producing its other inputs,
+/// with the time of its newest input file
+fn operation_fingerprints(config: &BatchConfig, graph: &BatchGraph, cache: &RasterCache) -> Vec<(String, Option<SystemTime>)> {
+    let producers: HashMap<&String, usize> = config
+        .operations
+        .iter()
+        .enumerate()
+        .map(|(i, op)| (&op.output, i))
+        .collect();
+    let mut fingerprints = vec![(String::new(), None); config.operations.len()];
+
+    for &i in &graph.order {
+        let op = &config.operations[i];
+        let settings = Settings::merge(op, &config.global);
+        let mut inputs = operation_inputs(op).unwrap_or_default();
+        inputs.extend(settings.mask.clone());
+
+        let mut newest: Option<SystemTime> = None;
+        let mut records = Vec::with_capacity(inputs.len());
+        for input in &inputs {
+            // Unreadable STAC items fail the operation when it runs
+            let input = product::qualify_input(input, settings.product.as_deref(), settings.resolution, cache)
+                .unwrap_or_else(|_| input.clone());
+            if let Some(&producer) = producers.get(&input) {
+                records.push(json!({"input": input, "operation": fingerprints[producer].0}));
+                continue;
+            }
+            let files = match product::resolve_band(&input, cache) {
+                Ok(Some(path)) => vec![path],
+                _ if mosaic::is_mosaic_input(&input) => mosaic::expand_tiles(&input).unwrap_or_default(),
+                _ => vec![input.clone()],
+            };
+            let stamps: Vec<Value> = files
+                .iter()
+                .map(|file| match manifest::file_stamp(file) {
+                    Some((size, modified)) => {
+                        newest = newest.max(Some(modified));
+                        json!([file, size, manifest::stamp_seconds(modified)])
+                    }
+                    None => json!([file]),
+                })
+                .collect();
+            records.push(json!({"input": input, "files": stamps}));
+        }
+
+        let description = json!({
+            "version": crate::VERSION,
+            "type": op.op_type.to_lowercase(),
+            "params": op.params,
+            "settings": settings,
+            "classify": op.classify,
+            "class_band": op.class_band,
+            "render": op.render,
+            "mask": op.mask.as_ref().or(config.global.mask.as_ref()),
+            "outputs": operation_outputs(op, &config.global),
+            "inputs": records,
+        });
+        fingerprints[i] = (manifest::fingerprint(&description), newest);
+    }
+    fingerprints
+}
+
+/// Operations to run: those the manifest does not record as up to date, those depending on one
+/// that runs, and intermediates whose output an operation that runs needs
+fn operations_to_run(
+    config: &BatchConfig,
+    graph: &BatchGraph,
+    manifest: &Manifest,
+    fingerprints: &[(String, Option<SystemTime>)],
+) -> Vec<bool> {
+    let mut run = vec![false; config.operations.len()];
+    for &i in &graph.order {
+        let (fingerprint, newest) = &fingerprints[i];
+        run[i] = !manifest.is_up_to_date(&config.operations[i].output, fingerprint, *newest)
+            || graph.dependencies[i].iter().any(|&d| run[d]);
+    }
+    for &i in graph.order.iter().rev() {
+        if config.operations[i].intermediate && !run[i] {
+            run[i] = graph.dependents[i].iter().any(|&d| run[d]);
+        }
+    }
+    run
+}
+
+/// Options of a batch run besides its configuration
+#[derive(Debug, Default, Clone)]
+pub struct BatchOptions {
+    /// Only print the plan, as `"text"` or `"json"`
+    pub dry_run: Option<String>,
+    /// Rerun the operations the manifest records as up to date
+    pub force: bool,
+    /// JSON run report, NDJSON for `.ndjson`/`.jsonl` paths
+    pub report: Option<PathBuf>,
+    /// Memory budget in bytes, overriding the configuration's `max_memory`
+    pub max_memory: Option<u64>,
+}
+
+/// Run a batch configuration, skipping the operations recorded as up to date in the manifest
+pub fn process_batch(config_path: &Path, options: &BatchOptions) -> Result<()> {
+    let started = Instant::now();
+    let started_at = timestamp_now();
+    let mut config = BatchConfig::load(config_path)?;
+    config.expand_templates()?;
+
+    // Datasets, products and `/vsimem/` files of this run, apart from other runs in the process
+    let cache = Arc::new(match config.global.max_open_datasets {
+        Some(capacity) => RasterCache::with_capacity(capacity),
+        None => RasterCache::new(),
     });
+    let graph = BatchGraph::build(&mut config.operations, &cache.scratch_dir())?;
+
+    // Configuration errors stop the batch before anything runs
+    let validation = validate_config(&config, false);
+    validation.print();
+    if !validation.errors.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} error(s) in batch configuration, nothing was processed",
+            validation.errors.len()
+        ));
+    }
+
+    // A budget that cannot fit the operations stops the batch before anything runs
+    let max_memory = match options.max_memory {
+        Some(bytes) => Some(bytes),
+        None => config.global.max_memory.as_deref().map(memory::parse_size).transpose()?,
+    };
+
+    if let Some(format) = &options.dry_run {
+        let mut plan = BatchPlan::new(&config, &graph);
+        if let Some(budget) = max_memory {
+            plan = plan.with_memory_budget(budget, &config.global)?;
+        }
+        match format.to_lowercase().as_str() {
+            "text" => plan.print(),
+            "json" => println!("{}", serde_json::to_string_pretty(&plan)?),
+            _ => return Err(anyhow::anyhow!("Unknown plan format '{}'. Expected text or json", format)),
+        }
+        return Ok(());
+    }
+
+    // A pool of its own, so batches with different thread counts can run in one process
+    let pool = thread_pool(&config.global)?;
+    println!("Running on a pool of {} threads (of {} available)", 
+             pool.current_num_threads(), num_cpus::get());
     
-    println!("Configuring thread pool with {} threads (of {} available)", 
-             thread_count, num_cpus::get());
-             
-    // Configure thread pool
-    ThreadPoolBuilder::new()
-        .num_threads(thread_count)
-        .build_global()
-        .unwrap_or_else(|e| eprintln!("Warning: Thread pool configuration failed: {}", e));
-    
-    // Create shared cache
-    let cache = Arc::new(RasterCache::new());
+    // The block readers and read-ahead memory shared by the operations
+    let memory_plan = match max_memory {
+        Some(budget) => {
+            let plan = BatchPlan::new(&config, &graph).with_memory_budget(budget, &config.global)?;
+            plan.memory
+        }
+        None => None,
+    };
+    // GDAL's block cache is process-wide: held until the run ends, at the smallest share of the
+    // batches running at once
+    let _gdal_cache = memory_plan.as_ref().map(|plan| GdalCacheShare::claim(plan.shares.gdal_cache));
+    if let Some(plan) = &memory_plan {
+        plan.print();
+    }
+    let scheduler = Arc::new(scheduler(&config.global, memory_plan.as_ref()));
+    println!(
+        "Sharing {} block reader threads and {} of read-ahead between operations",
+        scheduler.io_threads(),
+        format_bytes(scheduler.budget().capacity())
+    );
     
-    // Collect all unique paths
-    let unique_paths: Vec<String> = collect_unique_paths(&config).into_iter().collect();
+    // Collect all unique paths, outputs of other operations do not exist yet
+    let outputs: HashSet<&String> = config.operations.iter().map(|op| &op.output).collect();
+    let unique_paths: Vec<String> = collect_unique_paths(&config, &cache)
+        .into_iter()
+        .filter(|path| !outputs.contains(path))
+        .collect();
     println!("Found {} unique input files", unique_paths.len());
     
-    // Prefetch datasets
-    for path in &unique_paths {
-        if let Err(e) = cache.get_dataset(path) {
+    let global_mosaic_rule = match config.global.mosaic_rule.as_deref() {
+        Some(name) => OverlapRule::from_name(name)?,
+        None => OverlapRule::default(),
+    };
+
+    // Prefetch datasets, building virtual mosaics for tile lists and glob patterns
+    for path in unique_paths.iter().take(cache.capacity().unwrap_or(usize::MAX)) {
+        let resolved = mosaic::resolve_input(path, global_mosaic_rule, &cache)
+            .and_then(|resolved| cache.get_dataset(&resolved).map(|_| ()));
+        if let Err(e) = resolved {
             eprintln!("Warning: Could not preload {}: {}", path, e);
         }
     }
     println!("Cache initialized with {} datasets", cache.len());
     
+    // Skip the operations whose outputs are up to date
+    let manifest_path = config
+        .global
+        .manifest
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| manifest::default_path(config_path));
+    let mut manifest = Manifest::load(&manifest_path)?;
+    let fingerprints = operation_fingerprints(&config, &graph, &cache);
+    let to_run = if options.force {
+        vec![true; config.operations.len()]
+    } else {
+        operations_to_run(&config, &graph, &manifest, &fingerprints)
+    };
+    let up_to_date = to_run.iter().filter(|run| !**run).count();
+    if up_to_date > 0 {
+        println!("{} operation(s) up to date, use --force to rerun them", up_to_date);
+    }
+
+    // Until they succeed, outputs about to be rewritten are not up to date, even after a crash
+    for (i, op) in config.operations.iter().enumerate().filter(|(i, _)| to_run[*i]) {
+        manifest.record(&op.output, &fingerprints[i].0, false, operation_outputs(op, &config.global));
+    }
+    manifest.save(&manifest_path)?;
+
     println!("Starting parallel batch processing with {} operations...", config.operations.len());
     
-    // Track errors across parallel operations
-    let errors = Arc::new(Mutex::new(Vec::new()));
+    let report = options.report.as_deref().map(ReportWriter::create).transpose()?;
     
-    // Process operations in parallel using rayon
-    config.operations.par_iter().enumerate().for_each(|(i, op)| {
-        println!("[{}/{}] Processing {} -> {}", i + 1, config.operations.len(), op.op_type, op.output);
-        
-        // Create a processor for each parallel operation with the shared cache
-        let processor = ParallelProcessor::with_cache(None, Arc::clone(&cache));
-        
-        // Get operation parameters
-        let float = op.float.unwrap_or(config.global.float);
-        let scale_factor = op.scale_factor.unwrap_or(config.global.scale_factor);
-        let input_scale_factor = op.input_scale_factor.unwrap_or(config.global.input_scale_factor);  // NEW LINE
-        let compress = op.compress.as_deref().unwrap_or(&config.global.compress);
-        let compress_level = op.compress_level.unwrap_or(config.global.compress_level);
-        let tiled = op.tiled.unwrap_or(config.global.tiled);
-        
-        // Process based on operation type
-        let result = match op.op_type.to_lowercase().as_str() {
-            "ndi" => {
-                match serde_json::from_value::<NdiParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = NDI::new(0, 1, None);
-                        processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing NDI params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            "evi" => {
-                match serde_json::from_value::<EviParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = EVI::new(0, 1, 2, None);
-                        processor.process(alg, &[p.a, p.b, p.c], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing EVI params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            "savi" => {
-                match serde_json::from_value::<SaviParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = SAVI::new(0, 1, p.l.unwrap_or(0.5), None);
-                        processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing SAVI params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            "ndwi" => {
-                match serde_json::from_value::<NdwiParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = NDWI::new(0, 1, None);
-                        processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing NDWI params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            "ndsi" => {
-                match serde_json::from_value::<NdsiParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = NDSI::new(0, 1, None);
-                        processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing NDSI params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            "bsi" => {
-                match serde_json::from_value::<BsiParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = BSI::new(0, 1, 2, 3, None);
-                        processor.process(alg, &[p.s, p.r, p.n, p.b], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing BSI params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            "msavi2" => {
-                match serde_json::from_value::<MsaviParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = MSAVI2::new(0, 1, None);
-                        processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing MSAVI2 params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            "osavi" => {
-                match serde_json::from_value::<OsaviParams>(op.params.clone()) {
-                    Ok(p) => {
-                        let alg = OSAVI::new(0, 1, None);
-                        processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
-                                       input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
-                    },
-                    Err(e) => {
-                        let mut error_list = errors.lock().unwrap();
-                        error_list.push(format!("Error parsing OSAVI params for operation {}: {}", i + 1, e));
-                        return;
-                    }
-                }
-            },
-            _ => {
-                let mut error_list = errors.lock().unwrap();
-                error_list.push(format!("Unknown operation type for operation {}: {}", i + 1, op.op_type));
-                return;
-            }
+    // Run the operations in dependency order, independent ones in parallel, collecting errors
+    let run = BatchRun::new(&config, &graph, &cache, &scheduler, global_mosaic_rule, &to_run, report.as_ref())
+        .limit_running(memory_plan.as_ref().map_or(usize::MAX, |plan| plan.concurrent_operations));
+    pool.in_place_scope(|scope| run.dispatch_retries(scope));
+
+    // Record what ran, failures included, so the next run only redoes what is needed
+    for (i, op) in config.operations.iter().enumerate().filter(|(i, _)| to_run[*i]) {
+        let succeeded = !run.failed[i].load(Ordering::SeqCst);
+        manifest.record(&op.output, &fingerprints[i].0, succeeded, operation_outputs(op, &config.global));
+    }
+    if let Err(e) = manifest.save(&manifest_path) {
+        eprintln!("Warning: {:#}", e);
+    }
+
+    if let (Some(report), Some(path)) = (&report, &options.report) {
+        let statuses = run.statuses.lock().unwrap();
+        let count = |status: OperationStatus| statuses.iter().filter(|s| **s == Some(status)).count();
+        let summary = BatchSummary {
+            config: config_path.display().to_string(),
+            started: started_at,
+            finished: timestamp_now(),
+            wall_seconds: started.elapsed().as_secs_f64(),
+            operations: config.operations.len(),
+            succeeded: count(OperationStatus::Succeeded),
+            failed: count(OperationStatus::Failed),
+            skipped: count(OperationStatus::Skipped),
+            up_to_date: count(OperationStatus::UpToDate),
+            cancelled: count(OperationStatus::Cancelled),
+            cached_datasets: cache.len(),
+            cache_hits: cache.hits(),
+            cache_misses: cache.misses(),
         };
-        
-        if let Err(e) = result {
-            let mut error_list = errors.lock().unwrap();
-            error_list.push(format!("Error processing operation {}: {}", i + 1, e));
+        match report.finish(&summary) {
+            Ok(()) => println!("Report: {}", path.display()),
+            Err(e) => eprintln!("Warning: {:#}", e),
         }
-    });
+    }
     
     // Check if any errors occurred
-    let error_list = errors.lock().unwrap();
+    let error_list = run.errors.lock().unwrap();
     if !error_list.is_empty() {
         for error in error_list.iter() {
             eprintln!("{}", error);
@@ -317,65 +1380,1258 @@ pub fn process_batch(config_path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn collect_unique_paths(config: &BatchConfig) -> HashSet<String> {
-    let mut paths = HashSet::new();
+/// Run one operation, recording its timings and result statistics in `stats`
+fn run_operation(
+    i: usize,
+    op: &Operation,
+    config: &BatchConfig,
+    cache: &Arc<RasterCache>,
+    scheduler: &Arc<Scheduler>,
+    global_mosaic_rule: OverlapRule,
+    stats: &Arc<ProcessStats>,
+) -> Result<()> {
+    println!("[{}/{}] Processing {} -> {}", i + 1, config.operations.len(), op.op_type, op.output);
+    let settings = Settings::merge(op, &config.global);
 
-    for op in &config.operations {
-        match op.op_type.to_lowercase().as_str() {
-            "ndi" => {
-                if let Ok(p) = serde_json::from_value::<NdiParams>(op.params.clone()) {
-                    paths.insert(p.a);
-                    paths.insert(p.b);
+    // Resolve the output format, an explicit format must be valid
+    let output_format = match settings.format.as_deref() {
+        Some(name) => match OutputFormat::from_name(name) {
+            Ok(format) => Some(format),
+            Err(e) => {
+                return Err(e).context("Invalid output format");
+            }
+        },
+        None => None,
+    };
+
+    let mosaic_rule = match op.mosaic_rule.as_deref() {
+        Some(name) => match OverlapRule::from_name(name) {
+            Ok(rule) => rule,
+            Err(e) => {
+                return Err(e).context("Invalid mosaic rule");
+            }
+        },
+        None => global_mosaic_rule,
+    };
+
+    // Optional class map of the operation result
+    let class_output = match &op.classify {
+        Some(rules) => match Classification::from_value(rules) {
+            Ok(classification) => {
+                let path = op
+                    .class_output
+                    .clone()
+                    .unwrap_or_else(|| classify::default_class_path(&op.output));
+                Some(ClassOutput::new(classification, &path, class_band(op)?))
+            }
+            Err(e) => {
+                return Err(e).context("Invalid classification");
+            }
+        },
+        None => None,
+    };
+
+    // Product or STAC item that bare band names refer to
+    let product = settings.product.as_deref();
+    let resolution = settings.resolution;
+
+    // Optional RGBA rendering of the operation result
+    let render_output = match &op.render {
+        Some(params) if op.op_type != "render" => {
+            let path = params
+                .output
+                .clone()
+                .unwrap_or_else(|| render::default_render_path(&op.output));
+            match build_render_output(params, &path) {
+                Ok(render_output) => Some(render_output),
+                Err(e) => {
+                    return Err(e).context("Invalid rendering");
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // Optional cloud/shadow/snow mask of the operation outputs
+    let mask = match op.mask.as_ref().or(config.global.mask.as_ref()) {
+        Some(params) => match Mask::new(&params.input, params.mask_type.as_deref(), &params.values) {
+            Ok(mask) => Some(mask),
+            Err(e) => {
+                return Err(e).context("Invalid mask");
+            }
+        },
+        None => None,
+    };
+
+    // Create a processor for each parallel operation with the shared cache
+    let processor = ParallelProcessor::with_cache(None, Arc::clone(cache))
+        .with_output_format(output_format)
+        .with_mosaic_rule(mosaic_rule)
+        .with_classification(class_output)
+        .with_product(product.map(str::to_string), resolution)
+        .with_auto_scaling(settings.auto_scale)
+        .with_render(render_output)
+        .with_mask(mask)
+        .with_stac_item(settings.write_stac)
+        .with_stats(Some(Arc::clone(stats)))
+        .with_max_block_bytes(scheduler.block_limit())
+        .with_scheduler(Some(Arc::clone(scheduler)));
+    
+    // Get operation parameters
+    let float = settings.float;
+    let scale_factor = settings.scale_factor;
+    let input_scale_factor = settings.input_scale_factor;
+    let compress = settings.compress.as_str();
+    let compress_level = settings.compress_level;
+    let tiled = settings.tiled;
+    
+    // Process based on operation type
+    let result = match op.op_type.to_lowercase().as_str() {
+        "ndi" => {
+            match serde_json::from_value::<NdiParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = NDI::new(0, 1, None);
+                    processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing NDI params");
+                }
+            }
+        },
+        "evi" => {
+            match serde_json::from_value::<EviParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = EVI::new(0, 1, 2, None);
+                    processor.process(alg, &[p.a, p.b, p.c], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing EVI params");
+                }
+            }
+        },
+        "savi" => {
+            match serde_json::from_value::<SaviParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = SAVI::new(0, 1, p.l.unwrap_or(0.5), None);
+                    processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing SAVI params");
+                }
+            }
+        },
+        "ndwi" => {
+            match serde_json::from_value::<NdwiParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = NDWI::new(0, 1, None);
+                    processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing NDWI params");
+                }
+            }
+        },
+        "ndsi" => {
+            match serde_json::from_value::<NdsiParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = NDSI::new(0, 1, None);
+                    processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing NDSI params");
                 }
             }
-            "evi" => {
-                if let Ok(p) = serde_json::from_value::<EviParams>(op.params.clone()) {
-                    paths.insert(p.a);
-                    paths.insert(p.b);
-                    paths.insert(p.c);
+        },
+        "bsi" => {
+            match serde_json::from_value::<BsiParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = BSI::new(0, 1, 2, 3, None);
+                    processor.process(alg, &[p.s, p.r, p.n, p.b], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing BSI params");
                 }
             }
-            "savi" => {
-                if let Ok(p) = serde_json::from_value::<SaviParams>(op.params.clone()) {
-                    paths.insert(p.a);
-                    paths.insert(p.b);
+        },
+        "msavi2" => {
+            match serde_json::from_value::<MsaviParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = MSAVI2::new(0, 1, None);
+                    processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing MSAVI2 params");
                 }
             }
-            // Add other index types
-            "ndwi" => {
-                if let Ok(p) = serde_json::from_value::<NdwiParams>(op.params.clone()) {
-                    paths.insert(p.a);
-                    paths.insert(p.b);
+        },
+        "osavi" => {
+            match serde_json::from_value::<OsaviParams>(op.params.clone()) {
+                Ok(p) => {
+                    let alg = OSAVI::new(0, 1, None);
+                    processor.process(alg, &[p.a, p.b], &op.output, !float, scale_factor, 
+                                   input_scale_factor, compress, compress_level, tiled)  // ADDED input_scale_factor
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing OSAVI params");
                 }
             }
-            "ndsi" => {
-                if let Ok(p) = serde_json::from_value::<NdsiParams>(op.params.clone()) {
-                    paths.insert(p.a);
-                    paths.insert(p.b);
+        },
+        "composite" => {
+            let composite = serde_json::from_value::<CompositeParams>(op.params.clone())
+                .map_err(anyhow::Error::from)
+                .and_then(|p| {
+                    let paths = composite_paths(&p)?;
+                    let scene_bands = paths.len() - p.cloud.len();
+                    let calculator = stack_calculator(&p.index, p.l, &paths[..scene_bands], mosaic_rule, cache)?;
+                    let rule = CompositeRule::from_name(&p.rule)?;
+                    if rule == CompositeRule::LeastCloudy && p.cloud.len() != p.scenes.len() {
+                        return Err(anyhow::anyhow!(
+                            "least_cloudy needs one cloud band per scene ({} given, {} scenes)",
+                            p.cloud.len(),
+                            p.scenes.len()
+                        ));
+                    }
+                    let alg = Composite::new(calculator, p.scenes.len(), rule, p.source_band, None);
+                    Ok((alg, paths))
+                });
+            match composite {
+                Ok((alg, paths)) => {
+                    processor.process(alg, &paths, &op.output, !float, scale_factor,
+                                   input_scale_factor, compress, compress_level, tiled)
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing composite params");
                 }
             }
-            "bsi" => {
-                if let Ok(p) = serde_json::from_value::<BsiParams>(op.params.clone()) {
-                    paths.insert(p.s);
-                    paths.insert(p.r);
-                    paths.insert(p.n);
-                    paths.insert(p.b);
+        },
+        "change" => {
+            let change = serde_json::from_value::<ChangeParams>(op.params.clone())
+                .map_err(anyhow::Error::from)
+                .and_then(|p| {
+                    let paths = change_paths(&p)?;
+                    let alg = ChangeDetection::new(stack_calculator(&p.index, p.l, &paths, mosaic_rule, cache)?);
+                    let class_output = if p.thresholds.is_empty() {
+                        None
+                    } else {
+                        let path = p
+                            .class_output
+                            .clone()
+                            .or_else(|| op.class_output.clone())
+                            .unwrap_or_else(|| classify::default_class_path(&op.output));
+                        Some(ClassOutput::new(Classification::from_thresholds(&p.thresholds)?, &path, 0))
+                    };
+                    Ok((alg, paths, class_output))
+                });
+            match change {
+                Ok((alg, paths, Some(class_output))) => {
+                    processor.with_classification(Some(class_output)).process(alg, &paths, &op.output, !float, scale_factor,
+                                   input_scale_factor, compress, compress_level, tiled)
+                },
+                Ok((alg, paths, None)) => {
+                    processor.process(alg, &paths, &op.output, !float, scale_factor,
+                                   input_scale_factor, compress, compress_level, tiled)
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing change params");
                 }
             }
-            "msavi2" => {
-                if let Ok(p) = serde_json::from_value::<MsaviParams>(op.params.clone()) {
-                    paths.insert(p.a);
-                    paths.insert(p.b);
+        },
+        "classify" => {
+            let reclassify = serde_json::from_value::<ClassifyParams>(op.params.clone())
+                .map_err(anyhow::Error::from)
+                .and_then(|p| {
+                    let input = product::qualify_input(&p.input, product, resolution, cache)?;
+                    let input = mosaic::resolve_input(&input, mosaic_rule, cache)?;
+                    let classification = Classification::from_value(&p.rules)?;
+                    Ok((RasterValues::from_path(&input)?, input, classification))
+                });
+            match reclassify {
+                Ok((alg, input, classification)) => {
+                    // Only the class map is written, to the operation output
+                    processor
+                        .with_classification(Some(ClassOutput::new(classification, &op.output, 0)))
+                        .with_index_output(false)
+                        .process(alg, &[input], &op.output, !float, scale_factor,
+                                 1.0, compress, compress_level, tiled)
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing classify params");
                 }
             }
-            "osavi" => {
-                if let Ok(p) = serde_json::from_value::<OsaviParams>(op.params.clone()) {
-                    paths.insert(p.a);
-                    paths.insert(p.b);
+        },
+        "render" => {
+            let rendering = serde_json::from_value::<RenderOpParams>(op.params.clone())
+                .map_err(anyhow::Error::from)
+                .and_then(|p| {
+                    let input = product::qualify_input(&p.input, product, resolution, cache)?;
+                    let input = mosaic::resolve_input(&input, mosaic_rule, cache)?;
+                    let render_output = build_render_output(&p.render, &op.output)?;
+                    Ok((RasterValues::from_path(&input)?, input, render_output))
+                });
+            match rendering {
+                Ok((alg, input, render_output)) => {
+                    // Only the rendering is written, to the operation output
+                    processor
+                        .with_render(Some(render_output))
+                        .with_index_output(false)
+                        .process(alg, &[input], &op.output, !float, scale_factor,
+                                 1.0, compress, compress_level, tiled)
+                },
+                Err(e) => {
+                    return Err(e).context("Error parsing render params");
                 }
             }
-            _ => {}
+        },
+        "timeseries" => {
+            let (p, alg) = stack_operation(op, |p: &TimeseriesParams| {
+                timeseries::from_inputs(&p.inputs, &p.stats, &p.dates)
+            })?;
+            processor.process(alg, &p.inputs, &op.output, !float, scale_factor,
+                           input_scale_factor, compress, compress_level, tiled)
+        },
+        "phenology" => {
+            let (p, alg) = stack_operation(op, |p: &PhenologyParams| {
+                let smoothing = Smoothing::from_name(&p.method, p.window, p.order)?;
+                phenology::from_inputs(&p.inputs, smoothing, p.threshold, &p.dates)
+            })?;
+            processor.process(alg, &p.inputs, &op.output, !float, scale_factor,
+                           input_scale_factor, compress, compress_level, tiled)
+        },
+        "gapfill" => {
+            let (p, alg) = stack_operation(op, |p: &GapfillParams| {
+                let method = FillMethod::from_name(&p.method, p.harmonics, p.lambda)?;
+                gapfill::from_inputs(&p.inputs, method, p.interval, &p.dates)
+            })?;
+            let output_dates = alg.output_dates().to_vec();
+            processor.process(alg, &p.inputs, &op.output, !float, scale_factor,
+                           input_scale_factor, compress, compress_level, tiled)
+                .and_then(|_| {
+                    if !p.per_date {
+                        return Ok(());
+                    }
+                    let format = OutputFormat::resolve(output_format, &op.output)?;
+                    let options = format.creation_options(compress, compress_level, tiled, !float);
+                    let paths = gapfill::per_date_paths(&op.output, &output_dates);
+                    output::split_bands(&op.output, &paths, format, &options)
+                })
+        },
+        _ => {
+            return Err(anyhow::anyhow!("Unknown operation type: {}", op.op_type));
+        }
+    };
+    
+    result
+}
+
+/// Params of a stack operation (timeseries, phenology, gapfill) and the calculator built from them
+fn stack_operation<P: DeserializeOwned, I>(op: &Operation, build: impl FnOnce(&P) -> Result<I>) -> Result<(P, I)> {
+    let context = || format!("Error parsing {} params", op.op_type);
+    let params: P = serde_json::from_value(op.params.clone()).with_context(context)?;
+    let alg = build(&params).with_context(context)?;
+    Ok((params, alg))
+}
+
+/// Operation types a batch can run
+const OPERATION_TYPES: [&str; 15] = [
+    "ndi", "evi", "savi", "ndwi", "ndsi", "bsi", "msavi2", "osavi", "composite", "change",
+    "classify", "render", "timeseries", "phenology", "gapfill",
+];
+
+/// Schema of the params of an operation type, or of an index inside a composite or change
+fn type_params_schema(gen: &mut SchemaGenerator, op_type: &str) -> Option<Schema> {
+    Some(match op_type.to_lowercase().as_str() {
+        "ndi" => gen.subschema_for::<NdiParams>(),
+        "evi" => gen.subschema_for::<EviParams>(),
+        "savi" => gen.subschema_for::<SaviParams>(),
+        "ndwi" => gen.subschema_for::<NdwiParams>(),
+        "ndsi" => gen.subschema_for::<NdsiParams>(),
+        "bsi" => gen.subschema_for::<BsiParams>(),
+        "msavi2" => gen.subschema_for::<MsaviParams>(),
+        "osavi" => gen.subschema_for::<OsaviParams>(),
+        "raster" => gen.subschema_for::<RasterParams>(),
+        "composite" => gen.subschema_for::<CompositeParams>(),
+        "change" => gen.subschema_for::<ChangeParams>(),
+        "classify" => gen.subschema_for::<ClassifyParams>(),
+        "render" => gen.subschema_for::<RenderOpParams>(),
+        "timeseries" => gen.subschema_for::<TimeseriesParams>(),
+        "phenology" => gen.subschema_for::<PhenologyParams>(),
+        "gapfill" => gen.subschema_for::<GapfillParams>(),
+        _ => return None,
+    })
+}
+
+/// Params schema of an operation type with its properties inlined, see [`type_params_schema`]
+fn params_root_schema(op_type: &str) -> Option<SchemaObject> {
+    let mut gen = SchemaSettings::draft07()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    type_params_schema(&mut gen, op_type).map(Schema::into_object)
+}
+
+/// Schema of `Operation::params`: an object, whose properties [`config_schema`] ties to the type
+fn params_schema(_gen: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// JSON Schema of batch configurations. The params of each operation type are checked
+/// against that type's params with an `if`/`then` per type.
+pub fn config_schema() -> Value {
+    let mut gen = SchemaGenerator::default();
+    let root = gen.root_schema_for::<BatchConfig>();
+    let by_type: Vec<Value> = OPERATION_TYPES
+        .iter()
+        .filter_map(|op_type| {
+            let params = type_params_schema(&mut gen, op_type)?;
+            Some(json!({
+                "if": {"properties": {"type": {"const": op_type}}, "required": ["type"]},
+                "then": {"properties": {"params": params}}
+            }))
+        })
+        .collect();
+
+    let mut schema = serde_json::to_value(root).unwrap_or_default();
+    if let Some(definitions) = schema.get_mut("definitions").and_then(Value::as_object_mut) {
+        for (name, definition) in gen.take_definitions() {
+            definitions.entry(name).or_insert_with(|| serde_json::to_value(definition).unwrap_or_default());
+        }
+        if let Some(operation) = definitions.get_mut("Operation").and_then(Value::as_object_mut) {
+            operation.insert("allOf".into(), Value::Array(by_type));
+        }
+    }
+    schema
+}
+
+/// Keys of the object `value` that are not properties of `schema`
+fn unknown_keys(schema: &SchemaObject, value: &Value) -> Vec<String> {
+    let Some(object) = value.as_object() else {
+        return Vec::new();
+    };
+    let properties = schema.object.as_ref().map(|o| &o.properties);
+    object
+        .keys()
+        .filter(|key| !properties.is_some_and(|p| p.contains_key(*key)))
+        .cloned()
+        .collect()
+}
+
+/// Input paths in an operation's params, before product qualification
+fn operation_inputs(op: &Operation) -> Result<Vec<String>> {
+    let params = op.params.clone();
+    let op_type = op.op_type.to_lowercase();
+    Ok(match op_type.as_str() {
+        "ndi" | "evi" | "savi" | "ndwi" | "ndsi" | "bsi" | "osavi" => index_band_paths(&op_type, &params)?,
+        "msavi2" => {
+            let p: MsaviParams = serde_json::from_value(params)?;
+            vec![p.a, p.b]
+        }
+        "composite" => composite_paths(&serde_json::from_value(params)?)?,
+        "change" => change_paths(&serde_json::from_value(params)?)?,
+        "classify" => vec![serde_json::from_value::<ClassifyParams>(params)?.input],
+        "render" => vec![serde_json::from_value::<RenderOpParams>(params)?.input],
+        "timeseries" => serde_json::from_value::<TimeseriesParams>(params)?.inputs,
+        "phenology" => serde_json::from_value::<PhenologyParams>(params)?.inputs,
+        "gapfill" => serde_json::from_value::<GapfillParams>(params)?.inputs,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unknown operation type '{}', expected one of: {}",
+                op.op_type,
+                OPERATION_TYPES.join(", ")
+            ))
+        }
+    })
+}
+
+/// Every file an operation writes: output, class map, rendering, quicklook and STAC item
+fn operation_outputs(op: &Operation, global: &GlobalParams) -> Vec<String> {
+    let mut outputs = vec![op.output.clone()];
+    let thresholds = op.op_type == "change"
+        && op.params.get("thresholds").and_then(Value::as_array).is_some_and(|t| !t.is_empty());
+    if op.classify.is_some() || thresholds {
+        let class_output = op
+            .params
+            .get("class_output")
+            .filter(|_| thresholds)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .or_else(|| op.class_output.clone())
+            .unwrap_or_else(|| classify::default_class_path(&op.output));
+        outputs.push(class_output);
+    }
+    match &op.render {
+        Some(params) if op.op_type != "render" => {
+            outputs.push(
+                params
+                    .output
+                    .clone()
+                    .unwrap_or_else(|| render::default_render_path(&op.output)),
+            );
+            outputs.extend(params.quicklook.clone());
+        }
+        _ => {}
+    }
+    if op.op_type == "render" {
+        outputs.extend(op.params.get("quicklook").and_then(Value::as_str).map(str::to_string));
+    }
+    if Settings::merge(op, global).write_stac {
+        outputs.push(stac::default_item_path(&op.output));
+    }
+    outputs
+}
+
+/// Problems found in a batch configuration before running it
+#[derive(Debug, Default)]
+pub struct Validation {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Validation {
+    fn print(&self) {
+        for warning in &self.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        for error in &self.errors {
+            eprintln!("Error: {}", error);
+        }
+    }
+}
+
+/// Check operation types, params, options and output collisions of an expanded configuration
+/// (references resolved by [`BatchGraph::build`]), and with `check_inputs` that every input
+/// opens and the inputs of each operation share a pixel grid
+pub fn validate_config(config: &BatchConfig, check_inputs: bool) -> Validation {
+    let mut validation = Validation::default();
+    let global = &config.global;
+    // Products, items and mosaics opened for the checks, dropped with them
+    let cache = RasterCache::new();
+
+    if let Some(Err(e)) = global.format.as_deref().map(OutputFormat::from_name) {
+        validation.errors.push(format!("Global output format: {}", e));
+    }
+    let global_mosaic_rule = match global.mosaic_rule.as_deref().map(OverlapRule::from_name) {
+        Some(Err(e)) => {
+            validation.errors.push(format!("Global mosaic rule: {}", e));
+            OverlapRule::default()
+        }
+        Some(Ok(rule)) => rule,
+        None => OverlapRule::default(),
+    };
+    if let Some(Err(e)) = global.mask.as_ref().map(|m| Mask::new(&m.input, m.mask_type.as_deref(), &m.values)) {
+        validation.errors.push(format!("Global mask: {}", e));
+    }
+    if let Some(Err(e)) = global.on_error.as_deref().map(OnError::from_name) {
+        validation.errors.push(format!("Global on_error: {}", e));
+    }
+    if let Some(Err(e)) = global.retry_delay.map(|delay| RetryPolicy::new(global.retries, delay)) {
+        validation.errors.push(format!("Global retry_delay: {}", e));
+    }
+    if let Some(Err(e)) = global.max_memory.as_deref().map(memory::parse_size) {
+        validation.errors.push(format!("Global max_memory: {}", e));
+    }
+
+    let outputs: HashSet<&String> = config.operations.iter().map(|op| &op.output).collect();
+    let mut written: HashMap<String, usize> = HashMap::new();
+
+    for (i, op) in config.operations.iter().enumerate() {
+        let name = format!("Operation {} ({})", i + 1, op.op_type);
+        let mut error = |message: String| validation.errors.push(format!("{}: {}", name, message));
+
+        let inputs = match operation_inputs(op) {
+            Ok(inputs) => inputs,
+            Err(e) => {
+                error(format!("{}", e));
+                Vec::new()
+            }
+        };
+        if let Some(Err(e)) = op.format.as_deref().map(OutputFormat::from_name) {
+            error(format!("{}", e));
+        }
+        let mosaic_rule = match op.mosaic_rule.as_deref().map(OverlapRule::from_name) {
+            Some(Err(e)) => {
+                error(format!("{}", e));
+                global_mosaic_rule
+            }
+            Some(Ok(rule)) => rule,
+            None => global_mosaic_rule,
+        };
+        if let Some(Err(e)) = op.classify.as_ref().map(Classification::from_value) {
+            error(format!("Invalid classification: {}", e));
         }
+        if let Err(e) = class_band(op) {
+            error(format!("{}", e));
+        }
+        if let Some(Err(e)) = op.render.as_ref().map(|r| build_render_output(r, &op.output)) {
+            error(format!("Invalid rendering: {}", e));
+        }
+        if let Some(Err(e)) = op.mask.as_ref().map(|m| Mask::new(&m.input, m.mask_type.as_deref(), &m.values)) {
+            error(format!("Invalid mask: {}", e));
+        }
+        if op.on_error.is_some() || op.retry_delay.is_some() {
+            if let Err(e) = failure_policy(op, global) {
+                error(format!("{}", e));
+            }
+        }
+
+        // Output collisions, with other operations or the operation's own inputs
+        for path in operation_outputs(op, global) {
+            if inputs.contains(&path) {
+                error(format!("'{}' is both an input and an output", path));
+            }
+            if let Some(other) = written.insert(path.clone(), i) {
+                error(format!("'{}' is also written by operation {}", path, other + 1));
+            }
+        }
+
+        // Misspelt option and parameter names are ignored when running, warn about them
+        let mut unknown: Vec<String> = op.unknown.keys().cloned().collect();
+        if let Some(schema) = params_root_schema(&op.op_type) {
+            unknown.extend(unknown_keys(&schema, &op.params).iter().map(|k| format!("params.{}", k)));
+        }
+        let index = op.params.get("index").and_then(Value::as_str).and_then(params_root_schema);
+        if let Some(schema) = index {
+            let nested = ["pre", "post"]
+                .iter()
+                .filter_map(|key| op.params.get(*key).map(|v| (key.to_string(), v)))
+                .chain(
+                    op.params
+                        .get("scenes")
+                        .and_then(Value::as_array)
+                        .into_iter()
+                        .flatten()
+                        .enumerate()
+                        .map(|(n, v)| (format!("scenes[{}]", n), v)),
+                );
+            for (key, value) in nested {
+                unknown.extend(unknown_keys(&schema, value).iter().map(|k| format!("params.{}.{}", key, k)));
+            }
+        }
+        for key in unknown {
+            validation.warnings.push(format!("{}: unknown key '{}' is ignored", name, key));
+        }
+
+        if check_inputs {
+            let product = operation_product(op, global);
+            let resolution = op.resolution.or(global.resolution);
+            validation
+                .errors
+                .extend(check_operation_inputs(&inputs, &outputs, product, resolution, mosaic_rule, &cache).into_iter().map(|e| format!("{}: {}", name, e)));
+            if let Some(mask) = op.mask.as_ref().or(global.mask.as_ref()) {
+                match product::qualify_input(&mask.input, product, resolution, &cache) {
+                    Ok(input) if outputs.contains(&input) => {}
+                    Ok(input) => {
+                        if let Err(e) = open_input(&input, mosaic_rule, &cache) {
+                            validation.errors.push(format!("{}: mask {:#}", name, e));
+                        }
+                    }
+                    Err(e) => validation.errors.push(format!("{}: mask {:#}", name, e)),
+                }
+            }
+        }
+    }
+
+    validation
+}
+
+/// Pixel grid of an input: size, geotransform and projection
+struct Grid {
+    size: (usize, usize),
+    geo_transform: [f64; 6],
+    projection: String,
+}
+
+impl Grid {
+    fn matches(&self, other: &Grid) -> bool {
+        let tolerance = self.geo_transform[1].abs().max(self.geo_transform[5].abs()) * 1e-3;
+        self.size == other.size
+            && self.projection == other.projection
+            && self
+                .geo_transform
+                .iter()
+                .zip(&other.geo_transform)
+                .all(|(a, b)| (a - b).abs() <= tolerance)
+    }
+}
+
+/// Open an input the way a batch run does, returning its grid
+fn open_input(input: &str, mosaic_rule: OverlapRule, cache: &RasterCache) -> Result<Grid> {
+    let path = mosaic::resolve_input(input, mosaic_rule, cache)?;
+    let dataset = Dataset::open(&path).with_context(|| format!("'{}' cannot be opened", input))?;
+    Ok(Grid {
+        size: dataset.raster_size(),
+        geo_transform: dataset.geo_transform()?,
+        projection: dataset.projection(),
+    })
+}
+
+/// Errors of inputs that do not open or are not on the grid of the first one.
+/// Outputs of other operations do not exist yet and are skipped.
+fn check_operation_inputs(
+    inputs: &[String],
+    outputs: &HashSet<&String>,
+    product: Option<&str>,
+    resolution: Option<u32>,
+    mosaic_rule: OverlapRule,
+    cache: &RasterCache,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut reference: Option<(String, Grid)> = None;
+    for input in inputs {
+        let input = match product::qualify_input(input, product, resolution, cache) {
+            Ok(input) => input,
+            Err(e) => {
+                errors.push(format!("{:#}", e));
+                continue;
+            }
+        };
+        if outputs.contains(&input) {
+            continue;
+        }
+        match open_input(&input, mosaic_rule, cache) {
+            Ok(grid) => match &reference {
+                Some((first, first_grid)) if !first_grid.matches(&grid) => errors.push(format!(
+                    "'{}' ({}x{}) is not on the pixel grid of '{}' ({}x{})",
+                    input, grid.size.0, grid.size.1, first, first_grid.size.0, first_grid.size.1
+                )),
+                Some(_) => {}
+                None => reference = Some((input, grid)),
+            },
+            Err(e) => errors.push(format!("{:#}", e)),
+        }
+    }
+    errors
+}
+
+/// Check a batch configuration without processing anything
+pub fn validate_batch(config_path: &Path) -> Result<()> {
+    let mut config = BatchConfig::load(config_path)?;
+    config.expand_templates()?;
+    BatchGraph::build(&mut config.operations, &RasterCache::new().scratch_dir())?;
+
+    let validation = validate_config(&config, true);
+    validation.print();
+    if !validation.errors.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} error(s) in batch configuration {}",
+            validation.errors.len(),
+            config_path.display()
+        ));
+    }
+    println!(
+        "{}: {} operation(s) OK, {} warning(s)",
+        config_path.display(),
+        config.operations.len(),
+        validation.warnings.len()
+    );
+    Ok(())
+}
+
+/// Threads of the batch pool: the configured count, or 60% of the cores (at least 4)
+pub fn thread_count(global: &GlobalParams) -> usize {
+    global
+        .threads
+        .unwrap_or_else(|| std::cmp::max(4, (num_cpus::get() as f32 * 0.6) as usize))
+}
+
+/// Block reader threads shared by the operations: the configured count, or one per core (at least 4)
+pub fn io_thread_count(global: &GlobalParams) -> usize {
+    global.io_threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map_or(4, std::num::NonZero::get)
+            .max(4)
+    })
+}
+
+/// Input blocks read ahead of processing across operations: the configured MB, or 1 GB
+fn max_inflight_bytes(global: &GlobalParams) -> u64 {
+    global.max_inflight_mb.unwrap_or(1024) * 1024 * 1024
+}
+
+/// Block reads, read-ahead memory and block size limit of one batch run, shared by its operations
+pub fn scheduler(global: &GlobalParams, memory: Option<&MemoryPlan>) -> Scheduler {
+    match memory {
+        Some(plan) => Scheduler::new(io_thread_count(global), plan.shares.inflight)
+            .with_block_limit(Some(plan.block_limit)),
+        None => Scheduler::new(io_thread_count(global), max_inflight_bytes(global)),
+    }
+}
+
+/// Thread pool of one batch run; operations and the processing inside them run on it
+pub fn thread_pool(global: &GlobalParams) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(thread_count(global))
+        .thread_name(|i| format!("batch-{}", i))
+        .build()
+        .context("Failed to create the batch thread pool")
+}
+
+/// Options of an operation after merging with the global ones
+#[derive(Serialize, Debug, Clone)]
+pub struct Settings {
+    pub float: bool,
+    pub scale_factor: i32,
+    pub input_scale_factor: f32,
+    pub compress: String,
+    pub compress_level: u8,
+    pub tiled: bool,
+    pub format: Option<String>,
+    pub mosaic_rule: Option<String>,
+    pub product: Option<String>,
+    pub resolution: Option<u32>,
+    pub auto_scale: bool,
+    /// Mask band input
+    pub mask: Option<String>,
+    pub write_stac: bool,
+}
+
+impl Settings {
+    pub fn merge(op: &Operation, global: &GlobalParams) -> Self {
+        Self {
+            float: op.float.unwrap_or(global.float),
+            scale_factor: op.scale_factor.unwrap_or(global.scale_factor),
+            input_scale_factor: op.input_scale_factor.unwrap_or(global.input_scale_factor),
+            compress: op.compress.clone().unwrap_or_else(|| global.compress.clone()),
+            compress_level: op.compress_level.unwrap_or(global.compress_level),
+            tiled: op.tiled.unwrap_or(global.tiled),
+            format: op.format.clone().or_else(|| global.format.clone()),
+            mosaic_rule: op.mosaic_rule.clone().or_else(|| global.mosaic_rule.clone()),
+            product: operation_product(op, global).map(str::to_string),
+            resolution: op.resolution.or(global.resolution),
+            auto_scale: op.auto_scale.or(global.auto_scale).unwrap_or(true),
+            mask: op.mask.as_ref().or(global.mask.as_ref()).map(|m| m.input.clone()),
+            write_stac: op.write_stac.unwrap_or(global.write_stac),
+        }
+    }
+}
+
+/// Failure policy and retries of an operation after merging with the global ones
+fn failure_policy(op: &Operation, global: &GlobalParams) -> Result<(OnError, RetryPolicy)> {
+    let on_error = match op.on_error.as_deref().or(global.on_error.as_deref()) {
+        Some(name) => OnError::from_name(name)?,
+        None => OnError::default(),
+    };
+    let retries = op.retries.unwrap_or(global.retries);
+    let delay = op.retry_delay.or(global.retry_delay).unwrap_or(2.0);
+    Ok((on_error, RetryPolicy::new(retries, delay)?))
+}
+
+/// Remove what a failed attempt wrote: in-memory outputs, and output files created or changed
+/// since `before` (their size and time when the operation started)
+fn remove_partial_outputs(outputs: &[String], before: &[Option<(u64, SystemTime)>], cache: &RasterCache) {
+    for (path, stamp) in outputs.iter().zip(before) {
+        cache.remove(path);
+        if path.starts_with("/vsimem/") {
+            let _ = gdal::vsi::unlink_mem_file(path);
+            continue;
+        }
+        let current = manifest::file_stamp(path);
+        if current.is_some() && current != *stamp {
+            match fs::remove_file(path) {
+                Ok(()) => println!("Removed partial output {}", path),
+                Err(e) => eprintln!("Warning: Failed to remove partial output {}: {}", path, e),
+            }
+        }
+    }
+}
+
+/// One operation of a dry run: resolved settings, inputs and size estimates
+#[derive(Serialize, Debug)]
+pub struct OperationPlan {
+    /// 1-based position in the configuration
+    pub number: usize,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub op_type: String,
+    /// Every file written: output, class map, rendering, quicklook, STAC item
+    pub outputs: Vec<String>,
+    pub intermediate: bool,
+    /// Operations that must finish first
+    pub after: Vec<usize>,
+    pub inputs: Vec<String>,
+    pub settings: Settings,
+    /// Output width and height, `None` when no input opens
+    pub size: Option<(usize, usize)>,
+    pub bands: usize,
+    /// Uncompressed size of the written rasters
+    pub output_bytes: Option<u64>,
+    /// Input blocks and results in memory when the block reader runs ahead of the writer
+    pub memory_bytes: Option<u64>,
+}
+
+/// What a batch would do: operations in run order, cache sharing and thread allocation
+#[derive(Serialize, Debug)]
+pub struct BatchPlan {
+    pub threads: usize,
+    pub available_threads: usize,
+    /// Block reader threads shared by the operations
+    pub reader_threads: usize,
+    /// Input blocks read ahead of processing across operations
+    pub max_inflight_bytes: u64,
+    /// Datasets kept open in the shared cache, `None` for no limit
+    pub max_open_datasets: Option<usize>,
+    /// Most operations that can run at the same time
+    pub concurrent_operations: usize,
+    /// Datasets opened once in the shared cache
+    pub cached_datasets: usize,
+    /// Inputs read by several operations, with their numbers
+    pub shared_inputs: BTreeMap<String, Vec<usize>>,
+    pub output_bytes: u64,
+    /// Memory of the largest operations that can run at the same time
+    pub peak_memory_bytes: u64,
+    /// How the run keeps within `max_memory`, when set
+    pub memory: Option<MemoryPlan>,
+    pub operations: Vec<OperationPlan>,
+}
+
+/// Division of a memory budget for a batch run
+#[derive(Serialize, Debug, Clone)]
+pub struct MemoryPlan {
+    /// GDAL cache, read-ahead and block shares of what intermediates leave
+    pub shares: MemoryShares,
+    /// In-memory intermediate outputs, held until the operations using them finished
+    pub intermediates: u64,
+    /// Most operations running at once, so the blocks of each fit
+    pub concurrent_operations: usize,
+    /// Working set of one block of each operation
+    pub block_limit: u64,
+    /// GDAL cache, read-ahead, blocks of the largest operations running at once and intermediates
+    pub peak_bytes: u64,
+}
+
+impl MemoryPlan {
+    pub fn print(&self) {
+        println!(
+            "Memory budget {}: GDAL cache {}, read-ahead {}, blocks of up to {} for {} operation(s) at once, \
+             intermediates {}, estimated peak {}",
+            format_bytes(self.shares.total + self.intermediates),
+            format_bytes(self.shares.gdal_cache),
+            format_bytes(self.shares.inflight),
+            format_bytes(self.block_limit),
+            self.concurrent_operations,
+            format_bytes(self.intermediates),
+            format_bytes(self.peak_bytes)
+        );
+    }
+}
+
+/// Inputs read by an operation, its mask included
+fn read_band_count(plan: &OperationPlan) -> usize {
+    plan.inputs.len() + plan.settings.mask.iter().count()
+}
+
+/// Output bands of an operation (gapfill: one per input date, more with a short interval)
+fn output_band_count(op: &Operation) -> usize {
+    let params = &op.params;
+    match op.op_type.to_lowercase().as_str() {
+        "composite" => 1 + params.get("source_band").and_then(Value::as_bool).unwrap_or(false) as usize,
+        "timeseries" => serde_json::from_value::<TimeseriesParams>(params.clone()).map_or(1, |p| p.stats.len()),
+        "phenology" => phenology::PHENOMETRICS.len(),
+        "gapfill" => params.get("inputs").and_then(Value::as_array).map_or(1, Vec::len),
+        "render" => 4,
+        _ => 1,
+    }
+}
+
+impl BatchPlan {
+    pub fn new(config: &BatchConfig, graph: &BatchGraph) -> Self {
+        let global = &config.global;
+        let threads = thread_count(global);
+        let reader_threads = io_thread_count(global);
+        // Products, items and mosaics opened to size the operations, dropped with the plan
+        let cache = RasterCache::new();
+
+        let outputs: HashSet<&String> = config.operations.iter().map(|op| &op.output).collect();
+        let mut sizes: HashMap<&String, (usize, usize)> = HashMap::new();
+        let mut readers: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        let mut operations = Vec::with_capacity(config.operations.len());
+
+        for &i in &graph.order {
+            let op = &config.operations[i];
+            let settings = Settings::merge(op, global);
+            let mosaic_rule = settings
+                .mosaic_rule
+                .as_deref()
+                .and_then(|name| OverlapRule::from_name(name).ok())
+                .unwrap_or_default();
+            let inputs: Vec<String> = operation_inputs(op)
+                .unwrap_or_default()
+                .iter()
+                .map(|input| {
+                    product::qualify_input(input, settings.product.as_deref(), settings.resolution, &cache)
+                        .unwrap_or_else(|_| input.clone())
+                })
+                .collect();
+            for input in inputs.iter().filter(|input| !outputs.contains(input)) {
+                let numbers = readers.entry(input.clone()).or_default();
+                if !numbers.contains(&(i + 1)) {
+                    numbers.push(i + 1);
+                }
+            }
+
+            // The grid of the first input, or of the operation producing it
+            let size = inputs.first().and_then(|input| match sizes.get(input) {
+                Some(size) => Some(*size),
+                None => open_input(input, mosaic_rule, &cache).ok().map(|grid| grid.size),
+            });
+            if let Some(size) = size {
+                sizes.insert(&op.output, size);
+            }
+
+            let bands = output_band_count(op);
+            let bytes_per_pixel = match op.op_type.to_lowercase().as_str() {
+                "classify" => 1,
+                "render" => 4,
+                _ => {
+                    bands * if settings.float { 4 } else { 2 }
+                        + op.classify.as_ref().map_or(0, |_| 1)
+                        + op.render.as_ref().map_or(0, |_| 4)
+                }
+            };
+            let read_bands = inputs.len() + settings.mask.iter().count();
+            let pixels = size.map(|(width, height)| (width * height) as u64);
+
+            operations.push(OperationPlan {
+                number: i + 1,
+                id: op.id.clone(),
+                op_type: op.op_type.clone(),
+                outputs: operation_outputs(op, global),
+                intermediate: op.intermediate,
+                after: graph.dependencies[i].iter().map(|d| d + 1).collect(),
+                inputs,
+                settings,
+                size,
+                bands,
+                output_bytes: pixels.map(|p| p * bytes_per_pixel as u64),
+                memory_bytes: pixels.map(|p| p * 4 * (read_bands + bands) as u64),
+            });
+        }
+
+        // Operations at the same depth of the graph can run together
+        let mut depth = vec![0; config.operations.len()];
+        for &i in &graph.order {
+            depth[i] = graph.dependencies[i].iter().map(|&d| depth[d] + 1).max().unwrap_or(0);
+        }
+        let mut per_depth: HashMap<usize, Vec<u64>> = HashMap::new();
+        for plan in &operations {
+            per_depth
+                .entry(depth[plan.number - 1])
+                .or_default()
+                .push(plan.memory_bytes.unwrap_or(0));
+        }
+        let concurrent_operations = per_depth.values().map(Vec::len).max().unwrap_or(0).min(threads);
+        let peak_memory_bytes = per_depth
+            .values_mut()
+            .map(|memory| {
+                memory.sort_unstable_by(|a, b| b.cmp(a));
+                memory.iter().take(concurrent_operations).sum::<u64>()
+            })
+            .max()
+            .unwrap_or(0);
+
+        let cached_datasets = readers.len();
+        readers.retain(|_, numbers| numbers.len() > 1);
+
+        Self {
+            threads,
+            available_threads: num_cpus::get(),
+            reader_threads,
+            max_inflight_bytes: max_inflight_bytes(global),
+            max_open_datasets: global.max_open_datasets,
+            concurrent_operations,
+            cached_datasets,
+            shared_inputs: readers,
+            output_bytes: operations.iter().filter(|p| !p.intermediate).filter_map(|p| p.output_bytes).sum(),
+            peak_memory_bytes,
+            memory: None,
+            operations,
+        }
+    }
+
+    /// Fit the run in `budget` bytes: how many operations run at once and how large their blocks
+    /// are, an error when not even one operation at a time fits with the smallest blocks
+    pub fn with_memory_budget(mut self, budget: u64, global: &GlobalParams) -> Result<Self> {
+        let intermediates: u64 = self
+            .operations
+            .iter()
+            .filter(|plan| plan.intermediate)
+            .filter_map(|plan| plan.output_bytes)
+            .sum();
+        if intermediates >= budget {
+            return Err(anyhow::anyhow!(
+                "Memory budget of {} is too small: in-memory intermediate outputs need {}, \
+                 write some to disk (\"intermediate\": false) or raise --max-memory",
+                format_bytes(budget),
+                format_bytes(intermediates)
+            ));
+        }
+        let mut shares = MemoryShares::split(budget - intermediates);
+        if let Some(mb) = global.max_inflight_mb {
+            shares.inflight = shares.inflight.min(mb * 1024 * 1024);
+        }
+
+        // The operation needing the most memory with blocks split down to the smallest; rasters
+        // within SMALL_RASTER_SIDE are never split, so their smallest block is the whole raster
+        let side = (memory::MIN_BLOCK_SIDE * memory::MIN_BLOCK_SIDE) as u64;
+        let pixels = |plan: &OperationPlan| plan.size.map(|(width, height)| (width * height) as u64);
+        let whole = |plan: &OperationPlan| {
+            plan.size
+                .filter(|&(width, height)| width <= SMALL_RASTER_SIDE && height <= SMALL_RASTER_SIDE)
+                .map(|(width, height)| (width * height) as u64)
+        };
+        let bytes_per_pixel = |plan: &OperationPlan| memory::block_bytes_per_pixel(read_band_count(plan), plan.bands);
+        let smallest_block = |plan: &OperationPlan| {
+            whole(plan).unwrap_or_else(|| pixels(plan).unwrap_or(side).min(side)) * bytes_per_pixel(plan)
+        };
+        let widest = self.concurrent_operations.clamp(1, self.threads.max(1));
+        let concurrent_operations = match self.operations.iter().max_by_key(|plan| smallest_block(plan)) {
+            Some(plan) => (1..=widest)
+                .rev()
+                .find(|&operations| shares.block_limit(operations) >= smallest_block(plan))
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Memory budget of {} is too small for operation {} ({}): its smallest blocks need {}, \
+                         one operation at a time leaves {} per block; raise --max-memory",
+                        format_bytes(budget),
+                        plan.number,
+                        plan.op_type,
+                        format_bytes(smallest_block(plan)),
+                        format_bytes(shares.block_limit(1))
+                    )
+                })?,
+            None => widest,
+        };
+        let block_limit = shares.block_limit(concurrent_operations);
+
+        // Rasters within SMALL_RASTER_SIDE are processed whole, larger ones in blocks split to the limit
+        let mut blocks: Vec<u64> = self
+            .operations
+            .iter()
+            .map(|plan| match whole(plan) {
+                Some(whole_pixels) => whole_pixels * bytes_per_pixel(plan),
+                None => pixels(plan).map_or(block_limit, |p| (p * bytes_per_pixel(plan)).min(block_limit)),
+            })
+            .collect();
+        blocks.sort_unstable_by(|a, b| b.cmp(a));
+        let mut inputs: Vec<u64> = self
+            .operations
+            .iter()
+            .map(|plan| pixels(plan).map_or(shares.inflight, |p| p * 4 * read_band_count(plan) as u64))
+            .collect();
+        inputs.sort_unstable_by(|a, b| b.cmp(a));
+        let read_ahead = inputs.iter().take(concurrent_operations).sum::<u64>().min(shares.inflight);
+        let peak_bytes = shares.gdal_cache
+            + read_ahead
+            + blocks.iter().take(concurrent_operations).sum::<u64>()
+            + intermediates;
+
+        self.concurrent_operations = concurrent_operations;
+        self.max_inflight_bytes = shares.inflight;
+        self.memory = Some(MemoryPlan {
+            shares,
+            intermediates,
+            concurrent_operations,
+            block_limit,
+            peak_bytes,
+        });
+        Ok(self)
+    }
+
+    /// Human-readable plan
+    pub fn print(&self) {
+        println!(
+            "Batch plan: {} operation(s), {} thread(s) (of {} available), up to {} operation(s) at once \
+             sharing {} block reader threads and {} of read-ahead",
+            self.operations.len(),
+            self.threads,
+            self.available_threads,
+            self.concurrent_operations,
+            self.reader_threads,
+            format_bytes(self.max_inflight_bytes)
+        );
+        let limit = self
+            .max_open_datasets
+            .map(|limit| format!(", at most {} open", limit))
+            .unwrap_or_default();
+        println!(
+            "Shared cache: {} dataset(s){}, {} read by several operations",
+            self.cached_datasets,
+            limit,
+            self.shared_inputs.len()
+        );
+        for (input, numbers) in &self.shared_inputs {
+            let numbers: Vec<String> = numbers.iter().map(|n| n.to_string()).collect();
+            println!("  {} -> operations {}", input, numbers.join(", "));
+        }
+        if let Some(memory) = &self.memory {
+            memory.print();
+        }
+        println!(
+            "Estimated output: {} uncompressed, peak memory {}",
+            format_bytes(self.output_bytes),
+            format_bytes(self.peak_memory_bytes)
+        );
+
+        for plan in &self.operations {
+            println!();
+            let id = plan.id.as_deref().map(|id| format!(" '{}'", id)).unwrap_or_default();
+            let intermediate = if plan.intermediate { " (intermediate)" } else { "" };
+            println!("[{}] {}{}{} -> {}", plan.number, plan.op_type, id, intermediate, plan.outputs.join(", "));
+            if !plan.after.is_empty() {
+                let after: Vec<String> = plan.after.iter().map(|n| n.to_string()).collect();
+                println!("    after: {}", after.join(", "));
+            }
+            println!("    inputs: {}", plan.inputs.join(", "));
+            let settings = serde_json::to_value(&plan.settings).unwrap_or_default();
+            let settings: Vec<String> = settings
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| match value {
+                    Value::String(text) => format!("{}={}", key, text),
+                    other => format!("{}={}", key, other),
+                })
+                .collect();
+            println!("    settings: {}", settings.join(" "));
+            match plan.size {
+                Some((width, height)) => println!(
+                    "    size: {}x{}, {} band(s), output {}, memory {}",
+                    width,
+                    height,
+                    plan.bands,
+                    format_bytes(plan.output_bytes.unwrap_or(0)),
+                    format_bytes(plan.memory_bytes.unwrap_or(0))
+                ),
+                None => println!("    size: unknown (inputs do not open), {} band(s)", plan.bands),
+            }
+        }
+    }
+}
+
+fn collect_unique_paths(config: &BatchConfig, cache: &RasterCache) -> HashSet<String> {
+    let mut paths = HashSet::new();
+
+    for op in &config.operations {
+        let op_paths: HashSet<String> = operation_inputs(op).unwrap_or_default().into_iter().collect();
+
+        // Bare band names refer to the operation's product or STAC item
+        let product = operation_product(op, &config.global);
+        let resolution = op.resolution.or(config.global.resolution);
+        paths.extend(
+            op_paths
+                .iter()
+                .filter_map(|path| product::qualify_input(path, product, resolution, cache).ok()),
+        );
     }
 
     paths
@@ -21,7 +21,7 @@ pub struct Cli {
     #[arg(long, default_value = "10000", global = true)]
     pub scale_factor: i32,
 
-    /// Input scaling factor (for L2A data use 10000, for TOA use 1)
+    /// Input scaling factor for inputs without scale/offset metadata (for L2A data use 10000, for TOA use 1)
     #[arg(long, default_value = "1.0", global = true)]
     pub input_scale_factor: f32,
 
@@ -36,6 +36,64 @@ pub struct Cli {
     /// Use tiled output
     #[arg(long, default_value = "true", global = true)]
     pub tiled: bool,
+
+    /// Output format (GTiff, GPKG, netCDF, Zarr, HFA). Guessed from the output extension if omitted
+    #[arg(long = "of", global = true)]
+    pub output_format: Option<String>,
+
+    /// Overlap rule for inputs given as tile lists or glob patterns (first, last, max)
+    #[arg(long, default_value = "last", global = true)]
+    pub mosaic_rule: String,
+
+    /// Also write a UInt8 class map: break values (0,0.2,0.5), rules (min:max=class[:name[:#color]]) or JSON
+    #[arg(long, global = true, allow_hyphen_values = true)]
+    pub classify: Option<String>,
+
+    // Global since it names the class map of --classify as well as that of `change --thresholds`,
+    // which still accepts it after the subcommand
+    /// Class map path (default: <output>_classes with the output extension)
+    #[arg(long, global = true)]
+    pub class_output: Option<PathBuf>,
+
+    /// Output band to classify (1-based)
+    #[arg(long, default_value = "1", global = true)]
+    pub class_band: usize,
+
+    /// Sentinel-2 (.SAFE directory or .zip) or Landsat product that band names (B04, SCL) or roles (red, nir) refer to
+    #[arg(long, global = true)]
+    pub product: Option<PathBuf>,
+
+    /// Local STAC item JSON whose assets are referred to by key or eo:bands common name (nir, red, swir16)
+    #[arg(long, global = true, conflicts_with = "product")]
+    pub stac_item: Option<PathBuf>,
+
+    /// Resolution in metres of Sentinel-2 bands, resampled if not in the product (default: 10)
+    #[arg(long, global = true)]
+    pub resolution: Option<u32>,
+
+    /// Do not apply the scale/offset found in band, Sentinel-2 or Landsat metadata
+    #[arg(long, global = true)]
+    pub no_auto_scale: bool,
+
+    /// Also write a STAC item (<output>.json) with bbox, projection, raster bands and provenance
+    #[arg(long, global = true)]
+    pub write_stac: bool,
+
+    /// Mask band (SCL, QA_PIXEL, a product band name or a path); masked pixels become nodata in every output
+    #[arg(long, global = true)]
+    pub mask: Option<String>,
+
+    /// How the mask band is encoded (scl, qa_pixel, modis, classes, bits). Guessed from its name if omitted
+    #[arg(long, global = true)]
+    pub mask_type: Option<String>,
+
+    /// Classes or bits to mask: names (cloud, shadow, snow, cirrus, water, nodata) or numbers
+    #[arg(long, default_value = "cloud,shadow,snow", global = true)]
+    pub mask_values: String,
+
+    /// Memory budget (e.g. 12G, 800M; plain numbers are MB) for GDAL's cache, read-ahead and blocks
+    #[arg(long, global = true)]
+    pub max_memory: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -144,10 +202,214 @@ pub enum Commands {
         red: PathBuf,
     },
 
-    /// Process multiple operations from a JSON configuration file
+    /// Best-pixel composite of an index over several acquisitions on the same grid
+    Composite {
+        /// Index computed for every acquisition (ndi, evi, savi, ndwi, ndsi, bsi, msavi2, osavi)
+        #[arg(short = 'i', long)]
+        index: String,
+
+        /// Per-pixel selection rule (max, median, least_cloudy)
+        #[arg(short = 'r', long, default_value = "max")]
+        rule: String,
+
+        /// First index band, once per acquisition (band order as in the index subcommand)
+        #[arg(short = 'a', long, required = true)]
+        band_a: Vec<PathBuf>,
+
+        /// Second index band, once per acquisition
+        #[arg(short = 'b', long)]
+        band_b: Vec<PathBuf>,
+
+        /// Third index band, once per acquisition
+        #[arg(short = 'c', long)]
+        band_c: Vec<PathBuf>,
+
+        /// Fourth index band, once per acquisition
+        #[arg(short = 'd', long)]
+        band_d: Vec<PathBuf>,
+
+        /// Cloud probability band, once per acquisition (required for least_cloudy)
+        #[arg(long)]
+        cloud: Vec<PathBuf>,
+
+        /// Soil adjustment factor for SAVI (default: 0.5)
+        #[arg(short = 'l', long, default_value = "0.5")]
+        soil_factor: f32,
+
+        /// Add a band recording which acquisition (1-based) was selected
+        #[arg(long)]
+        source_band: bool,
+    },
+
+    /// Per-pixel statistics and trends over a time series of index rasters
+    Timeseries {
+        /// Index rasters of the time series, one per date
+        #[arg(short = 'i', long = "input", required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+
+        /// Statistics to compute: mean, median, pNN, count, slope, sen_slope, mk_tau, mk_p
+        #[arg(short = 's', long, value_delimiter = ',', default_value = "mean,median,count,slope,sen_slope,mk_p")]
+        stats: Vec<String>,
+
+        /// Acquisition dates (YYYY-MM-DD), one per input. Read from metadata or file names if omitted
+        #[arg(long, value_delimiter = ',')]
+        dates: Vec<String>,
+    },
+
+    /// Change of an index between a pre and a post acquisition (difference and relative difference)
+    Change {
+        /// Index computed for both acquisitions (ndi, evi, savi, ndwi, ndsi, bsi, msavi2, osavi)
+        #[arg(short = 'i', long)]
+        index: String,
+
+        /// First index band, given twice: pre then post (band order as in the index subcommand)
+        #[arg(short = 'a', long, required = true, num_args = 2)]
+        band_a: Vec<PathBuf>,
+
+        /// Second index band, given twice: pre then post
+        #[arg(short = 'b', long, num_args = 2)]
+        band_b: Vec<PathBuf>,
+
+        /// Third index band, given twice: pre then post
+        #[arg(short = 'c', long, num_args = 2)]
+        band_c: Vec<PathBuf>,
+
+        /// Fourth index band, given twice: pre then post
+        #[arg(short = 'd', long, num_args = 2)]
+        band_d: Vec<PathBuf>,
+
+        /// Soil adjustment factor for SAVI (default: 0.5)
+        #[arg(short = 'l', long, default_value = "0.5")]
+        soil_factor: f32,
+
+        /// Classify the difference into decrease/stable/increase: one value T (stable in [-T, T)) or DECREASE,INCREASE
+        #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+        thresholds: Vec<f32>,
+    },
+
+    /// Season metrics (start, end, peak, length, amplitude, integral) from a dated stack of index rasters
+    Phenology {
+        /// Index rasters of the season, one per date
+        #[arg(short = 'i', long = "input", required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+
+        /// Smoothing method (savgol, double_logistic)
+        #[arg(short = 'm', long, default_value = "savgol")]
+        method: String,
+
+        /// Savitzky-Golay window length in resampled observations (odd)
+        #[arg(long, default_value = "7")]
+        window: usize,
+
+        /// Savitzky-Golay polynomial order
+        #[arg(long, default_value = "2")]
+        order: usize,
+
+        /// Start/end of season threshold as a fraction of the amplitude
+        #[arg(long, default_value = "0.5")]
+        threshold: f32,
+
+        /// Acquisition dates (YYYY-MM-DD), one per input. Read from metadata or file names if omitted
+        #[arg(long, value_delimiter = ',')]
+        dates: Vec<String>,
+    },
+
+    /// Fill nodata gaps of a dated stack of index rasters by temporal interpolation
+    Gapfill {
+        /// Index rasters of the time series, one per date
+        #[arg(short = 'i', long = "input", required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+
+        /// Interpolation method (linear, harmonic, whittaker)
+        #[arg(short = 'm', long, default_value = "linear")]
+        method: String,
+
+        /// Number of annual harmonics of the harmonic fit
+        #[arg(long, default_value = "2")]
+        harmonics: usize,
+
+        /// Smoothing strength of the Whittaker smoother (daily grid)
+        #[arg(long, default_value = "100")]
+        lambda: f64,
+
+        /// Resample to a regular interval in days instead of the input dates
+        #[arg(long)]
+        interval: Option<u32>,
+
+        /// Also write each filled date to its own file (<output>_<YYYY-MM-DD>), usable as timeseries, phenology or composite inputs
+        #[arg(long)]
+        per_date: bool,
+
+        /// Acquisition dates (YYYY-MM-DD), one per input. Read from metadata or file names if omitted
+        #[arg(long, value_delimiter = ',')]
+        dates: Vec<String>,
+    },
+
+    /// Reclassify a raster into a UInt8 class map with a color table and category names
+    Classify {
+        /// Raster to classify (first band, scale/offset and nodata applied)
+        #[arg(short = 'i', long)]
+        input: PathBuf,
+
+        /// Break values (0,0.2,0.5), rules (min:max=class[:name[:#color]]) or JSON (file or literal)
+        #[arg(short = 'r', long, allow_hyphen_values = true)]
+        rules: String,
+    },
+
+    /// Render a raster through a colormap into an RGBA GeoTIFF (output) and an optional quicklook
+    Render {
+        /// Raster to render (first band, scale/offset and nodata applied)
+        #[arg(short = 'i', long)]
+        input: PathBuf,
+
+        /// Colormap name (RdYlGn, viridis, greys; _r reverses) or color file (value r g b [a] per line)
+        #[arg(long, default_value = "RdYlGn")]
+        colormap: String,
+
+        /// Values mapped to the ends of the colormap as MIN,MAX (default: -1,1)
+        #[arg(long, allow_hyphen_values = true)]
+        range: Option<String>,
+
+        /// Downsampled PNG or JPEG quicklook with a world file
+        #[arg(long)]
+        quicklook: Option<PathBuf>,
+
+        /// Longest side of the quicklook in pixels
+        #[arg(long, default_value = "1024")]
+        quicklook_size: usize,
+    },
+
+    /// Process multiple operations from a JSON, YAML or TOML configuration file
     Batch {
-        /// Path to JSON configuration file
+        /// Path to JSON, YAML (.yaml) or TOML (.toml) configuration file
         #[arg(short = 'c', long)]
         config: PathBuf,
+
+        /// Print the resolved plan (settings, inputs, size and memory estimates) without processing
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Format of the dry-run plan (text, json)
+        #[arg(long, default_value = "text")]
+        plan_format: String,
+
+        /// Rerun every operation, even those the manifest records as up to date
+        #[arg(long)]
+        force: bool,
+
+        /// Write a run report: JSON, or NDJSON for .ndjson/.jsonl paths
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+
+    /// Check a batch configuration (types, params, inputs, grids, outputs) without processing
+    Validate {
+        /// Path to JSON, YAML (.yaml) or TOML (.toml) configuration file
+        #[arg(short = 'c', long, required_unless_present = "schema")]
+        config: Option<PathBuf>,
+
+        /// Print the JSON Schema of batch configurations instead
+        #[arg(long)]
+        schema: bool,
     },
 }
\ No newline at end of file
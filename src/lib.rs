@@ -2,6 +2,7 @@
 pub mod cli;
 pub mod utils;
 pub mod processing;
+pub mod batch;
 
 // Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
\ No newline at end of file
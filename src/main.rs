@@ -2,21 +2,108 @@
 use anyhow::Result;
 use clap::Parser;
 
-mod cli;
-mod utils;
-mod processing;
-mod batch;
+use raster_calc::{batch, cli, processing, utils};
 
 use crate::cli::{Cli, Commands};
-use crate::processing::{ParallelProcessor, indices::{NDI, EVI, SAVI, NDWI, NDSI, BSI, MSAVI2, OSAVI}};
+use crate::utils::cache::RasterCache;
+use crate::utils::memory::{self, MemoryShares};
+use crate::utils::mosaic::{self, OverlapRule};
+use crate::utils::product;
+use crate::utils::output::OutputFormat;
+use crate::processing::{ParallelProcessor, indices::{self, NDI, EVI, SAVI, NDWI, NDSI, BSI, MSAVI2, OSAVI}};
+use crate::processing::composite::{Composite, CompositeRule};
+use crate::processing::timeseries;
+use crate::processing::change::ChangeDetection;
+use crate::processing::phenology::{self, Smoothing};
+use crate::processing::gapfill::{self, FillMethod};
+use crate::utils::output;
+use crate::processing::classify::{self, ClassOutput, Classification, RasterValues};
+use crate::processing::render::{self, Colormap, RenderOutput, Renderer};
+use crate::processing::mask::{self, Mask};
+use crate::processing::parallel::IndexCalculator;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let processor = ParallelProcessor::new(None);
+    let output_format = cli
+        .output_format
+        .as_deref()
+        .map(OutputFormat::from_name)
+        .transpose()?;
+    let mosaic_rule = OverlapRule::from_name(&cli.mosaic_rule)?;
+
+    // Optional class map of any calculator result
+    let class_path = cli
+        .class_output
+        .as_ref()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| classify::default_class_path(&cli.output.to_string_lossy()));
+    let class_output = match &cli.classify {
+        Some(rules) if !matches!(cli.command, Commands::Classify { .. }) => {
+            if cli.class_band == 0 {
+                return Err(anyhow::anyhow!("--class-band is 1-based"));
+            }
+            println!("Classes: {}", class_path);
+            Some(ClassOutput::new(Classification::parse(rules)?, &class_path, cli.class_band - 1))
+        }
+        _ => None,
+    };
+
+    let product = cli
+        .stac_item
+        .as_ref()
+        .or(cli.product.as_ref())
+        .map(|p| p.to_string_lossy().to_string());
+
+    // Optional cloud/shadow/snow mask of every output
+    let mask = cli
+        .mask
+        .as_deref()
+        .map(|input| Mask::new(input, cli.mask_type.as_deref(), &mask::parse_values(&cli.mask_values)))
+        .transpose()?;
+
+    // Memory budget shared by GDAL's cache, read-ahead and blocks
+    let max_memory = cli.max_memory.as_deref().map(memory::parse_size).transpose()?;
+    if let Some(budget) = max_memory {
+        let shares = MemoryShares::split(budget);
+        if !matches!(cli.command, Commands::Batch { .. }) {
+            memory::set_gdal_cache(shares.gdal_cache);
+            println!(
+                "Memory budget {}: GDAL cache {}, read-ahead {}, blocks of up to {}",
+                memory::format_bytes(budget),
+                memory::format_bytes(shares.gdal_cache),
+                memory::format_bytes(shares.inflight),
+                memory::format_bytes(shares.block_limit(1))
+            );
+        }
+    }
+
+    let processor = ParallelProcessor::new(None)
+        .with_output_format(output_format)
+        .with_mosaic_rule(mosaic_rule)
+        .with_classification(class_output)
+        .with_product(product.clone(), cli.resolution)
+        .with_auto_scaling(!cli.no_auto_scale)
+        .with_mask(mask)
+        .with_stac_item(cli.write_stac)
+        .with_max_memory(max_memory);
 
     match &cli.command {
-        Commands::Batch { config } => {
-            batch::process_batch(config)?;
+        Commands::Batch { config, dry_run, plan_format, force, report } => {
+            let options = batch::BatchOptions {
+                dry_run: dry_run.then(|| plan_format.clone()),
+                force: *force,
+                report: report.clone(),
+                max_memory,
+            };
+            batch::process_batch(config, &options)?;
+        }
+
+        Commands::Validate { config, schema } => {
+            if *schema {
+                println!("{}", serde_json::to_string_pretty(&batch::config_schema())?);
+            } else if let Some(config) = config {
+                batch::validate_batch(config)?;
+            }
         }
         
         Commands::Ndi { band_a, band_b } => {
@@ -171,6 +258,212 @@ fn main() -> Result<()> {
                 cli.compress_level,     
                 cli.tiled,          
             )?;
+        },
+
+        Commands::Composite { index, rule, band_a, band_b, band_c, band_d, cloud, soil_factor, source_band } => {
+            // Create the per-acquisition index calculator and wrap it in a composite
+            let calculator = indices::from_name(index, Some(*soil_factor))?;
+            let rule = CompositeRule::from_name(rule)?;
+            let bands_per_scene = calculator.required_bands();
+            let scenes = band_a.len();
+
+            let roles = [band_a, band_b, band_c, band_d];
+            for (role, paths) in roles.iter().enumerate().take(bands_per_scene) {
+                if paths.len() != scenes {
+                    return Err(anyhow::anyhow!(
+                        "{} needs {} bands per acquisition: got {} paths for band {} but {} acquisitions",
+                        index, bands_per_scene, paths.len(), role + 1, scenes
+                    ));
+                }
+            }
+
+            // Scene bands in order, followed by the cloud bands
+            let mut inputs = Vec::with_capacity(scenes * (bands_per_scene + 1));
+            for scene in 0..scenes {
+                for paths in roles.iter().take(bands_per_scene) {
+                    inputs.push(paths[scene].to_string_lossy().to_string());
+                }
+            }
+            if rule == CompositeRule::LeastCloudy {
+                if cloud.len() != scenes {
+                    return Err(anyhow::anyhow!(
+                        "least_cloudy needs one --cloud band per acquisition ({} given, {} acquisitions)",
+                        cloud.len(), scenes
+                    ));
+                }
+                inputs.extend(cloud.iter().map(|p| p.to_string_lossy().to_string()));
+            }
+
+            let composite = Composite::new(calculator, scenes, rule, *source_band, None);
+
+            processor.process(
+                composite,
+                &inputs,
+                cli.output.to_string_lossy().as_ref(),
+                !cli.float,
+                cli.scale_factor,
+                cli.input_scale_factor,
+                &cli.compress,          
+                cli.compress_level,     
+                cli.tiled,          
+            )?;
+        },
+        Commands::Change { index, band_a, band_b, band_c, band_d, soil_factor, thresholds } => {
+            // Create the index calculator and compare its pre and post results
+            let calculator = indices::from_name(index, Some(*soil_factor))?;
+            let bands = calculator.required_bands();
+
+            let roles = [band_a, band_b, band_c, band_d];
+            for (role, paths) in roles.iter().enumerate().take(bands) {
+                if paths.len() != 2 {
+                    return Err(anyhow::anyhow!(
+                        "{} needs band {} for both the pre and the post acquisition", index, role + 1
+                    ));
+                }
+            }
+
+            // Pre bands followed by post bands
+            let inputs: Vec<String> = (0..2)
+                .flat_map(|date| roles.iter().take(bands).map(move |paths| paths[date].to_string_lossy().to_string()))
+                .collect();
+
+            // Change thresholds are a preset for --classify on the difference band
+            let processor = if thresholds.is_empty() {
+                processor
+            } else {
+                if cli.classify.is_some() {
+                    return Err(anyhow::anyhow!("Use either --thresholds or --classify, not both"));
+                }
+                println!("Change classes: {}", class_path);
+                let classification = Classification::from_thresholds(thresholds)?;
+                processor.with_classification(Some(ClassOutput::new(classification, &class_path, 0)))
+            };
+            let output = cli.output.to_string_lossy().to_string();
+
+            processor.process(
+                ChangeDetection::new(calculator),
+                &inputs,
+                &output,
+                !cli.float,
+                cli.scale_factor,
+                cli.input_scale_factor,
+                &cli.compress,          
+                cli.compress_level,     
+                cli.tiled,          
+            )?;
+        },
+        Commands::Classify { input, rules } => {
+            // Only the class map is written, to the output path
+            let scratch = RasterCache::new();
+            let input = product::qualify_input(&input.to_string_lossy(), product.as_deref(), cli.resolution, &scratch)?;
+            let input = mosaic::resolve_input(&input, mosaic_rule, &scratch)?;
+            let classification = Classification::parse(rules)?;
+            let output = cli.output.to_string_lossy().to_string();
+
+            processor
+                .with_classification(Some(ClassOutput::new(classification, &output, 0)))
+                .with_index_output(false)
+                .process(
+                    RasterValues::from_path(&input)?,
+                    &[input],
+                    &output,
+                    !cli.float,
+                    cli.scale_factor,
+                    1.0,
+                    &cli.compress,
+                    cli.compress_level,
+                    cli.tiled,
+                )?;
+        },
+        Commands::Render { input, colormap, range, quicklook, quicklook_size } => {
+            // Only the RGBA rendering is written, to the output path
+            let scratch = RasterCache::new();
+            let input = product::qualify_input(&input.to_string_lossy(), product.as_deref(), cli.resolution, &scratch)?;
+            let input = mosaic::resolve_input(&input, mosaic_rule, &scratch)?;
+            let range = range.as_deref().map(render::parse_range).transpose()?;
+            let renderer = Renderer::new(Colormap::parse(colormap)?, range)?;
+            let output = cli.output.to_string_lossy().to_string();
+            let quicklook = quicklook.as_ref().map(|p| p.to_string_lossy().to_string());
+            let render_output = RenderOutput::new(renderer, &output, 0)
+                .with_quicklook(quicklook, *quicklook_size)?;
+
+            processor
+                .with_render(Some(render_output))
+                .with_index_output(false)
+                .process(
+                    RasterValues::from_path(&input)?,
+                    &[input],
+                    &output,
+                    !cli.float,
+                    cli.scale_factor,
+                    1.0,
+                    &cli.compress,
+                    cli.compress_level,
+                    cli.tiled,
+                )?;
+        },
+        Commands::Timeseries { inputs, stats, dates } => {
+            // Create time series calculator with one input per date
+            let inputs: Vec<String> = inputs.iter().map(|p| p.to_string_lossy().to_string()).collect();
+            let timeseries = timeseries::from_inputs(&inputs, stats, dates)?;
+
+            processor.process(
+                timeseries,
+                &inputs,
+                cli.output.to_string_lossy().as_ref(),
+                !cli.float,
+                cli.scale_factor,
+                cli.input_scale_factor,
+                &cli.compress,          
+                cli.compress_level,     
+                cli.tiled,          
+            )?;
+        },
+        Commands::Phenology { inputs, method, window, order, threshold, dates } => {
+            // Create phenology calculator with one input per date
+            let inputs: Vec<String> = inputs.iter().map(|p| p.to_string_lossy().to_string()).collect();
+            let smoothing = Smoothing::from_name(method, *window, *order)?;
+            let phenology = phenology::from_inputs(&inputs, smoothing, *threshold, dates)?;
+
+            processor.process(
+                phenology,
+                &inputs,
+                cli.output.to_string_lossy().as_ref(),
+                !cli.float,
+                cli.scale_factor,
+                cli.input_scale_factor,
+                &cli.compress,          
+                cli.compress_level,     
+                cli.tiled,          
+            )?;
+        },
+        Commands::Gapfill { inputs, method, harmonics, lambda, interval, per_date, dates } => {
+            // Create gap filling calculator with one input per date and one output band per output date
+            let inputs: Vec<String> = inputs.iter().map(|p| p.to_string_lossy().to_string()).collect();
+            let method = FillMethod::from_name(method, *harmonics, *lambda)?;
+            let gapfill = gapfill::from_inputs(&inputs, method, *interval, dates)?;
+            let output_dates = gapfill.output_dates().to_vec();
+            let output_path = cli.output.to_string_lossy().to_string();
+
+            processor.process(
+                gapfill,
+                &inputs,
+                &output_path,
+                !cli.float,
+                cli.scale_factor,
+                cli.input_scale_factor,
+                &cli.compress,          
+                cli.compress_level,     
+                cli.tiled,          
+            )?;
+
+            if *per_date {
+                let format = OutputFormat::resolve(output_format, &output_path)?;
+                let options = format.creation_options(&cli.compress, cli.compress_level, cli.tiled, !cli.float);
+                let paths = gapfill::per_date_paths(&output_path, &output_dates);
+                output::split_bands(&output_path, &paths, format, &options)?;
+                println!("Wrote {} per-date files", paths.len());
+            }
         }
     }
 
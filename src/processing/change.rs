@@ -0,0 +1,111 @@
+// src/processing/change.rs
+use crate::processing::parallel::{IndexCalculator, NODATA_VALUE_FLOAT};
+use crate::utils::gdal_ext::TypedBuffer;
+use gdal::raster::Buffer;
+use rayon::prelude::*;
+
+/// Fixed-point multiplier of the difference, ±2 stays within Int16
+const DIFFERENCE_SCALE: f32 = 10000.0;
+/// Fixed-point multiplier of the relative difference, in percent
+const RELATIVE_SCALE: f32 = 100.0;
+
+/// Bi-temporal change of an index between a pre and a post acquisition
+///
+/// Inputs are the index bands of the pre scene followed by those of the post scene.
+/// Outputs the difference (post - pre) and the relative difference ((post - pre) / |pre|).
+/// In fixed-point outputs the difference is scaled by 10000 without the ±1 clamp of index
+/// bands, since post - pre of a normalized index spans ±2, and the relative difference is
+/// written in percent since it exceeds ±1 for changes above 100%.
+pub struct ChangeDetection<I: IndexCalculator> {
+    calculator: I,
+    name: String,
+}
+
+impl<I: IndexCalculator> ChangeDetection<I> {
+    pub fn new(calculator: I) -> Self {
+        let name = format!("{} change", calculator.name());
+        Self { calculator, name }
+    }
+
+    fn change(&self, inputs: &[TypedBuffer]) -> (TypedBuffer, TypedBuffer) {
+        let bands = self.calculator.required_bands();
+        let pre = self.calculator.calculate(&inputs[..bands]);
+        let post = self.calculator.calculate(&inputs[bands..2 * bands]);
+
+        let shape = pre.shape();
+        let pre = pre.as_f32().expect("index calculators return F32 buffers").data();
+        let post = post.as_f32().expect("index calculators return F32 buffers").data();
+
+        let mut difference = vec![NODATA_VALUE_FLOAT; pre.len()];
+        let mut relative = vec![NODATA_VALUE_FLOAT; pre.len()];
+
+        difference
+            .par_iter_mut()
+            .zip(relative.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, (diff, rel))| {
+                if pre[i] == NODATA_VALUE_FLOAT || post[i] == NODATA_VALUE_FLOAT {
+                    return;
+                }
+                *diff = post[i] - pre[i];
+                if pre[i].abs() > f32::EPSILON {
+                    *rel = *diff / pre[i].abs();
+                }
+            });
+
+        (
+            TypedBuffer::F32(Buffer::new(shape, difference)),
+            TypedBuffer::F32(Buffer::new(shape, relative)),
+        )
+    }
+}
+
+impl<I: IndexCalculator> IndexCalculator for ChangeDetection<I> {
+    fn calculate(&self, inputs: &[TypedBuffer]) -> TypedBuffer {
+        self.change(inputs).0
+    }
+
+    fn required_bands(&self) -> usize {
+        2 * self.calculator.required_bands()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formula(&self) -> String {
+        format!("post - pre of {}", self.calculator.formula())
+    }
+
+    fn parameters(&self) -> Vec<(String, String)> {
+        self.calculator.parameters()
+    }
+
+    fn needs_input_scaling(&self) -> bool {
+        self.calculator.needs_input_scaling()
+    }
+
+    fn applies_input_scaling(&self) -> bool {
+        self.calculator.applies_input_scaling()
+    }
+
+    fn output_bands(&self) -> Vec<String> {
+        vec![
+            format!("{} difference", self.calculator.name()),
+            format!("{} relative difference", self.calculator.name()),
+        ]
+    }
+
+    fn calculate_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        let (difference, relative) = self.change(inputs);
+        vec![difference, relative]
+    }
+
+    fn band_scale(&self, band: usize) -> Option<f32> {
+        match band {
+            0 => Some(DIFFERENCE_SCALE),
+            1 => Some(RELATIVE_SCALE),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,485 @@
+// src/processing/classify.rs
+use std::ffi::{c_char, CString};
+
+use anyhow::{Context, Result};
+use gdal::raster::{ColorEntry, ColorTable, PaletteInterpretation, RasterBand};
+use gdal::{Dataset, Metadata};
+use serde::Deserialize;
+
+use crate::processing::parallel::{IndexCalculator, NODATA_VALUE_FLOAT};
+use crate::processing::timeseries::StackInput;
+use crate::utils::gdal_ext::TypedBuffer;
+
+/// Class value written where the input is nodata or matches no rule
+pub const NODATA_CLASS: u8 = 0;
+
+/// One class: values in `[min, max)` are assigned `value`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassRule {
+    pub min: f32,
+    pub max: f32,
+    pub value: u8,
+    pub name: String,
+    pub color: [u8; 4],
+}
+
+impl ClassRule {
+    pub fn contains(&self, value: f32) -> bool {
+        value >= self.min && value < self.max
+    }
+}
+
+/// Class rule as written in JSON; a missing bound is open-ended
+#[derive(Deserialize, Debug)]
+pub struct RuleSpec {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    #[serde(rename = "class")]
+    pub value: u8,
+    pub name: Option<String>,
+    pub color: Option<String>,
+}
+
+/// JSON classification: break values, explicit rules, or a bare list of rules
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ClassificationSpec {
+    Breaks {
+        breaks: Vec<f32>,
+        #[serde(default)]
+        names: Vec<String>,
+        #[serde(default)]
+        colors: Vec<String>,
+    },
+    Rules {
+        rules: Vec<RuleSpec>,
+    },
+    List(Vec<RuleSpec>),
+}
+
+/// Ordered set of class rules turning a continuous result into a UInt8 class map
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    pub rules: Vec<ClassRule>,
+}
+
+impl Classification {
+    pub fn new(rules: Vec<ClassRule>) -> Result<Self> {
+        if rules.is_empty() {
+            return Err(anyhow::anyhow!("A classification needs at least one class"));
+        }
+        if let Some(rule) = rules.iter().find(|r| r.value == NODATA_CLASS) {
+            return Err(anyhow::anyhow!(
+                "Class '{}' uses value {}, which is reserved for nodata",
+                rule.name,
+                NODATA_CLASS
+            ));
+        }
+        if let Some(rule) = rules.iter().find(|r| r.min >= r.max) {
+            return Err(anyhow::anyhow!(
+                "Class '{}' has an empty range [{}, {})",
+                rule.name,
+                rule.min,
+                rule.max
+            ));
+        }
+        Ok(Self { rules })
+    }
+
+    /// Change classes: 1 decrease (below `decrease`), 2 stable, 3 increase (at or above `increase`)
+    pub fn change(decrease: f32, increase: f32) -> Result<Self> {
+        if decrease > increase {
+            return Err(anyhow::anyhow!(
+                "Decrease threshold {} is above increase threshold {}",
+                decrease,
+                increase
+            ));
+        }
+        Self::new(vec![
+            ClassRule {
+                min: f32::NEG_INFINITY,
+                max: decrease,
+                value: 1,
+                name: "Decrease".to_string(),
+                color: [215, 48, 39, 255],
+            },
+            ClassRule {
+                min: decrease,
+                max: increase,
+                value: 2,
+                name: "Stable".to_string(),
+                color: [220, 220, 220, 255],
+            },
+            ClassRule {
+                min: increase,
+                max: f32::INFINITY,
+                value: 3,
+                name: "Increase".to_string(),
+                color: [26, 152, 80, 255],
+            },
+        ])
+    }
+
+    /// Change classes from `--thresholds`: one value `t` means `[-t, t)` is stable,
+    /// two values are the decrease and increase thresholds
+    pub fn from_thresholds(thresholds: &[f32]) -> Result<Self> {
+        match thresholds {
+            [t] => Self::change(-t.abs(), t.abs()),
+            [decrease, increase] => Self::change(*decrease, *increase),
+            _ => Err(anyhow::anyhow!(
+                "Expected one or two change thresholds, got {}",
+                thresholds.len()
+            )),
+        }
+    }
+
+    /// Classes between consecutive break values: below the first break is class 1,
+    /// at or above the last break is class `breaks.len() + 1`
+    pub fn from_breaks(breaks: &[f32], names: &[String], colors: &[[u8; 4]]) -> Result<Self> {
+        if breaks.is_empty() {
+            return Err(anyhow::anyhow!("At least one break value is needed"));
+        }
+        if breaks.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(anyhow::anyhow!("Break values must be strictly increasing: {:?}", breaks));
+        }
+        let classes = breaks.len() + 1;
+        if classes > u8::MAX as usize {
+            return Err(anyhow::anyhow!("Too many classes for a UInt8 raster: {}", classes));
+        }
+        if !names.is_empty() && names.len() != classes {
+            return Err(anyhow::anyhow!(
+                "{} breaks define {} classes but {} names were given",
+                breaks.len(),
+                classes,
+                names.len()
+            ));
+        }
+        if !colors.is_empty() && colors.len() != classes {
+            return Err(anyhow::anyhow!(
+                "{} breaks define {} classes but {} colors were given",
+                breaks.len(),
+                classes,
+                colors.len()
+            ));
+        }
+
+        let rules = (0..classes)
+            .map(|i| {
+                let min = if i == 0 { f32::NEG_INFINITY } else { breaks[i - 1] };
+                let max = if i == breaks.len() { f32::INFINITY } else { breaks[i] };
+                let name = names.get(i).cloned().unwrap_or_else(|| match i {
+                    0 => format!("< {}", max),
+                    _ if i == breaks.len() => format!(">= {}", min),
+                    _ => format!("{} - {}", min, max),
+                });
+                ClassRule {
+                    min,
+                    max,
+                    value: (i + 1) as u8,
+                    name,
+                    color: colors.get(i).copied().unwrap_or_else(|| ramp_color(i, classes)),
+                }
+            })
+            .collect();
+        Self::new(rules)
+    }
+
+    /// Parse a classification given on the command line or in a batch config:
+    /// - break values: `0,0.2,0.5`
+    /// - inline rules: `min:max=class[:name[:color]]`, comma-separated, empty bounds are open
+    /// - JSON: a path to a `.json` file or a literal object/array (see [`ClassificationSpec`])
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if spec.starts_with('{') || spec.starts_with('[') {
+            let parsed: ClassificationSpec =
+                serde_json::from_str(spec).context("Invalid JSON classification")?;
+            return Self::from_spec(parsed);
+        }
+        if spec.to_lowercase().ends_with(".json") {
+            let content = std::fs::read_to_string(spec)
+                .with_context(|| format!("Failed to read classification file {}", spec))?;
+            let parsed: ClassificationSpec = serde_json::from_str(&content)
+                .with_context(|| format!("Invalid classification file {}", spec))?;
+            return Self::from_spec(parsed);
+        }
+
+        if spec.contains('=') {
+            let rules = spec
+                .split(',')
+                .map(parse_inline_rule)
+                .collect::<Result<Vec<_>>>()?;
+            return Self::from_spec(ClassificationSpec::List(rules));
+        }
+
+        let breaks = spec
+            .split(',')
+            .map(|b| {
+                b.trim()
+                    .parse::<f32>()
+                    .map_err(|_| anyhow::anyhow!("Invalid break value '{}'", b))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_breaks(&breaks, &[], &[])
+    }
+
+    /// Classification from a batch config value: a string as accepted by [`Self::parse`]
+    /// or the JSON form itself
+    pub fn from_value(value: &serde_json::Value) -> Result<Self> {
+        match value {
+            serde_json::Value::String(spec) => Self::parse(spec),
+            value => Self::from_spec(
+                serde_json::from_value(value.clone()).context("Invalid classification")?,
+            ),
+        }
+    }
+
+    /// Build a classification from its JSON form
+    pub fn from_spec(spec: ClassificationSpec) -> Result<Self> {
+        match spec {
+            ClassificationSpec::Breaks {
+                breaks,
+                names,
+                colors,
+            } => {
+                let colors = colors
+                    .iter()
+                    .map(|c| parse_color(c))
+                    .collect::<Result<Vec<_>>>()?;
+                Self::from_breaks(&breaks, &names, &colors)
+            }
+            ClassificationSpec::Rules { rules } | ClassificationSpec::List(rules) => {
+                let count = rules.len();
+                let rules = rules
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, rule)| {
+                        Ok(ClassRule {
+                            min: rule.min.unwrap_or(f32::NEG_INFINITY),
+                            max: rule.max.unwrap_or(f32::INFINITY),
+                            value: rule.value,
+                            name: rule.name.unwrap_or_else(|| format!("Class {}", rule.value)),
+                            color: match rule.color {
+                                Some(color) => parse_color(&color)?,
+                                None => ramp_color(i, count),
+                            },
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Self::new(rules)
+            }
+        }
+    }
+
+    /// Class of a single value, the first matching rule wins
+    pub fn classify(&self, value: f32) -> u8 {
+        if value == NODATA_VALUE_FLOAT || value.is_nan() {
+            return NODATA_CLASS;
+        }
+        self.rules
+            .iter()
+            .find(|rule| rule.contains(value))
+            .map_or(NODATA_CLASS, |rule| rule.value)
+    }
+
+    pub fn classify_values(&self, values: &[f32]) -> Vec<u8> {
+        values.iter().map(|&v| self.classify(v)).collect()
+    }
+
+    /// Color table with one entry per class value, nodata is transparent
+    pub fn color_table(&self) -> ColorTable<'static> {
+        let mut table = ColorTable::new(PaletteInterpretation::Rgba);
+        table.set_color_entry(NODATA_CLASS as u16, &ColorEntry::rgba(0, 0, 0, 0));
+        for rule in &self.rules {
+            let [r, g, b, a] = rule.color.map(i16::from);
+            table.set_color_entry(rule.value as u16, &ColorEntry::rgba(r, g, b, a));
+        }
+        table
+    }
+
+    /// Category names indexed by class value, unused values are empty
+    pub fn category_names(&self) -> Vec<String> {
+        let max_value = self.rules.iter().map(|r| r.value).max().unwrap_or(0) as usize;
+        let mut names = vec![String::new(); max_value + 1];
+        names[NODATA_CLASS as usize] = "No data".to_string();
+        for rule in &self.rules {
+            names[rule.value as usize] = rule.name.clone();
+        }
+        names
+    }
+
+    /// Attach nodata, the color table and the category names to a class band
+    pub fn apply_to_band(&self, band: &mut RasterBand) -> Result<()> {
+        band.set_no_data_value(Some(NODATA_CLASS as f64))?;
+        band.set_color_table(&self.color_table());
+        set_category_names(band, &self.category_names())
+    }
+}
+
+/// Classification of one calculator output band, written to its own UInt8 raster
+#[derive(Debug, Clone)]
+pub struct ClassOutput {
+    pub classification: Classification,
+    pub path: String,
+    /// 0-based index of the calculator output band to classify
+    pub band: usize,
+}
+
+impl ClassOutput {
+    pub fn new(classification: Classification, path: &str, band: usize) -> Self {
+        Self {
+            classification,
+            path: path.to_string(),
+            band,
+        }
+    }
+
+    /// Copy the georeferencing of `reference` and set up the class band
+    pub fn setup(&self, output: &mut Dataset, reference: &Dataset, description: &str) -> Result<()> {
+        output.set_projection(&reference.projection())?;
+        output.set_geo_transform(&reference.geo_transform()?)?;
+
+        let mut band = output.rasterband(1)?;
+        band.set_description(description)?;
+        self.classification.apply_to_band(&mut band)
+    }
+}
+
+/// Parse `min:max=class[:name[:color]]`
+fn parse_inline_rule(rule: &str) -> Result<RuleSpec> {
+    let invalid = || anyhow::anyhow!("Invalid class rule '{}', expected min:max=class[:name[:color]]", rule);
+
+    let (range, class) = rule.trim().split_once('=').ok_or_else(invalid)?;
+    let (min, max) = range.split_once(':').ok_or_else(invalid)?;
+    let bound = |value: &str| -> Result<Option<f32>> {
+        match value.trim() {
+            "" => Ok(None),
+            value => value.parse().map(Some).map_err(|_| invalid()),
+        }
+    };
+
+    let mut parts = class.splitn(3, ':');
+    let value = parts
+        .next()
+        .and_then(|v| v.trim().parse().ok())
+        .ok_or_else(invalid)?;
+    let name = parts
+        .next()
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty());
+
+    Ok(RuleSpec {
+        min: bound(min)?,
+        max: bound(max)?,
+        value,
+        name,
+        color: parts.next().map(str::to_string),
+    })
+}
+
+/// Parse `#rrggbb` or `#rrggbbaa`
+pub fn parse_color(color: &str) -> Result<[u8; 4]> {
+    let hex = color.trim().trim_start_matches('#');
+    let invalid = || anyhow::anyhow!("Invalid color '{}', expected #rrggbb or #rrggbbaa", color);
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Ok([channel(0)?, channel(2)?, channel(4)?, alpha])
+}
+
+/// Default class color: brown through yellow to green, like a vegetation legend
+fn ramp_color(index: usize, count: usize) -> [u8; 4] {
+    const STOPS: [[f32; 3]; 3] = [[166.0, 97.0, 26.0], [255.0, 255.0, 191.0], [26.0, 150.0, 65.0]];
+    let position = if count <= 1 {
+        1.0
+    } else {
+        (index.min(count - 1)) as f32 / (count - 1) as f32
+    };
+    let scaled = position * 2.0;
+    let segment = (scaled.floor() as usize).min(1);
+    let t = scaled - segment as f32;
+    let [a, b] = [STOPS[segment], STOPS[segment + 1]];
+    [
+        (a[0] + (b[0] - a[0]) * t).round() as u8,
+        (a[1] + (b[1] - a[1]) * t).round() as u8,
+        (a[2] + (b[2] - a[2]) * t).round() as u8,
+        255,
+    ]
+}
+
+/// Physical values of a single raster (scale, offset and nodata applied), for classifying
+/// existing rasters
+pub struct RasterValues {
+    input: StackInput,
+    name: String,
+}
+
+impl RasterValues {
+    pub fn new(input: StackInput, name: &str) -> Self {
+        Self {
+            input,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn from_path(path: &str) -> Result<Self> {
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .map_or("Raster".into(), |s| s.to_string_lossy());
+        Ok(Self::new(StackInput::from_path(path)?, &name))
+    }
+}
+
+impl IndexCalculator for RasterValues {
+    fn calculate(&self, inputs: &[TypedBuffer]) -> TypedBuffer {
+        let buffer = inputs[0].as_f32().expect("inputs are read as F32");
+        let values = buffer
+            .data()
+            .iter()
+            .map(|&raw| self.input.value(raw).unwrap_or(NODATA_VALUE_FLOAT))
+            .collect();
+        TypedBuffer::F32(gdal::raster::Buffer::new(buffer.shape(), values))
+    }
+
+    fn required_bands(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn applies_input_scaling(&self) -> bool {
+        true
+    }
+}
+
+/// Class map path next to `output`, e.g. `change.tif` -> `change_classes.tif`
+pub fn default_class_path(output: &str) -> String {
+    let path = std::path::Path::new(output);
+    let stem = path.file_stem().map_or("output".into(), |s| s.to_string_lossy());
+    let file_name = match path.extension() {
+        Some(ext) => format!("{}_classes.{}", stem, ext.to_string_lossy()),
+        None => format!("{}_classes", stem),
+    };
+    path.with_file_name(file_name).to_string_lossy().to_string()
+}
+
+/// gdal has no safe wrapper for GDALSetRasterCategoryNames
+fn set_category_names(band: &mut RasterBand, names: &[String]) -> Result<()> {
+    let names = names
+        .iter()
+        .map(|name| CString::new(name.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut pointers: Vec<*mut c_char> = names.iter().map(|n| n.as_ptr() as *mut c_char).collect();
+    pointers.push(std::ptr::null_mut());
+
+    let result =
+        unsafe { gdal_sys::GDALSetRasterCategoryNames(band.c_rasterband(), pointers.as_mut_ptr()) };
+    if result != gdal_sys::CPLErr::CE_None {
+        return Err(anyhow::anyhow!("Failed to set category names"));
+    }
+    Ok(())
+}
@@ -0,0 +1,202 @@
+// src/processing/composite.rs
+use crate::processing::parallel::{IndexCalculator, NODATA_VALUE_FLOAT};
+use crate::utils::gdal_ext::TypedBuffer;
+use anyhow::Result;
+use rayon::prelude::*;
+
+/// Per-pixel selection rule for best-pixel composites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompositeRule {
+    /// Highest index value (e.g. max-NDVI)
+    MaxIndex,
+    /// Median of the valid index values
+    Median,
+    /// Index of the acquisition with the lowest cloud value
+    LeastCloudy,
+}
+
+impl CompositeRule {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "max" | "max_index" | "max-index" => Ok(CompositeRule::MaxIndex),
+            "median" => Ok(CompositeRule::Median),
+            "least_cloudy" | "least-cloudy" => Ok(CompositeRule::LeastCloudy),
+            _ => Err(anyhow::anyhow!(
+                "Unknown composite rule '{}'. Expected one of: max, median, least_cloudy",
+                name
+            )),
+        }
+    }
+}
+
+/// Best-pixel composite of an index over several acquisitions on the same grid
+///
+/// Inputs are the index bands of every scene in turn (scene 1 bands, scene 2 bands, ...),
+/// followed by one cloud band per scene for [`CompositeRule::LeastCloudy`].
+/// Cloud bands hold cloud probability or any value where lower means clearer.
+pub struct Composite<I: IndexCalculator> {
+    calculator: I,
+    scenes: usize,
+    rule: CompositeRule,
+    source_band: bool,
+    name: String,
+}
+
+impl<I: IndexCalculator> Composite<I> {
+    pub fn new(
+        calculator: I,
+        scenes: usize,
+        rule: CompositeRule,
+        source_band: bool,
+        name: Option<String>,
+    ) -> Self {
+        let name = name.unwrap_or_else(|| format!("{} composite", calculator.name()));
+        Self {
+            calculator,
+            scenes,
+            rule,
+            source_band,
+            name,
+        }
+    }
+
+    /// Calculate the index of every scene and select per pixel.
+    /// Returns the composite values and the 1-based scene chosen for each pixel
+    fn composite(&self, inputs: &[TypedBuffer]) -> (TypedBuffer, TypedBuffer) {
+        let bands_per_scene = self.calculator.required_bands();
+
+        let scene_results: Vec<TypedBuffer> = (0..self.scenes)
+            .map(|s| {
+                self.calculator
+                    .calculate(&inputs[s * bands_per_scene..(s + 1) * bands_per_scene])
+            })
+            .collect();
+        let scene_values: Vec<&[f32]> = scene_results
+            .iter()
+            .map(|r| r.as_f32().expect("index calculators return F32 buffers").data())
+            .collect();
+
+        let cloud_values: Vec<&[f32]> = if self.rule == CompositeRule::LeastCloudy {
+            (0..self.scenes)
+                .map(|s| match &inputs[self.scenes * bands_per_scene + s] {
+                    TypedBuffer::F32(buf) => buf.data(),
+                    _ => panic!("Unsupported cloud band type for composite calculation"),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let shape = scene_results[0].shape();
+        let mut values = vec![NODATA_VALUE_FLOAT; shape.0 * shape.1];
+        let mut sources = vec![NODATA_VALUE_FLOAT; shape.0 * shape.1];
+
+        values
+            .par_iter_mut()
+            .zip(sources.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, (value, source))| {
+                let valid = (0..self.scenes)
+                    .filter(|&s| scene_values[s][i] != NODATA_VALUE_FLOAT)
+                    .map(|s| (s, scene_values[s][i]));
+
+                let selected = match self.rule {
+                    CompositeRule::MaxIndex => valid.max_by(|a, b| a.1.total_cmp(&b.1)),
+                    CompositeRule::LeastCloudy => valid
+                        .filter(|&(s, _)| cloud_values[s][i] >= 0.0)
+                        .min_by(|a, b| cloud_values[a.0][i].total_cmp(&cloud_values[b.0][i])),
+                    CompositeRule::Median => {
+                        let mut valid: Vec<(usize, f32)> = valid.collect();
+                        if valid.is_empty() {
+                            None
+                        } else {
+                            valid.sort_by(|a, b| a.1.total_cmp(&b.1));
+                            let mid = valid.len() / 2;
+                            let median = if valid.len() % 2 == 1 {
+                                valid[mid].1
+                            } else {
+                                (valid[mid - 1].1 + valid[mid].1) / 2.0
+                            };
+                            // Record the acquisition closest to the median
+                            let closest = valid
+                                .iter()
+                                .min_by(|a, b| (a.1 - median).abs().total_cmp(&(b.1 - median).abs()))
+                                .unwrap()
+                                .0;
+                            Some((closest, median))
+                        }
+                    }
+                };
+
+                if let Some((scene, selected_value)) = selected {
+                    *value = selected_value;
+                    *source = (scene + 1) as f32;
+                }
+            });
+
+        (
+            TypedBuffer::F32(gdal::raster::Buffer::new(shape, values)),
+            TypedBuffer::F32(gdal::raster::Buffer::new(shape, sources)),
+        )
+    }
+}
+
+impl<I: IndexCalculator> IndexCalculator for Composite<I> {
+    fn calculate(&self, inputs: &[TypedBuffer]) -> TypedBuffer {
+        self.composite(inputs).0
+    }
+
+    fn required_bands(&self) -> usize {
+        let cloud_bands = if self.rule == CompositeRule::LeastCloudy {
+            self.scenes
+        } else {
+            0
+        };
+        self.scenes * self.calculator.required_bands() + cloud_bands
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn formula(&self) -> String {
+        self.calculator.formula()
+    }
+
+    fn parameters(&self) -> Vec<(String, String)> {
+        let mut parameters = self.calculator.parameters();
+        parameters.push(("composite_rule".to_string(), format!("{:?}", self.rule)));
+        parameters.push(("scenes".to_string(), self.scenes.to_string()));
+        parameters
+    }
+
+    fn needs_input_scaling(&self) -> bool {
+        // Scaling cloud bands too keeps their order, so least-cloudy selection is unaffected
+        self.calculator.needs_input_scaling()
+    }
+
+    fn applies_input_scaling(&self) -> bool {
+        self.calculator.applies_input_scaling()
+    }
+
+    fn output_bands(&self) -> Vec<String> {
+        if self.source_band {
+            vec![self.name.clone(), "Source acquisition".to_string()]
+        } else {
+            vec![self.name.clone()]
+        }
+    }
+
+    fn calculate_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        let (values, sources) = self.composite(inputs);
+        if self.source_band {
+            vec![values, sources]
+        } else {
+            vec![values]
+        }
+    }
+
+    fn is_scaled_band(&self, band: usize) -> bool {
+        band == 0
+    }
+}
@@ -0,0 +1,333 @@
+// src/processing/gapfill.rs
+use std::f64::consts::PI;
+
+use crate::processing::parallel::IndexCalculator;
+use crate::processing::timeseries::{self, interpolate, solve_linear, StackInput};
+use crate::utils::dates::Date;
+use crate::utils::gdal_ext::TypedBuffer;
+use anyhow::Result;
+
+/// Period of the harmonic fit, in days
+const YEAR_DAYS: f64 = 365.25;
+
+/// Per-pixel temporal interpolation method
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillMethod {
+    /// Linear interpolation between the nearest valid observations, observations are kept
+    Linear,
+    /// Least squares fit of a mean plus `harmonics` annual harmonics
+    Harmonic { harmonics: usize },
+    /// Whittaker smoother with a second-order difference penalty on a daily grid
+    Whittaker { lambda: f64 },
+}
+
+impl FillMethod {
+    /// Parse a method name (linear, harmonic, whittaker); `harmonics` and `lambda` apply to
+    /// the harmonic fit and the Whittaker smoother
+    pub fn from_name(name: &str, harmonics: usize, lambda: f64) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "linear" => Ok(FillMethod::Linear),
+            "harmonic" | "fourier" => {
+                if harmonics == 0 {
+                    return Err(anyhow::anyhow!("A harmonic fit needs at least one harmonic"));
+                }
+                Ok(FillMethod::Harmonic { harmonics })
+            }
+            "whittaker" => {
+                if lambda <= 0.0 {
+                    return Err(anyhow::anyhow!("Whittaker lambda must be positive, got {}", lambda));
+                }
+                Ok(FillMethod::Whittaker { lambda })
+            }
+            _ => Err(anyhow::anyhow!(
+                "Unknown gap filling method '{}'. Expected one of: linear, harmonic, whittaker",
+                name
+            )),
+        }
+    }
+
+    /// Fewest valid observations a pixel needs to be filled
+    pub fn min_observations(&self) -> usize {
+        match self {
+            FillMethod::Linear | FillMethod::Whittaker { .. } => 2,
+            FillMethod::Harmonic { harmonics } => 2 * harmonics + 2,
+        }
+    }
+}
+
+/// Set up a [`GapFill`] calculator for the given stack paths and dates.
+/// With `interval` (days) values are resampled from the first date on, otherwise
+/// the stack is filled at its own dates
+pub fn from_inputs(
+    paths: &[String],
+    method: FillMethod,
+    interval: Option<u32>,
+    dates: &[String],
+) -> Result<GapFill> {
+    let dates = timeseries::stack_dates(paths, dates)?.ok_or_else(|| {
+        anyhow::anyhow!("Acquisition dates not found for all inputs, pass them with --dates")
+    })?;
+    let inputs = paths
+        .iter()
+        .map(|p| StackInput::from_path(p))
+        .collect::<Result<Vec<_>>>()?;
+
+    let output_dates = match interval {
+        Some(0) => return Err(anyhow::anyhow!("The resampling interval must be at least 1 day")),
+        Some(interval) => {
+            let first = dates.iter().min().unwrap().days_since_epoch();
+            let last = dates.iter().max().unwrap().days_since_epoch();
+            (first..=last)
+                .step_by(interval as usize)
+                .map(Date::from_days_since_epoch)
+                .collect()
+        }
+        None => dates.clone(),
+    };
+
+    Ok(GapFill::new(method, &dates, output_dates, inputs, None))
+}
+
+/// Fills nodata observations of a dated stack of index rasters per pixel,
+/// writing one band per output date
+pub struct GapFill {
+    method: FillMethod,
+    days: Vec<f64>,
+    output_dates: Vec<Date>,
+    output_days: Vec<f64>,
+    inputs: Vec<StackInput>,
+    name: String,
+}
+
+impl GapFill {
+    pub fn new(
+        method: FillMethod,
+        dates: &[Date],
+        output_dates: Vec<Date>,
+        inputs: Vec<StackInput>,
+        name: Option<String>,
+    ) -> Self {
+        let to_days = |d: &Date| d.days_since_epoch() as f64;
+        Self {
+            method,
+            days: dates.iter().map(to_days).collect(),
+            output_days: output_dates.iter().map(to_days).collect(),
+            output_dates,
+            inputs,
+            name: name.unwrap_or_else(|| "Gap-filled".to_string()),
+        }
+    }
+
+    /// Dates of the output bands
+    pub fn output_dates(&self) -> &[Date] {
+        &self.output_dates
+    }
+
+    /// Values of a time-sorted series at the output dates
+    fn fill(&self, series: &[(f64, f32)]) -> Option<Vec<f64>> {
+        match self.method {
+            FillMethod::Linear => Some(
+                self.output_days
+                    .iter()
+                    .map(|&t| interpolate(series, t))
+                    .collect(),
+            ),
+            FillMethod::Harmonic { harmonics } => {
+                let coefficients = harmonic_fit(series, harmonics)?;
+                Some(
+                    self.output_days
+                        .iter()
+                        .map(|&t| harmonic_value(&coefficients, t))
+                        .collect(),
+                )
+            }
+            FillMethod::Whittaker { lambda } => {
+                // Daily grid covering the observations and the output dates
+                let start = series[0].0.min(self.output_days.iter().copied().fold(f64::INFINITY, f64::min));
+                let end = series[series.len() - 1]
+                    .0
+                    .max(self.output_days.iter().copied().fold(f64::NEG_INFINITY, f64::max));
+                let len = (end - start).round() as usize + 1;
+
+                let mut values = vec![0.0; len];
+                let mut weights = vec![0.0; len];
+                for &(t, v) in series {
+                    let i = (t - start).round() as usize;
+                    // Average observations falling on the same day
+                    values[i] = (values[i] * weights[i] + v as f64) / (weights[i] + 1.0);
+                    weights[i] += 1.0;
+                }
+
+                let smoothed = whittaker(&values, &weights, lambda)?;
+                Some(
+                    self.output_days
+                        .iter()
+                        .map(|&t| smoothed[(t - start).round() as usize])
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    fn filled_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        let min_observations = self.method.min_observations();
+        timeseries::stack_bands(inputs, &self.inputs, &self.days, self.output_days.len(), |mut series, results| {
+            if series.len() < min_observations {
+                return;
+            }
+            series.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            if let Some(filled) = self.fill(&series) {
+                for (result, value) in results.iter_mut().zip(filled) {
+                    *result = value as f32;
+                }
+            }
+        })
+    }
+}
+
+impl IndexCalculator for GapFill {
+    fn calculate(&self, inputs: &[TypedBuffer]) -> TypedBuffer {
+        self.filled_bands(inputs).swap_remove(0)
+    }
+
+    fn required_bands(&self) -> usize {
+        self.days.len()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn applies_input_scaling(&self) -> bool {
+        true
+    }
+
+    fn output_bands(&self) -> Vec<String> {
+        self.output_dates.iter().map(Date::to_string).collect()
+    }
+
+    fn calculate_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        self.filled_bands(inputs)
+    }
+}
+
+/// Per-date output paths next to `output`, e.g. `ndvi.tif` -> `ndvi_2024-06-01.tif`.
+/// The dates in the file names are picked up by `timeseries` and `phenology`
+pub fn per_date_paths(output: &str, dates: &[Date]) -> Vec<String> {
+    let path = std::path::Path::new(output);
+    let stem = path.file_stem().map_or("output".into(), |s| s.to_string_lossy());
+    dates
+        .iter()
+        .map(|date| {
+            let file_name = match path.extension() {
+                Some(ext) => format!("{}_{}.{}", stem, date, ext.to_string_lossy()),
+                None => format!("{}_{}", stem, date),
+            };
+            path.with_file_name(file_name).to_string_lossy().to_string()
+        })
+        .collect()
+}
+
+fn harmonic_terms(t: f64, harmonics: usize) -> Vec<f64> {
+    let mut terms = vec![1.0];
+    for k in 1..=harmonics {
+        let angle = 2.0 * PI * k as f64 * t / YEAR_DAYS;
+        terms.push(angle.cos());
+        terms.push(angle.sin());
+    }
+    terms
+}
+
+/// Least squares coefficients of a mean plus `harmonics` annual harmonics (time in days)
+pub fn harmonic_fit(series: &[(f64, f32)], harmonics: usize) -> Option<Vec<f64>> {
+    let terms = 2 * harmonics + 1;
+    if series.len() < terms {
+        return None;
+    }
+
+    let mut normal = vec![vec![0.0; terms]; terms];
+    let mut rhs = vec![0.0; terms];
+    for &(t, v) in series {
+        let basis = harmonic_terms(t, harmonics);
+        for j in 0..terms {
+            rhs[j] += basis[j] * v as f64;
+            for k in 0..terms {
+                normal[j][k] += basis[j] * basis[k];
+            }
+        }
+    }
+    solve_linear(normal, rhs)
+}
+
+/// Value of a harmonic fit at time `t` (days)
+pub fn harmonic_value(coefficients: &[f64], t: f64) -> f64 {
+    let harmonics = (coefficients.len() - 1) / 2;
+    harmonic_terms(t, harmonics)
+        .iter()
+        .zip(coefficients)
+        .map(|(b, c)| b * c)
+        .sum()
+}
+
+/// Whittaker smoother: minimises Σ w (y - z)² + λ Σ (Δ²z)² over regularly spaced values.
+/// Zero weights mark gaps. The pentadiagonal system is solved by banded LDLᵀ
+pub fn whittaker(values: &[f64], weights: &[f64], lambda: f64) -> Option<Vec<f64>> {
+    let n = values.len();
+    if n < 3 {
+        return Some(values.to_vec());
+    }
+
+    // Bands of W + λ DᵀD: diagonal, first and second superdiagonal
+    let mut diagonal: Vec<f64> = weights.to_vec();
+    let mut upper1 = vec![0.0; n];
+    let mut upper2 = vec![0.0; n];
+    for r in 0..n - 2 {
+        diagonal[r] += lambda;
+        diagonal[r + 1] += 4.0 * lambda;
+        diagonal[r + 2] += lambda;
+        upper1[r] -= 2.0 * lambda;
+        upper1[r + 1] -= 2.0 * lambda;
+        upper2[r] += lambda;
+    }
+
+    // Factorise into unit lower L (bandwidth 2) and diagonal D
+    let mut d = vec![0.0; n];
+    let mut l1 = vec![0.0; n];
+    let mut l2 = vec![0.0; n];
+    for i in 0..n {
+        if i >= 2 {
+            l2[i] = upper2[i - 2] / d[i - 2];
+        }
+        if i >= 1 {
+            let coupling = if i >= 2 { l2[i] * d[i - 2] * l1[i - 1] } else { 0.0 };
+            l1[i] = (upper1[i - 1] - coupling) / d[i - 1];
+        }
+        d[i] = diagonal[i]
+            - if i >= 1 { l1[i] * l1[i] * d[i - 1] } else { 0.0 }
+            - if i >= 2 { l2[i] * l2[i] * d[i - 2] } else { 0.0 };
+        if d[i] <= 1e-12 * lambda.max(1.0) {
+            // Fewer than two distinct observations
+            return None;
+        }
+    }
+
+    // Forward substitution, scaling and back substitution
+    let mut z: Vec<f64> = values.iter().zip(weights).map(|(v, w)| v * w).collect();
+    for i in 1..n {
+        z[i] -= l1[i] * z[i - 1];
+        if i >= 2 {
+            z[i] -= l2[i] * z[i - 2];
+        }
+    }
+    for i in 0..n {
+        z[i] /= d[i];
+    }
+    for i in (0..n - 1).rev() {
+        z[i] -= l1[i + 1] * z[i + 1];
+        if i + 2 < n {
+            z[i] -= l2[i + 2] * z[i + 2];
+        }
+    }
+    Some(z)
+}
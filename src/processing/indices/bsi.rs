@@ -88,4 +88,8 @@ impl IndexCalculator for BSI {
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn formula(&self) -> String {
+        "((SWIR + RED) - (NIR + BLUE)) / ((SWIR + RED) + (NIR + BLUE))".to_string()
+    }
 }
\ No newline at end of file
@@ -100,6 +100,10 @@ impl IndexCalculator for EVI {
         &self.name
     }
 
+    fn formula(&self) -> String {
+        "2.5 * (NIR - RED) / (NIR + 6 * RED - 7.5 * BLUE + 1)".to_string()
+    }
+
     fn needs_input_scaling(&self) -> bool {
         true // EVI has constants (L=1.0, C1=6.0, C2=7.5) that require proper reflectance values
     }
@@ -1,4 +1,7 @@
 // src/processing/indices/mod.rs
+use crate::processing::parallel::IndexCalculator;
+use anyhow::Result;
+
 pub mod ndi;
 pub mod evi;
 pub mod savi;
@@ -16,4 +19,21 @@ pub use ndwi::NDWI;
 pub use ndsi::NDSI;
 pub use bsi::BSI;
 pub use msavi2::MSAVI2;
-pub use osavi::OSAVI;
\ No newline at end of file
+pub use osavi::OSAVI;
+
+/// Create a calculator by index name, reading its bands in the same order as the CLI subcommand
+/// (e.g. NIR, RED for savi; SWIR, RED, NIR, BLUE for bsi)
+pub fn from_name(name: &str, soil_factor: Option<f32>) -> Result<Box<dyn IndexCalculator>> {
+    let calculator: Box<dyn IndexCalculator> = match name.to_lowercase().as_str() {
+        "ndi" => Box::new(NDI::new(0, 1, None)),
+        "evi" => Box::new(EVI::new(0, 1, 2, None)),
+        "savi" => Box::new(SAVI::new(0, 1, soil_factor.unwrap_or(0.5), None)),
+        "ndwi" => Box::new(NDWI::new(0, 1, None)),
+        "ndsi" => Box::new(NDSI::new(0, 1, None)),
+        "bsi" => Box::new(BSI::new(0, 1, 2, 3, None)),
+        "msavi2" => Box::new(MSAVI2::new(0, 1, None)),
+        "osavi" => Box::new(OSAVI::new(0, 1, None)),
+        _ => return Err(anyhow::anyhow!("Unknown index type: {}", name)),
+    };
+    Ok(calculator)
+}
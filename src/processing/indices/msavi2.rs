@@ -80,6 +80,10 @@ impl IndexCalculator for MSAVI2 {
         &self.name
     }
 
+    fn formula(&self) -> String {
+        "(2 * NIR + 1 - sqrt((2 * NIR + 1)^2 - 8 * (NIR - RED))) / 2".to_string()
+    }
+
     fn needs_input_scaling(&self) -> bool {
         true // MSAVI2 has constants (1, 2, 8) that require proper reflectance values
     }
@@ -63,4 +63,8 @@ impl IndexCalculator for NDI {
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn formula(&self) -> String {
+        "(A - B) / (A + B)".to_string()
+    }
 }
\ No newline at end of file
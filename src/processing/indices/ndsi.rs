@@ -66,4 +66,8 @@ impl IndexCalculator for NDSI {
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn formula(&self) -> String {
+        "(GREEN - SWIR) / (GREEN + SWIR)".to_string()
+    }
 }
\ No newline at end of file
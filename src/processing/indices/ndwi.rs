@@ -63,4 +63,8 @@ impl IndexCalculator for NDWI {
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn formula(&self) -> String {
+        "(GREEN - NIR) / (GREEN + NIR)".to_string()
+    }
 }
\ No newline at end of file
@@ -73,6 +73,10 @@ impl IndexCalculator for OSAVI {
         &self.name
     }
 
+    fn formula(&self) -> String {
+        "(NIR - RED) / (NIR + RED + 0.16) * (1 + 0.16)".to_string()
+    }
+
     fn needs_input_scaling(&self) -> bool {
         true // OSAVI has constant L=0.16 that requires proper reflectance values
     }
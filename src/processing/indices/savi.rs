@@ -74,6 +74,14 @@ impl IndexCalculator for SAVI {
         &self.name
     }
 
+    fn formula(&self) -> String {
+        "(NIR - RED) / (NIR + RED + L) * (1 + L)".to_string()
+    }
+
+    fn parameters(&self) -> Vec<(String, String)> {
+        vec![("soil_factor".to_string(), self.soil_factor.to_string())]
+    }
+
     fn needs_input_scaling(&self) -> bool {
         true // SAVI has soil factor L (typically 0.5) that requires proper reflectance values
     }
@@ -0,0 +1,262 @@
+// src/processing/mask.rs
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use anyhow::{Context, Result};
+use gdal::Dataset;
+
+use crate::processing::parallel::NODATA_VALUE_FLOAT;
+use crate::utils::cache::RasterCache;
+use crate::utils::gdal_ext::TypedBuffer;
+use crate::utils::vrt::{SingleBandVrt, VrtSource};
+
+/// Values masked when none are given
+pub const DEFAULT_MASK_VALUES: [&str; 3] = ["cloud", "shadow", "snow"];
+
+/// How the mask band encodes what to mask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskType {
+    /// Sentinel-2 scene classification (SCL), one class per pixel
+    Scl,
+    /// Landsat Collection 2 QA_PIXEL bit flags
+    LandsatQa,
+    /// MODIS surface reflectance state QA (state_1km) bit flags
+    ModisQa,
+    /// Generic class values
+    Classes,
+    /// Generic bit flags
+    Bits,
+}
+
+impl MaskType {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "scl" => Ok(MaskType::Scl),
+            "qa_pixel" | "landsat" => Ok(MaskType::LandsatQa),
+            "modis" | "state_1km" => Ok(MaskType::ModisQa),
+            "classes" | "values" => Ok(MaskType::Classes),
+            "bits" => Ok(MaskType::Bits),
+            _ => Err(anyhow::anyhow!(
+                "Unknown mask type '{}'. Expected one of: scl, qa_pixel, modis, classes, bits",
+                name
+            )),
+        }
+    }
+
+    /// Guess the mask type from the band or file name (SCL, QA_PIXEL, state_1km)
+    pub fn from_input(input: &str) -> Result<Self> {
+        let upper = input.to_uppercase();
+        if upper.contains("SCL") {
+            Ok(MaskType::Scl)
+        } else if upper.contains("QA_PIXEL") {
+            Ok(MaskType::LandsatQa)
+        } else if upper.contains("STATE_1KM") {
+            Ok(MaskType::ModisQa)
+        } else {
+            Err(anyhow::anyhow!(
+                "Cannot tell the mask type of '{}', give it explicitly (scl, qa_pixel, modis, classes, bits)",
+                input
+            ))
+        }
+    }
+
+    fn is_bitmask(&self) -> bool {
+        matches!(self, MaskType::LandsatQa | MaskType::ModisQa | MaskType::Bits)
+    }
+
+    /// Classes or bits of a named value (cloud, shadow, snow, ...)
+    fn named(&self, name: &str) -> Option<&'static [u32]> {
+        let values: &[u32] = match (self, name) {
+            (MaskType::Scl, "nodata") => &[0, 1],
+            (MaskType::Scl, "dark") => &[2],
+            (MaskType::Scl, "shadow") => &[3],
+            (MaskType::Scl, "water") => &[6],
+            (MaskType::Scl, "unclassified") => &[7],
+            (MaskType::Scl, "cloud") => &[8, 9, 10],
+            (MaskType::Scl, "cirrus") => &[10],
+            (MaskType::Scl, "snow") => &[11],
+            (MaskType::LandsatQa, "nodata" | "fill") => &[0],
+            (MaskType::LandsatQa, "cloud") => &[1, 2, 3],
+            (MaskType::LandsatQa, "cirrus") => &[2],
+            (MaskType::LandsatQa, "shadow") => &[4],
+            (MaskType::LandsatQa, "snow") => &[5],
+            (MaskType::LandsatQa, "water") => &[7],
+            (MaskType::ModisQa, "cloud") => &[10, 13],
+            (MaskType::ModisQa, "shadow") => &[2],
+            (MaskType::ModisQa, "cirrus") => &[8, 9],
+            (MaskType::ModisQa, "snow") => &[12, 15],
+            _ => return None,
+        };
+        Some(values)
+    }
+
+    /// Multi-bit fields and the values of a named value: (first bit, width, value)
+    fn named_fields(&self, name: &str) -> &'static [BitField] {
+        match (self, name) {
+            // Cloud state (bits 0-1): cloudy or mixed, not clear or assumed clear
+            (MaskType::ModisQa, "cloud") => &[
+                BitField { shift: 0, width: 2, value: 1 },
+                BitField { shift: 0, width: 2, value: 2 },
+            ],
+            _ => &[],
+        }
+    }
+}
+
+/// Value of a field of several bits that flags a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitField {
+    pub shift: u32,
+    pub width: u32,
+    pub value: u32,
+}
+
+impl BitField {
+    fn matches(&self, raw: u32) -> bool {
+        (raw >> self.shift) & ((1 << self.width) - 1) == self.value
+    }
+}
+
+/// Masks pixels of every output where a classification or QA band flags clouds, shadows, snow...
+#[derive(Debug, Clone)]
+pub struct Mask {
+    /// Mask band, a path or a Sentinel-2/Landsat band reference (e.g. `SCL`)
+    pub input: String,
+    pub mask_type: MaskType,
+    /// Class values, or bit positions for bit flag types
+    pub values: Vec<u32>,
+    /// Multi-bit fields of bit flag types, e.g. the MODIS cloud state
+    pub fields: Vec<BitField>,
+}
+
+impl Mask {
+    /// `values` are names (cloud, shadow, snow, cirrus, water, nodata) or numbers (classes or
+    /// bit positions), the type is guessed from the input name when `mask_type` is `None`
+    pub fn new(input: &str, mask_type: Option<&str>, values: &[String]) -> Result<Self> {
+        let mask_type = match mask_type {
+            Some(name) if !name.eq_ignore_ascii_case("auto") => MaskType::from_name(name)?,
+            _ => MaskType::from_input(input)?,
+        };
+
+        let names: Vec<String> = if values.is_empty() {
+            DEFAULT_MASK_VALUES.iter().map(|v| v.to_string()).collect()
+        } else {
+            values.to_vec()
+        };
+
+        let mut masked = Vec::new();
+        let mut fields = Vec::new();
+        for name in &names {
+            let name = name.trim().to_lowercase();
+            if let Ok(value) = name.parse::<u32>() {
+                masked.push(value);
+            } else if let Some(named) = mask_type.named(&name) {
+                masked.extend_from_slice(named);
+                fields.extend_from_slice(mask_type.named_fields(&name));
+            } else {
+                return Err(anyhow::anyhow!(
+                    "Unknown mask value '{}' for {:?} masks, use a number or one of: \
+                     cloud, shadow, snow, cirrus, water, nodata",
+                    name,
+                    mask_type
+                ));
+            }
+        }
+        if mask_type.is_bitmask() && masked.iter().any(|bit| *bit >= 32) {
+            return Err(anyhow::anyhow!("Mask bit positions must be below 32"));
+        }
+        masked.sort_unstable();
+        masked.dedup();
+
+        Ok(Self {
+            input: input.to_string(),
+            mask_type,
+            values: masked,
+            fields,
+        })
+    }
+
+    /// True if the raw mask value flags the pixel
+    pub fn is_masked(&self, raw: f32) -> bool {
+        if raw.is_nan() || raw < 0.0 {
+            return false;
+        }
+        let value = raw as u32;
+        if self.mask_type.is_bitmask() {
+            self.values.iter().any(|bit| value & (1 << bit) != 0)
+                || self.fields.iter().any(|field| field.matches(value))
+        } else {
+            self.values.contains(&value)
+        }
+    }
+
+    /// Set every result band to nodata where the mask block flags the pixel
+    pub fn apply(&self, mask: &TypedBuffer, results: &mut [TypedBuffer]) {
+        let Some(mask) = mask.as_f32() else {
+            return;
+        };
+        let masked: Vec<bool> = mask.data().iter().map(|&raw| self.is_masked(raw)).collect();
+
+        for result in results {
+            if let TypedBuffer::F32(buffer) = result {
+                for (value, &masked) in buffer.data_mut().iter_mut().zip(&masked) {
+                    if masked {
+                        *value = NODATA_VALUE_FLOAT;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// VRT in the run's `/vsimem/` directory showing `path` on the pixel grid of `reference` (nearest
+/// neighbour), e.g. a 20 m SCL band under a 10 m index. Both must share the projection.
+pub fn resample_to_grid(path: &str, reference: &str, cache: &RasterCache) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    (path, reference).hash(&mut hasher);
+    let key = hasher.finish();
+
+    cache.build_vrt(key, &format!("mask_{:016x}", key), || {
+        let source = Dataset::open(path).with_context(|| format!("Failed to open mask {}", path))?;
+        let target = Dataset::open(reference)?;
+        let src_gt = source.geo_transform()?;
+        let gt = target.geo_transform()?;
+        let (src_width, src_height) = source.raster_size();
+
+        // Footprint of the mask in pixels of the reference grid
+        let dst_rect = (
+            (src_gt[0] - gt[0]) / gt[1],
+            (src_gt[3] - gt[3]) / gt[5],
+            src_width as f64 * src_gt[1] / gt[1],
+            src_height as f64 * src_gt[5] / gt[5],
+        );
+
+        let vrt = SingleBandVrt {
+            size: target.raster_size(),
+            projection: target.projection(),
+            geo_transform: gt,
+            data_type: source.rasterband(1)?.band_type().name(),
+            nodata: None,
+            pixel_function: None,
+            sources: vec![VrtSource {
+                path: path.to_string(),
+                size: (src_width, src_height),
+                dst_rect,
+                nodata: None,
+                resampling: Some("nearest".to_string()),
+            }],
+        };
+        Ok(vrt.to_xml())
+    })
+}
+
+/// Mask values given as a comma-separated list
+pub fn parse_values(values: &str) -> Vec<String> {
+    values
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
@@ -1,6 +1,15 @@
 // src/processing/mod.rs
 pub mod parallel;
 pub mod indices;
+pub mod composite;
+pub mod timeseries;
+pub mod classify;
+pub mod change;
+pub mod phenology;
+pub mod gapfill;
+pub mod render;
+pub mod mask;
+pub mod scheduler;
 
 // Re-export main components
-pub use parallel::{ParallelProcessor};
\ No newline at end of file
+pub use parallel::{ParallelProcessor};
@@ -8,22 +8,75 @@ use std::{
     num::NonZero,
     ops::DerefMut,
     panic,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread::{self, JoinHandle},
+    time::Instant,
 };
 
 use anyhow::Result;
 use flume::{Receiver, Sender};
 use gdal::{
     raster::{Buffer, RasterCreationOptions},
-    Dataset, DriverManager, DriverType,
+    Dataset,
 };
 use parking_lot::Mutex;
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator as _, ParallelIterator as _};
 
+use crate::processing::classify::ClassOutput;
+use crate::processing::mask::{self, Mask};
+use crate::processing::render::RenderOutput;
+use crate::processing::scheduler::{MemoryBudget, Scheduler};
 use crate::utils::gdal_ext::TypedBuffer;
+use crate::utils::memory::{self, MemoryShares};
+use crate::utils::mosaic::{self, OverlapRule};
+use crate::utils::output::OutputFormat;
+use crate::utils::product;
+use crate::utils::provenance::Provenance;
+use crate::utils::report::ProcessStats;
+use crate::utils::stac;
+use crate::utils::scaling::{self, InputScaling};
+
+/// Nodata value of fixed-point (int16) outputs
+pub const NODATA_VALUE_INT: i16 = -10000;
+/// Nodata value of float outputs and of calculator results
+pub const NODATA_VALUE_FLOAT: f32 = -999.0;
+/// Rasters at most this wide and high are processed whole, whatever the memory budget
+pub const SMALL_RASTER_SIDE: usize = 512;
 
 type BlockReadHandler = Box<dyn Fn(usize, usize, HashMap<usize, TypedBuffer>) + Send + Sync>;
+type BlockMessage = (usize, usize, Option<HashMap<usize, TypedBuffer>>);
+
+/// Sends the blocks of one position to the processing loop, or `None` once dropped without
+/// them because a read failed, so the loop never waits for a block that will not come
+struct BlockSender {
+    tx: Sender<BlockMessage>,
+    position: (usize, usize),
+    sent: AtomicBool,
+}
+
+impl BlockSender {
+    fn new(tx: Sender<BlockMessage>, position: (usize, usize)) -> Self {
+        Self { tx, position, sent: AtomicBool::new(false) }
+    }
+
+    fn send(&self, blocks: HashMap<usize, TypedBuffer>) {
+        if !self.sent.swap(true, Ordering::SeqCst) {
+            // The loop is gone if the operation already failed
+            let _ = self.tx.send((self.position.0, self.position.1, Some(blocks)));
+        }
+    }
+}
+
+impl Drop for BlockSender {
+    fn drop(&mut self) {
+        if !self.sent.swap(true, Ordering::SeqCst) {
+            let _ = self.tx.send((self.position.0, self.position.1, None));
+        }
+    }
+}
 
 struct BlockReadRequest {
     datasets: Arc<Vec<Box<[Arc<Mutex<Dataset>>]>>>,
@@ -44,6 +97,21 @@ struct BlockReadState {
 pub struct ParallelProcessor {
     io_threads: usize,
     cache: Option<Arc<RasterCache>>, // Make cache optional
+    scratch: Arc<RasterCache>,       // Products, STAC items and VRTs of the run (the cache if given)
+    output_format: Option<OutputFormat>, // Guessed from the output path when None
+    mosaic_rule: OverlapRule,            // Used for tile list / glob inputs
+    class_output: Option<ClassOutput>,   // Optional UInt8 class map written alongside
+    render_output: Option<RenderOutput>, // Optional RGBA rendering written alongside
+    index_output: bool,                  // False to only write the class map / rendering
+    product: Option<String>,             // Sentinel-2 product bare band names refer to
+    resolution: Option<u32>,             // Resolution (m) of Sentinel-2 bands
+    auto_scale: bool,                    // Detect per-input scale/offset from metadata
+    mask: Option<Mask>,                  // Cloud/shadow/snow mask applied to every output
+    stac_item: bool,                     // Write a STAC item next to the output
+    stats: Option<Arc<ProcessStats>>,    // Timings and output statistics for run reports
+    scheduler: Option<Arc<Scheduler>>,   // Block readers and memory shared with other processors
+    max_block_bytes: Option<u64>,        // Working set of one block, native blocks are split to fit
+    inflight: Option<Arc<MemoryBudget>>, // Read-ahead limit without a scheduler
 }
 
 impl ParallelProcessor {
@@ -58,6 +126,21 @@ impl ParallelProcessor {
         Self {
             io_threads,
             cache: None,
+            scratch: Arc::new(RasterCache::new()),
+            output_format: None,
+            mosaic_rule: OverlapRule::default(),
+            class_output: None,
+            render_output: None,
+            index_output: true,
+            product: None,
+            resolution: None,
+            auto_scale: true,
+            mask: None,
+            stac_item: false,
+            stats: None,
+            scheduler: None,
+            max_block_bytes: None,
+            inflight: None,
         }
     }
     pub fn cache_size(&self) -> usize {
@@ -85,10 +168,114 @@ impl ParallelProcessor {
 
         Self {
             io_threads,
+            scratch: Arc::clone(&cache),
             cache: Some(cache),
+            output_format: None,
+            mosaic_rule: OverlapRule::default(),
+            class_output: None,
+            render_output: None,
+            index_output: true,
+            product: None,
+            resolution: None,
+            auto_scale: true,
+            mask: None,
+            stac_item: false,
+            stats: None,
+            scheduler: None,
+            max_block_bytes: None,
+            inflight: None,
+        }
+    }
+
+    /// Select the output format explicitly instead of guessing it from the output extension
+    pub fn with_output_format(mut self, output_format: Option<OutputFormat>) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Select how overlapping tiles are combined when an input is a tile list or glob pattern
+    pub fn with_mosaic_rule(mut self, mosaic_rule: OverlapRule) -> Self {
+        self.mosaic_rule = mosaic_rule;
+        self
+    }
+
+    /// Also classify one result band into a UInt8 class map, without an intermediate file
+    pub fn with_classification(mut self, class_output: Option<ClassOutput>) -> Self {
+        self.class_output = class_output;
+        self
+    }
+
+    /// Also render one result band through a colormap into an RGBA GeoTIFF (and quicklook)
+    pub fn with_render(mut self, render_output: Option<RenderOutput>) -> Self {
+        self.render_output = render_output;
+        self
+    }
+
+    /// Resolve bare band names (`B04`, `SCL`) against a Sentinel-2 product, at `resolution` metres
+    pub fn with_product(mut self, product: Option<String>, resolution: Option<u32>) -> Self {
+        self.product = product;
+        self.resolution = resolution;
+        self
+    }
+
+    /// Detect the scale and offset of each input (band metadata, Sentinel-2 or Landsat
+    /// product metadata) and apply them before calculating. On by default.
+    pub fn with_auto_scaling(mut self, auto_scale: bool) -> Self {
+        self.auto_scale = auto_scale;
+        self
+    }
+
+    /// Set every output to nodata where a SCL or QA band flags the pixel
+    pub fn with_mask(mut self, mask: Option<Mask>) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    /// Also write a STAC item (`<output>.json`) describing the output and its provenance
+    pub fn with_stac_item(mut self, stac_item: bool) -> Self {
+        self.stac_item = stac_item;
+        self
+    }
+
+    /// Record read/compute/write time, bytes read and result statistics in `stats`
+    pub fn with_stats(mut self, stats: Option<Arc<ProcessStats>>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Read blocks on the shared readers of `scheduler`, within its in-flight memory budget,
+    /// instead of readers of this processor's own (requires a cache)
+    pub fn with_scheduler(mut self, scheduler: Option<Arc<Scheduler>>) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Split native blocks so the inputs, results and output conversion of one block fit in `bytes`
+    pub fn with_max_block_bytes(mut self, bytes: Option<u64>) -> Self {
+        self.max_block_bytes = bytes;
+        self
+    }
+
+    /// Keep blocks and read-ahead within `bytes` (GDAL's share is sized by `memory::set_gdal_cache`)
+    pub fn with_max_memory(mut self, bytes: Option<u64>) -> Self {
+        let shares = bytes.map(MemoryShares::split);
+        self.max_block_bytes = shares.map(|shares| shares.block_limit(1));
+        self.inflight = shares.map(|shares| Arc::new(MemoryBudget::new(shares.inflight)));
+        self
+    }
+
+    fn record(&self, update: impl FnOnce(&ProcessStats)) {
+        if let Some(stats) = &self.stats {
+            update(stats);
         }
     }
 
+    /// Skip writing the calculator result itself, e.g. when only the class map is wanted
+    pub fn with_index_output(mut self, index_output: bool) -> Self {
+        self.index_output = index_output;
+        self
+    }
+
     pub fn process<I: IndexCalculator>(
         &self,
         calculator: I,
@@ -101,6 +288,13 @@ impl ParallelProcessor {
         compress_level: u8,
         tiled: bool,
     ) -> Result<()> {
+        // Resolve Sentinel-2 band names, expand tile lists and glob patterns into virtual mosaics
+        let qualified_paths: Vec<String> = input_paths
+            .iter()
+            .map(|input| product::qualify_input(input, self.product.as_deref(), self.resolution, &self.scratch))
+            .collect::<Result<_>>()?;
+        let input_paths = &mosaic::resolve_inputs(&qualified_paths, self.mosaic_rule, &self.scratch)?;
+
         if input_paths.len() < calculator.required_bands() {
             return Err(anyhow::anyhow!(
                 "Not enough input bands provided. Required: {}, provided: {}",
@@ -109,19 +303,77 @@ impl ParallelProcessor {
             ));
         }
 
+        if !self.index_output && self.class_output.is_none() && self.render_output.is_none() {
+            return Err(anyhow::anyhow!(
+                "Nothing to write: no index output, class output or rendering"
+            ));
+        }
+        if let Some(class_output) = &self.class_output {
+            if class_output.band >= calculator.output_bands().len() {
+                return Err(anyhow::anyhow!(
+                    "Cannot classify band {} of {}, it only has {} output bands",
+                    class_output.band + 1,
+                    calculator.name(),
+                    calculator.output_bands().len()
+                ));
+            }
+        }
+        if let Some(render_output) = &self.render_output {
+            if render_output.band >= calculator.output_bands().len() {
+                return Err(anyhow::anyhow!(
+                    "Cannot render band {} of {}, it only has {} output bands",
+                    render_output.band + 1,
+                    calculator.name(),
+                    calculator.output_bands().len()
+                ));
+            }
+        }
+
+        // Per-input scale/offset; the global input scale factor only applies to inputs without one
+        let scalings = self.detect_scalings(&calculator, &qualified_paths, input_paths)?;
+        let fallback_divisor = (calculator.needs_input_scaling()
+            && (input_scale_factor - 1.0).abs() > f32::EPSILON)
+            .then_some(input_scale_factor);
+
+        // Provenance recorded in the output metadata (and STAC item)
+        let provenance = if self.index_output {
+            Some(self.provenance(
+                &calculator,
+                &qualified_paths,
+                input_paths,
+                &scalings,
+                use_fixed_point,
+                scale_factor,
+                input_scale_factor,
+                compress,
+            )?)
+        } else {
+            None
+        };
+
+        // The mask band is read as one more input, on the grid of the first one
+        let mut read_paths = input_paths.clone();
+        if let Some(mask) = &self.mask {
+            let input = product::qualify_input(&mask.input, self.product.as_deref(), self.resolution, &self.scratch)?;
+            let path = mosaic::resolve_input(&input, self.mosaic_rule, &self.scratch)?;
+            read_paths.push(mask::resample_to_grid(&path, &input_paths[0], &self.scratch)?);
+        }
+
         // Get input raster dimensions from the first file
         let dataset = Dataset::open(&input_paths[0])?;
         let (width, height) = dataset.raster_size();
 
         // For small test rasters (like in our tests), use a simple single-threaded approach
-        if width <= 512 && height <= 512 {
+        if width <= SMALL_RASTER_SIDE && height <= SMALL_RASTER_SIDE {
             return self.process_small_raster(
                 calculator,
-                input_paths,
+                &read_paths,
                 output_path,
                 use_fixed_point,
                 scale_factor,
-                input_scale_factor,
+                &scalings,
+                fallback_divisor,
+                provenance.as_ref(),
                 width,
                 height,
                 compress,
@@ -132,146 +384,159 @@ impl ParallelProcessor {
 
         // For larger images, use the parallel block reader
         // Create block reader with cache if available
-        let block_reader = if let Some(cache) = &self.cache {
-            ParallelBlockReader::with_cache(input_paths, self.io_threads, Arc::clone(cache))
-                .map_err(|e| anyhow::anyhow!("Failed to create block reader: {}", e))?
-        } else {
-            ParallelBlockReader::new(input_paths, self.io_threads)?
-        };
-
-        let driver =
-            DriverManager::get_output_driver_for_dataset_name(output_path, DriverType::Raster)
-                .expect("unknown output format");
-
-        // Create options vector
-        let mut options = Vec::new();
-
-        // Add compression if not NONE
-        if compress.to_uppercase() != "NONE" {
-            options.push(format!("COMPRESS={}", compress.to_uppercase()));
-
-            // Add compression level for supported algorithms
-            match compress.to_uppercase().as_str() {
-                "DEFLATE" => options.push(format!("ZLEVEL={}", compress_level.min(9))),
-                "ZSTD" => options.push(format!("ZSTD_LEVEL={}", compress_level.min(22))),
-                _ => {}
+        let mut block_reader = match (&self.cache, &self.scheduler) {
+            (Some(cache), Some(scheduler)) => {
+                ParallelBlockReader::with_scheduler(&read_paths, Arc::clone(cache), Arc::clone(scheduler))
+                    .map_err(|e| anyhow::anyhow!("Failed to create block reader: {}", e))?
             }
-        }
+            (Some(cache), None) => {
+                ParallelBlockReader::with_cache(&read_paths, self.io_threads, Arc::clone(cache))
+                    .map_err(|e| anyhow::anyhow!("Failed to create block reader: {}", e))?
+            }
+            (None, _) => ParallelBlockReader::new(&read_paths, self.io_threads)?,
+        };
 
-        // Add tiling if enabled
-        if tiled {
-            options.push("TILED=YES".to_string());
+        // Split native blocks that do not fit in the memory budget
+        if let Some(max_bytes) = self.max_block_bytes {
+            let bytes_per_pixel = memory::block_bytes_per_pixel(read_paths.len(), calculator.output_bands().len());
+            let region = memory::fit_region(block_reader.region_size, bytes_per_pixel, max_bytes).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Memory budget too small for {}: a {}x{} block needs {}, {} are available per block",
+                    output_path,
+                    memory::MIN_BLOCK_SIDE,
+                    memory::MIN_BLOCK_SIDE,
+                    memory::format_bytes((memory::MIN_BLOCK_SIDE * memory::MIN_BLOCK_SIDE) as u64 * bytes_per_pixel),
+                    memory::format_bytes(max_bytes)
+                )
+            })?;
+            block_reader.set_region_size(region);
         }
 
-        // Always use multi-threading
-        options.push("NUM_THREADS=ALL_CPUS".to_string());
-
-        let creation_options = RasterCreationOptions::from_iter(options);
-
         // Create output dataset with appropriate type
-        let mut output = if use_fixed_point {
-            driver.create_with_band_type_with_options::<i16, _>(
-                output_path,
-                width,
-                height,
-                1,
-                &creation_options,
-            )?
-        } else {
-            driver.create_with_band_type_with_options::<f32, _>(
+        let output = if self.index_output {
+            let mut output = self.create_output(
                 output_path,
                 width,
                 height,
-                1,
-                &creation_options,
-            )?
-        };
-
-        // Define constants for fixed-point conversion
-        const NODATA_VALUE_INT: i16 = -10000;
-        const NODATA_VALUE_FLOAT: f32 = -999.0;
-
-        // Set up output dataset properties
-        output.set_projection(&dataset.projection())?;
-        output.set_geo_transform(&dataset.geo_transform()?.try_into().unwrap())?;
-
-        let mut output_band = output.rasterband(1)?;
-        if use_fixed_point {
-            output_band.set_no_data_value(Some(NODATA_VALUE_INT as f64))?;
-            output_band.set_metadata_item(
-                "SCALE",
-                &format!("{}", 1.0 / scale_factor as f64),
-                "",
+                calculator.output_bands().len(),
+                use_fixed_point,
+                compress,
+                compress_level,
+                tiled,
             )?;
-            output_band.set_metadata_item("OFFSET", "0", "")?;
-            output_band.set_description(&format!(
-                "{} (scaled by {})",
-                calculator.name(),
-                scale_factor
-            ))?;
+
+            // Set up output dataset properties
+            output.set_projection(&dataset.projection())?;
+            output.set_geo_transform(&dataset.geo_transform()?.try_into().unwrap())?;
+            setup_output_bands(&output, &calculator, use_fixed_point, scale_factor)?;
+            if let Some(provenance) = &provenance {
+                provenance.write_metadata(&mut output)?;
+            }
+            Some(output)
         } else {
-            output_band.set_no_data_value(Some(NODATA_VALUE_FLOAT as f64))?;
-            output_band.set_description(calculator.name())?;
-        }
+            None
+        };
+        let class_dataset = self.create_class_output(
+            &dataset,
+            &calculator,
+            width,
+            height,
+            compress,
+            compress_level,
+            tiled,
+        )?;
+        let render_dataset = self.create_render_output(
+            &dataset,
+            &calculator,
+            width,
+            height,
+            compress,
+            compress_level,
+            tiled,
+        )?;
 
         // Set up processing pipeline
         let (tx, rx) = flume::unbounded();
-        let dataset_indices = (0..input_paths.len()).collect::<Vec<_>>();
+        let dataset_indices = (0..read_paths.len()).collect::<Vec<_>>();
+        let (blocks_x, blocks_y) = block_reader.blocks;
+        let total = blocks_x * blocks_y;
+
+        // Blocks read ahead are bounded by the scheduler's or the processor's memory budget
+        let budget = match &self.scheduler {
+            Some(scheduler) => Some(Arc::clone(scheduler.budget())),
+            None => self.inflight.clone(),
+        };
+        let region_size = block_reader.region_size;
+        let block_bytes = (region_size.0 * region_size.1 * mem::size_of::<f32>() * read_paths.len()) as u64;
+        let mut reservations = HashMap::new();
+        let mut requested = 0;
+        let mut received = 0;
 
-        // Request processing of each block
-        for y in 0..block_reader.blocks.1 {
-            for x in 0..block_reader.blocks.0 {
-                let tx = tx.clone();
-                block_reader.run(
-                    x,
-                    y,
-                    &dataset_indices,
-                    Box::new(move |x, y, blocks| {
-                        tx.send((x, y, blocks)).unwrap();
-                    }),
-                );
+        // Process blocks as they become available
+        let band_names = calculator.output_bands();
+        let mut waiting = Instant::now();
+        while received < total {
+            // Request blocks while the budget allows, always at least one
+            while requested < total {
+                let reservation = match &budget {
+                    Some(budget) if requested == received => Some(budget.reserve(block_bytes)),
+                    Some(budget) => match budget.try_reserve(block_bytes) {
+                        Some(reservation) => Some(reservation),
+                        None => break,
+                    },
+                    None => None,
+                };
+                let (x, y) = (requested % blocks_x, requested / blocks_x);
+                reservations.insert((x, y), reservation);
+                let sender = BlockSender::new(tx.clone(), (x, y));
+                block_reader.run(x, y, &dataset_indices, Box::new(move |_, _, blocks| sender.send(blocks)));
+                requested += 1;
             }
-        }
-        drop(tx);
 
-        // Process blocks as they become available
-        for (x, y, blocks) in rx {
+            // Senders are kept, a failed read arrives as `None` instead of closing the channel
+            let (x, y, blocks) = rx.recv()?;
+            received += 1;
+            let _reservation = reservations.remove(&(x, y));
+            self.record(|stats| stats.add_read(waiting.elapsed()));
+            // An IO error, so the retry policy treats it as transient like the read that failed
+            let Some(blocks) = blocks else {
+                return Err(std::io::Error::other(format!(
+                    "Failed to read block ({}, {}) of {}",
+                    x,
+                    y,
+                    read_paths.join(", ")
+                ))
+                .into());
+            };
             // Skip empty blocks (could happen at edges)
             if blocks
                 .values()
                 .any(|block| block.shape().0 == 0 || block.shape().1 == 0)
             {
+                waiting = Instant::now();
                 continue;
             }
 
             // Convert blocks to a vector in the expected order
+            let computing = Instant::now();
             let mut inputs = Vec::with_capacity(blocks.len());
             for i in 0..blocks.len() {
                 inputs.push(blocks[&i].clone());
             }
-            // Apply input scaling if the calculator needs it and scaling factor is not 1.0
-            let needs_scaling =
-                calculator.needs_input_scaling() && (input_scale_factor - 1.0).abs() > f32::EPSILON;
-
-            // println!("🔍 DEBUG: Calculator={}, needs_input_scaling={}, input_scale_factor={}, needs_scaling={}", 
-            //  calculator.name(), calculator.needs_input_scaling(), input_scale_factor, needs_scaling);
-
-             if needs_scaling {
-                for buffer in &mut inputs {
-                    if let TypedBuffer::F32(ref mut buf) = buffer {
-                        // println!("BEFORE scaling: First 3 values: {:?}", &buf.data()[0..3.min(buf.data().len())]);
-                        for value in buf.data_mut() {
-                            *value /= input_scale_factor;
-                        }
-                        // println!("AFTER scaling: First 3 values: {:?}", &buf.data()[0..3.min(buf.data().len())]);
-                    }
-                }
-            }
-            // Calculate the index using the provided calculator
-            let result = calculator.calculate(&inputs);
+            self.record(|stats| stats.add_inputs(&inputs));
+            let mask_block = self.mask.as_ref().and_then(|_| inputs.pop());
+            // Apply the scale/offset of each input
+            scaling::scale_inputs(&mut inputs, &scalings, fallback_divisor);
 
-            // Get the actual shape of the result
-            let result_shape = result.shape();
+            // Calculate the index using the provided calculator
+            let mut results = calculator.calculate_bands(&inputs);
+            if let (Some(mask), Some(mask_block)) = (&self.mask, &mask_block) {
+                mask.apply(mask_block, &mut results);
+            }
+            self.record(|stats| {
+                stats.add_results(&band_names, &results);
+                stats.add_compute(computing.elapsed());
+            });
 
             // Calculate actual pixel coordinates
             let start_x = x as isize * block_reader.region_size.0 as isize;
@@ -279,40 +544,43 @@ impl ParallelProcessor {
 
             // Skip if we'd be writing out of bounds
             if start_x >= width as isize || start_y >= height as isize {
+                waiting = Instant::now();
                 continue;
             }
 
-            // Prepare output data
-            if use_fixed_point {
-                // Convert float result to fixed-point
-                let result_data = result.as_f32().unwrap();
-                let mut buffer_data = vec![0i16; result_data.data().len()];
-
-                // Apply scaling factor for fixed-point conversion
-                for (dst, &src) in buffer_data.iter_mut().zip(result_data.data()) {
-                    *dst = if src == NODATA_VALUE_FLOAT {
-                        NODATA_VALUE_INT
-                    } else {
-                        (src.max(-0.9999).min(0.9999) * scale_factor as f32).round() as i16
-                    };
-                }
-
-                let mut buffer = Buffer::new(result_shape, buffer_data);
-
-                // Write to output
-                output_band.write((start_x, start_y), result_shape, &mut buffer)?;
-            } else {
-                // Use float result directly
-                let result_data = result.as_f32().unwrap();
-
-                // Write directly to output
-                let mut buffer = Buffer::new(result_shape, result_data.data().to_vec());
-                output_band.write((start_x, start_y), result_shape, &mut buffer)?;
+            let writing = Instant::now();
+            if let Some(output) = &output {
+                write_output_bands(
+                    output,
+                    &calculator,
+                    &results,
+                    (start_x, start_y),
+                    use_fixed_point,
+                    scale_factor,
+                )?;
+            }
+            if let (Some(class_output), Some(class_dataset)) = (&self.class_output, &class_dataset) {
+                write_class_band(class_dataset, class_output, &results, (start_x, start_y))?;
+            }
+            if let (Some(render_output), Some(render_dataset)) = (&self.render_output, &render_dataset) {
+                write_render_block(render_dataset, render_output, &results, (start_x, start_y))?;
             }
+            self.record(|stats| stats.add_write(writing.elapsed()));
+            waiting = Instant::now();
         }
 
         // Finish processing
         block_reader.join();
+
+        // The quicklook and STAC item are read back from the closed outputs
+        let writing = Instant::now();
+        drop(render_dataset);
+        if let Some(render_output) = &self.render_output {
+            render_output.write_quicklook(&self.scratch)?;
+        }
+        drop(output);
+        self.write_stac_item(output_path, provenance.as_ref())?;
+        self.record(|stats| stats.add_write(writing.elapsed()));
         Ok(())
     }
 
@@ -324,18 +592,17 @@ impl ParallelProcessor {
         output_path: &str,
         use_fixed_point: bool,
         scale_factor: i32,
-        input_scale_factor: f32, // NEW PARAMETER
+        scalings: &[InputScaling],
+        fallback_divisor: Option<f32>,
+        provenance: Option<&Provenance>,
         width: usize,
         height: usize,
         compress: &str,
         compress_level: u8,
         tiled: bool,
     ) -> Result<()> {
-        // Define constants for fixed-point conversion
-        const NODATA_VALUE_INT: i16 = -10000;
-        const NODATA_VALUE_FLOAT: f32 = -999.0;
-
         // Read all input rasters into memory
+        let reading = Instant::now();
         let mut inputs = Vec::with_capacity(input_paths.len());
         for path in input_paths {
             let dataset = Dataset::open(path)?;
@@ -343,67 +610,210 @@ impl ParallelProcessor {
             let buffer = band.read_as::<f32>((0, 0), (width, height), (width, height), None)?;
             inputs.push(TypedBuffer::F32(buffer));
         }
+        self.record(|stats| {
+            stats.add_read(reading.elapsed());
+            stats.add_inputs(&inputs);
+        });
+        let mask_block = self.mask.as_ref().and_then(|_| inputs.pop());
 
-        // Apply input scaling if the calculator needs it and scaling factor is not 1.0
-        let needs_scaling =
-            calculator.needs_input_scaling() && (input_scale_factor - 1.0).abs() > f32::EPSILON;
+        // Apply the scale/offset of each input
+        let computing = Instant::now();
+        scaling::scale_inputs(&mut inputs, scalings, fallback_divisor);
 
-        if needs_scaling {
-            for buffer in &mut inputs {
-                if let TypedBuffer::F32(ref mut buf) = buffer {
-                    // println!(
-                    //     "BEFORE scaling: First 3 values: {:?}",
-                    //     &buf.data()[0..3.min(buf.data().len())]
-                    // );
-                    for value in buf.data_mut() {
-                        *value /= input_scale_factor;
-                    }
-                    // println!(
-                    //     "AFTER scaling: First 3 values: {:?}",
-                    //     &buf.data()[0..3.min(buf.data().len())]
-                    // );
-                }
+        // Calculate the index and mask it
+        let mut results = calculator.calculate_bands(&inputs);
+        if let (Some(mask), Some(mask_block)) = (&self.mask, &mask_block) {
+            mask.apply(mask_block, &mut results);
+        }
+        self.record(|stats| {
+            stats.add_results(&calculator.output_bands(), &results);
+            stats.add_compute(computing.elapsed());
+        });
+
+        let writing = Instant::now();
+        let dataset = Dataset::open(&input_paths[0])?;
+        if self.index_output {
+            // Create output dataset
+            let mut output = self.create_output(
+                output_path,
+                width,
+                height,
+                calculator.output_bands().len(),
+                use_fixed_point,
+                compress,
+                compress_level,
+                tiled,
+            )?;
+
+            // Copy geospatial metadata
+            output.set_projection(&dataset.projection())?;
+            output.set_geo_transform(&dataset.geo_transform()?.try_into().unwrap())?;
+
+            // Set up band metadata and write the result
+            setup_output_bands(&output, &calculator, use_fixed_point, scale_factor)?;
+            if let Some(provenance) = provenance {
+                provenance.write_metadata(&mut output)?;
             }
+            write_output_bands(
+                &output,
+                &calculator,
+                &results,
+                (0, 0),
+                use_fixed_point,
+                scale_factor,
+            )?;
         }
-        // Calculate the index
-        let result = calculator.calculate(&inputs);
 
-        // Create output dataset
-        let driver =
-            DriverManager::get_output_driver_for_dataset_name(output_path, DriverType::Raster)
-                .expect("unknown output format");
+        // Classify the result if requested
+        let class_dataset = self.create_class_output(
+            &dataset,
+            &calculator,
+            width,
+            height,
+            compress,
+            compress_level,
+            tiled,
+        )?;
+        if let (Some(class_output), Some(class_dataset)) = (&self.class_output, &class_dataset) {
+            write_class_band(class_dataset, class_output, &results, (0, 0))?;
+        }
 
-        // Create options vector
-        let mut options = Vec::new();
+        // Render the result if requested
+        let render_dataset = self.create_render_output(
+            &dataset,
+            &calculator,
+            width,
+            height,
+            compress,
+            compress_level,
+            tiled,
+        )?;
+        if let (Some(render_output), Some(render_dataset)) = (&self.render_output, render_dataset) {
+            write_render_block(&render_dataset, render_output, &results, (0, 0))?;
+            drop(render_dataset);
+            render_output.write_quicklook(&self.scratch)?;
+        }
 
-        // Add compression if not NONE
-        if compress.to_uppercase() != "NONE" {
-            options.push(format!("COMPRESS={}", compress.to_uppercase()));
+        self.write_stac_item(output_path, provenance)?;
+        self.record(|stats| stats.add_write(writing.elapsed()));
+        Ok(())
+    }
 
-            // Add compression level for supported algorithms
-            match compress.to_uppercase().as_str() {
-                "DEFLATE" => options.push(format!("ZLEVEL={}", compress_level.min(9))),
-                "ZSTD" => options.push(format!("ZSTD_LEVEL={}", compress_level.min(22))),
-                _ => {}
+    /// Provenance of an output: calculator, inputs and the parameters it was written with
+    #[allow(clippy::too_many_arguments)]
+    fn provenance<I: IndexCalculator>(
+        &self,
+        calculator: &I,
+        inputs: &[String],
+        paths: &[String],
+        scalings: &[InputScaling],
+        use_fixed_point: bool,
+        scale_factor: i32,
+        input_scale_factor: f32,
+        compress: &str,
+    ) -> Result<Provenance> {
+        let mut provenance = Provenance::new(calculator, inputs, paths)
+            .with_parameter("output_type", if use_fixed_point { "Int16" } else { "Float32" })
+            .with_parameter("input_scale_factor", input_scale_factor)
+            .with_parameter("compress", compress);
+        if use_fixed_point {
+            provenance = provenance.with_parameter("scale_factor", scale_factor);
+        }
+        for (i, scaling) in scalings.iter().enumerate() {
+            if scaling.is_detected() {
+                provenance = provenance.with_parameter(
+                    &format!("input_{}_scaling", i + 1),
+                    format!("x{} {:+} ({})", scaling.scale, scaling.offset, scaling.source),
+                );
             }
         }
+        if let Some(mask) = &self.mask {
+            provenance = provenance.with_parameter(
+                "mask",
+                format!("{} {:?} {:?}", mask.input, mask.mask_type, mask.values),
+            );
+        }
+        // Hashing reads every input again, so only STAC items pay for it
+        if self.stac_item {
+            provenance = provenance.with_checksums()?;
+        }
+        Ok(provenance)
+    }
 
-        // Add tiling if enabled
-        if tiled {
-            options.push("TILED=YES".to_string());
+    /// Write the STAC item of the output (with the class map and rendering as extra assets)
+    fn write_stac_item(&self, output_path: &str, provenance: Option<&Provenance>) -> Result<()> {
+        let (true, Some(provenance)) = (self.stac_item, provenance) else {
+            return Ok(());
+        };
+        let mut assets = Vec::new();
+        if let Some(class_output) = &self.class_output {
+            assets.push(("classes", class_output.path.as_str(), "data"));
+        }
+        if let Some(render_output) = &self.render_output {
+            assets.push(("rendering", render_output.path.as_str(), "visual"));
+            if let Some(quicklook) = &render_output.quicklook {
+                assets.push(("thumbnail", quicklook.as_str(), "thumbnail"));
+            }
         }
+        let item_path = stac::write_item(output_path, &assets, provenance)?;
+        println!("STAC item: {}", item_path);
+        Ok(())
+    }
 
-        // Always use multi-threading
-        options.push("NUM_THREADS=ALL_CPUS".to_string());
+    /// Scale and offset of every input, none when disabled or applied by the calculator itself
+    fn detect_scalings<I: IndexCalculator>(
+        &self,
+        calculator: &I,
+        inputs: &[String],
+        paths: &[String],
+    ) -> Result<Vec<InputScaling>> {
+        if !self.auto_scale || calculator.applies_input_scaling() {
+            return Ok(vec![InputScaling::NONE; paths.len()]);
+        }
 
-        let creation_options = RasterCreationOptions::from_iter(options);
+        let mut scalings = Vec::with_capacity(paths.len());
+        for (input, path) in inputs.iter().zip(paths) {
+            let scaling = InputScaling::detect(input, path, &self.scratch)?;
+            if scaling.is_detected() {
+                println!(
+                    "Input scaling of {}: x{} {:+} ({})",
+                    input, scaling.scale, scaling.offset, scaling.source
+                );
+            }
+            scalings.push(scaling);
+        }
+        Ok(scalings)
+    }
 
-        let mut output = if use_fixed_point {
+    /// Create the output dataset using the selected (or guessed) output format
+    #[allow(clippy::too_many_arguments)]
+    fn create_output(
+        &self,
+        output_path: &str,
+        width: usize,
+        height: usize,
+        band_count: usize,
+        use_fixed_point: bool,
+        compress: &str,
+        compress_level: u8,
+        tiled: bool,
+    ) -> Result<Dataset> {
+        let format = OutputFormat::resolve(self.output_format, output_path)?;
+        let driver = format.driver()?;
+
+        let creation_options = RasterCreationOptions::from_iter(format.creation_options(
+            compress,
+            compress_level,
+            tiled,
+            use_fixed_point,
+        ));
+
+        let output = if use_fixed_point {
             driver.create_with_band_type_with_options::<i16, _>(
                 output_path,
                 width,
                 height,
-                1,
+                band_count,
                 &creation_options,
             )?
         } else {
@@ -411,58 +821,190 @@ impl ParallelProcessor {
                 output_path,
                 width,
                 height,
-                1,
+                band_count,
                 &creation_options,
             )?
         };
 
-        // Copy geospatial metadata
-        let dataset = Dataset::open(&input_paths[0])?;
-        output.set_projection(&dataset.projection())?;
-        output.set_geo_transform(&dataset.geo_transform()?.try_into().unwrap())?;
+        Ok(output)
+    }
+
+    /// Create the UInt8 class map of the classification post-step, if any
+    #[allow(clippy::too_many_arguments)]
+    fn create_class_output<I: IndexCalculator>(
+        &self,
+        reference: &Dataset,
+        calculator: &I,
+        width: usize,
+        height: usize,
+        compress: &str,
+        compress_level: u8,
+        tiled: bool,
+    ) -> Result<Option<Dataset>> {
+        let Some(class_output) = &self.class_output else {
+            return Ok(None);
+        };
+
+        let format = OutputFormat::resolve(self.output_format, &class_output.path)?;
+        let creation_options = RasterCreationOptions::from_iter(format.creation_options(
+            compress,
+            compress_level,
+            tiled,
+            true,
+        ));
+        let mut dataset = format.driver()?.create_with_band_type_with_options::<u8, _>(
+            &class_output.path,
+            width,
+            height,
+            1,
+            &creation_options,
+        )?;
+
+        let description = format!("{} classes", calculator.output_bands()[class_output.band]);
+        class_output.setup(&mut dataset, reference, &description)?;
+        Ok(Some(dataset))
+    }
+
+    /// Create the RGBA GeoTIFF of the render post-step, if any
+    #[allow(clippy::too_many_arguments)]
+    fn create_render_output<I: IndexCalculator>(
+        &self,
+        reference: &Dataset,
+        calculator: &I,
+        width: usize,
+        height: usize,
+        compress: &str,
+        compress_level: u8,
+        tiled: bool,
+    ) -> Result<Option<Dataset>> {
+        let Some(render_output) = &self.render_output else {
+            return Ok(None);
+        };
+
+        let description = format!("{} rendering", calculator.output_bands()[render_output.band]);
+        let dataset = render_output.create(
+            reference,
+            width,
+            height,
+            compress,
+            compress_level,
+            tiled,
+            &description,
+        )?;
+        Ok(Some(dataset))
+    }
+}
 
-        // Set up band metadata
-        let mut output_band = output.rasterband(1)?;
+/// Render one block of calculator results into the RGBA dataset
+fn write_render_block(
+    render_dataset: &Dataset,
+    render_output: &RenderOutput,
+    results: &[TypedBuffer],
+    offset: (isize, isize),
+) -> Result<()> {
+    let result_data = results[render_output.band].as_f32().unwrap();
+    render_output.write_block(render_dataset, result_data.data(), result_data.shape(), offset)
+}
+
+/// Classify one block of calculator results into the class map
+fn write_class_band(
+    class_dataset: &Dataset,
+    class_output: &ClassOutput,
+    results: &[TypedBuffer],
+    offset: (isize, isize),
+) -> Result<()> {
+    let result_data = results[class_output.band].as_f32().unwrap();
+    let result_shape = result_data.shape();
+    let classes = class_output.classification.classify_values(result_data.data());
+
+    let mut buffer = Buffer::new(result_shape, classes);
+    class_dataset.rasterband(1)?.write(offset, result_shape, &mut buffer)?;
+    Ok(())
+}
+
+/// Set nodata, scaling metadata and descriptions on every output band
+fn setup_output_bands<I: IndexCalculator>(
+    output: &Dataset,
+    calculator: &I,
+    use_fixed_point: bool,
+    scale_factor: i32,
+) -> Result<()> {
+    for (i, name) in calculator.output_bands().iter().enumerate() {
+        let mut output_band = output.rasterband(i + 1)?;
         if use_fixed_point {
             output_band.set_no_data_value(Some(NODATA_VALUE_INT as f64))?;
-            output_band.set_metadata_item(
-                "SCALE",
-                &format!("{}", 1.0 / scale_factor as f64),
-                "",
-            )?;
-            output_band.set_metadata_item("OFFSET", "0", "")?;
-            output_band.set_description(&format!(
-                "{} (scaled by {})",
-                calculator.name(),
-                scale_factor
-            ))?;
-
-            // Convert and write the result
-            let result_data = result.as_f32().unwrap();
+            if let Some(scale) = calculator.band_scale(i) {
+                output_band.set_metadata_item("SCALE", &format!("{}", 1.0 / scale as f64), "")?;
+                output_band.set_metadata_item("OFFSET", "0", "")?;
+                output_band.set_description(&format!("{} (scaled by {})", name, scale))?;
+            } else if calculator.is_scaled_band(i) {
+                output_band.set_metadata_item(
+                    "SCALE",
+                    &format!("{}", 1.0 / scale_factor as f64),
+                    "",
+                )?;
+                output_band.set_metadata_item("OFFSET", "0", "")?;
+                output_band.set_description(&format!("{} (scaled by {})", name, scale_factor))?;
+            } else {
+                output_band.set_description(name)?;
+            }
+        } else {
+            output_band.set_no_data_value(Some(NODATA_VALUE_FLOAT as f64))?;
+            output_band.set_description(name)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fixed-point value of a result multiplied by `scale` (rounded when `None`), index values
+/// (`index_range`) clamped to ±0.9999 first and other values to the Int16 range above nodata
+pub fn fixed_point_value(value: f32, scale: Option<f32>, index_range: bool) -> i16 {
+    match scale {
+        _ if value == NODATA_VALUE_FLOAT => NODATA_VALUE_INT,
+        Some(scale) if index_range => (value.clamp(-0.9999, 0.9999) * scale).round() as i16,
+        Some(scale) => (value * scale).round().clamp(NODATA_VALUE_INT as f32 + 1.0, i16::MAX as f32) as i16,
+        None => value.round().clamp(i16::MIN as f32 + 1.0, i16::MAX as f32) as i16,
+    }
+}
+
+/// Write one block of calculator results, converting to fixed-point if requested
+fn write_output_bands<I: IndexCalculator>(
+    output: &Dataset,
+    calculator: &I,
+    results: &[TypedBuffer],
+    offset: (isize, isize),
+    use_fixed_point: bool,
+    scale_factor: i32,
+) -> Result<()> {
+    for (i, result) in results.iter().enumerate() {
+        let mut output_band = output.rasterband(i + 1)?;
+        let result_data = result.as_f32().unwrap();
+        let result_shape = result_data.shape();
+
+        if use_fixed_point {
+            // Convert float result to fixed-point, unscaled bands are only rounded
+            let scale = match calculator.band_scale(i) {
+                Some(scale) => Some(scale),
+                None => calculator.is_scaled_band(i).then_some(scale_factor as f32),
+            };
+            let index_range = calculator.band_scale(i).is_none();
             let mut buffer_data = vec![0i16; result_data.data().len()];
 
             for (dst, &src) in buffer_data.iter_mut().zip(result_data.data()) {
-                *dst = if src == NODATA_VALUE_FLOAT {
-                    NODATA_VALUE_INT
-                } else {
-                    (src.max(-0.9999).min(0.9999) * scale_factor as f32).round() as i16
-                };
+                *dst = fixed_point_value(src, scale, index_range);
             }
 
-            let mut buffer = Buffer::new(result_data.shape(), buffer_data);
-            output_band.write((0, 0), result_data.shape(), &mut buffer)?;
+            let mut buffer = Buffer::new(result_shape, buffer_data);
+            output_band.write(offset, result_shape, &mut buffer)?;
         } else {
-            output_band.set_no_data_value(Some(NODATA_VALUE_FLOAT as f64))?;
-            output_band.set_description(calculator.name())?;
-
-            // Write the result directly
-            let result_data = result.as_f32().unwrap();
-            let mut buffer = Buffer::new(result_data.shape(), result_data.data().to_vec());
-            output_band.write((0, 0), result_data.shape(), &mut buffer)?;
+            // Use float result directly
+            let mut buffer = Buffer::new(result_shape, result_data.data().to_vec());
+            output_band.write(offset, result_shape, &mut buffer)?;
         }
-
-        Ok(())
     }
+
+    Ok(())
 }
 
 struct ParallelBlockReader {
@@ -470,10 +1012,12 @@ struct ParallelBlockReader {
     datasets: Arc<Vec<Box<[Arc<Mutex<Dataset>>]>>>,
     dataset_paths: Option<Arc<Vec<String>>>,
     cache: Option<Arc<RasterCache>>,
+    scheduler: Option<Arc<Scheduler>>,
+    raster_size: (usize, usize),
     region_size: (usize, usize),
     blocks: (usize, usize),
     workers: Vec<JoinHandle<()>>,
-    req_tx: Sender<BlockReadRequest>,
+    req_tx: Option<Sender<BlockReadRequest>>,
 }
 
 impl ParallelBlockReader {
@@ -505,7 +1049,13 @@ impl ParallelBlockReader {
                     let block = {
                         let region_size = request.state.region_size;
                         let dataset = datasets[thread_id][request.dataset_idx].lock();
-                        let band = dataset.rasterband(1).unwrap();
+                        let band = match dataset.rasterband(1) {
+                            Ok(band) => band,
+                            Err(e) => {
+                                eprintln!("Error reading input {}: {}", request.dataset_idx + 1, e);
+                                continue;
+                            }
+                        };
                         let size = band.size();
                         let window = (request.x * region_size.0, request.y * region_size.1);
 
@@ -526,16 +1076,25 @@ impl ParallelBlockReader {
                                 },
                             );
 
-                            let buffer = band
-                                .read_as::<f32>(
-                                    (window.0 as isize, window.1 as isize),
-                                    window_size,
-                                    window_size,
-                                    None,
-                                )
-                                .unwrap();
-
-                            TypedBuffer::F32(buffer)
+                            let buffer = band.read_as::<f32>(
+                                (window.0 as isize, window.1 as isize),
+                                window_size,
+                                window_size,
+                                None,
+                            );
+                            match buffer {
+                                Ok(buffer) => TypedBuffer::F32(buffer),
+                                Err(e) => {
+                                    eprintln!(
+                                        "Error reading block ({}, {}) of input {}: {}",
+                                        request.x,
+                                        request.y,
+                                        request.dataset_idx + 1,
+                                        e
+                                    );
+                                    continue;
+                                }
+                            }
                         }
                     };
 
@@ -587,10 +1146,12 @@ impl ParallelBlockReader {
             datasets,
             dataset_paths: None,
             cache: None,
+            scheduler: None,
+            raster_size,
             region_size,
             blocks,
             workers,
-            req_tx,
+            req_tx: Some(req_tx),
         })
     }
     // New constructor that uses cache
@@ -599,9 +1160,41 @@ impl ParallelBlockReader {
         threads: usize,
         cache: Arc<RasterCache>,
     ) -> anyhow::Result<Self> {
-        // Create dataset_paths
-        let dataset_paths = Arc::new(paths.to_vec());
+        let mut reader = Self::cached(paths, cache)?;
+        let (req_tx, req_rx) = flume::unbounded();
+
+        // Set up worker threads
+        let mut workers = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let req_rx: Receiver<BlockReadRequest> = req_rx.clone();
+            let cache = Arc::clone(reader.cache.as_ref().unwrap());
+            let dataset_paths = Arc::clone(reader.dataset_paths.as_ref().unwrap());
+
+            workers.push(thread::spawn(move || {
+                for request in req_rx {
+                    read_cached_block(&cache, &dataset_paths, request);
+                }
+            }));
+        }
+
+        reader.workers = workers;
+        reader.req_tx = Some(req_tx);
+        Ok(reader)
+    }
+
+    /// Reader whose blocks are read by the shared readers of `scheduler`
+    pub fn with_scheduler(
+        paths: &[String],
+        cache: Arc<RasterCache>,
+        scheduler: Arc<Scheduler>,
+    ) -> anyhow::Result<Self> {
+        let mut reader = Self::cached(paths, cache)?;
+        reader.scheduler = Some(scheduler);
+        Ok(reader)
+    }
 
+    /// Reader of cached datasets, without workers yet
+    fn cached(paths: &[String], cache: Arc<RasterCache>) -> anyhow::Result<Self> {
         // For dimensions, we temporarily open the first dataset
         let first_dataset = cache.get_dataset(&paths[0])?;
         let band_size = {
@@ -630,111 +1223,28 @@ impl ParallelBlockReader {
             (raster_size.1 + region_size.1 - 1) / region_size.1,
         );
 
-        // Create request channel
-        let (req_tx, req_rx) = flume::unbounded();
-
-        // Set up worker threads
-        let mut workers = Vec::with_capacity(threads);
-        for _ in 0..threads {
-            let req_rx = req_rx.clone();
-            let cache = Arc::clone(&cache);
-            let dataset_paths = Arc::clone(&dataset_paths);
-
-            let worker = thread::spawn(move || {
-                for request in req_rx {
-                    let BlockReadRequest {
-                        dataset_idx,
-                        x,
-                        y,
-                        state,
-                        handler,
-                        ..
-                    } = request;
-
-                    let path = &dataset_paths[dataset_idx];
-
-                    // Get dataset from cache
-                    let result = cache.get_dataset(path);
-                    if let Err(e) = result {
-                        eprintln!("Error opening dataset {}: {}", path, e);
-                        continue;
-                    }
-
-                    let dataset_mutex = result.unwrap();
-
-                    // Process block with the dataset
-                    let block = {
-                        let region_size = state.region_size;
-                        let dataset = dataset_mutex.lock().unwrap();
-                        let band = dataset.rasterband(1).unwrap();
-                        let size = band.size();
-                        let window = (x * region_size.0, y * region_size.1);
-
-                        // Same logic as original for reading the block
-                        // ...
-
-                        // Read the block
-                        if window.0 >= size.0 || window.1 >= size.1 {
-                            TypedBuffer::F32(Buffer::new((0, 0), vec![]))
-                        } else {
-                            let window_size = (
-                                if window.0 + region_size.0 <= size.0 {
-                                    region_size.0
-                                } else {
-                                    size.0 - window.0
-                                },
-                                if window.1 + region_size.1 <= size.1 {
-                                    region_size.1
-                                } else {
-                                    size.1 - window.1
-                                },
-                            );
-
-                            let buffer = band
-                                .read_as::<f32>(
-                                    (window.0 as isize, window.1 as isize),
-                                    window_size,
-                                    window_size,
-                                    None,
-                                )
-                                .unwrap();
-
-                            TypedBuffer::F32(buffer)
-                        }
-                    };
-
-                    // Use the same logic as original for handling the result
-                    let blocks = {
-                        let mut blocks = state.blocks.lock();
-                        blocks.insert(dataset_idx, block);
-                        if blocks.len() == dataset_paths.len() {
-                            let blocks = mem::take(blocks.deref_mut());
-                            Some(blocks)
-                        } else {
-                            None
-                        }
-                    };
-
-                    if let Some(blocks) = blocks {
-                        (handler)(x, y, blocks);
-                    }
-                }
-            });
-
-            workers.push(worker);
-        }
-
         Ok(Self {
             datasets: Arc::new(vec![]), // Empty datasets since we're using cache
-            dataset_paths: Some(dataset_paths),
+            dataset_paths: Some(Arc::new(paths.to_vec())),
             cache: Some(cache),
+            scheduler: None,
+            raster_size,
             region_size,
             blocks,
-            workers,
-            req_tx,
+            workers: Vec::new(),
+            req_tx: None,
         })
     }
 
+    /// Read regions of `region_size` instead of the native block size
+    fn set_region_size(&mut self, region_size: (usize, usize)) {
+        self.region_size = region_size;
+        self.blocks = (
+            self.raster_size.0.div_ceil(region_size.0),
+            self.raster_size.1.div_ceil(region_size.1),
+        );
+    }
+
     // Modify run method to handle both cached and non-cached cases
     pub fn run(
         &self,
@@ -759,7 +1269,15 @@ impl ParallelBlockReader {
                 state: state.clone(),
                 handler: handler.clone(),
             };
-            self.req_tx.send(request).unwrap();
+            match (&self.scheduler, &self.req_tx) {
+                (Some(scheduler), _) => {
+                    let cache = Arc::clone(self.cache.as_ref().unwrap());
+                    let dataset_paths = Arc::clone(self.dataset_paths.as_ref().unwrap());
+                    scheduler.submit(move || read_cached_block(&cache, &dataset_paths, request));
+                }
+                (None, Some(req_tx)) => req_tx.send(request).unwrap(),
+                (None, None) => {}
+            }
         }
     }
 
@@ -779,6 +1297,92 @@ impl ParallelBlockReader {
     }
 }
 
+/// Read one block of a cached dataset, calling the handler once the blocks of every dataset are in
+fn read_cached_block(cache: &RasterCache, dataset_paths: &[String], request: BlockReadRequest) {
+    let BlockReadRequest {
+        dataset_idx,
+        x,
+        y,
+        state,
+        handler,
+        ..
+    } = request;
+
+    let path = &dataset_paths[dataset_idx];
+
+    // Get dataset from cache
+    let dataset_mutex = match cache.get_dataset(path) {
+        Ok(dataset) => dataset,
+        Err(e) => {
+            eprintln!("Error opening dataset {}: {}", path, e);
+            return;
+        }
+    };
+
+    // Process block with the dataset
+    let block = {
+        let region_size = state.region_size;
+        let dataset = dataset_mutex.lock().unwrap();
+        let band = match dataset.rasterband(1) {
+            Ok(band) => band,
+            Err(e) => {
+                eprintln!("Error reading dataset {}: {}", path, e);
+                return;
+            }
+        };
+        let size = band.size();
+        let window = (x * region_size.0, y * region_size.1);
+
+        // Read the block
+        if window.0 >= size.0 || window.1 >= size.1 {
+            TypedBuffer::F32(Buffer::new((0, 0), vec![]))
+        } else {
+            let window_size = (
+                if window.0 + region_size.0 <= size.0 {
+                    region_size.0
+                } else {
+                    size.0 - window.0
+                },
+                if window.1 + region_size.1 <= size.1 {
+                    region_size.1
+                } else {
+                    size.1 - window.1
+                },
+            );
+
+            let buffer = band.read_as::<f32>(
+                (window.0 as isize, window.1 as isize),
+                window_size,
+                window_size,
+                None,
+            );
+            match buffer {
+                Ok(buffer) => TypedBuffer::F32(buffer),
+                Err(e) => {
+                    eprintln!("Error reading block ({}, {}) of {}: {}", x, y, path, e);
+                    return;
+                }
+            }
+        }
+    };
+
+    // Use the same logic as original for handling the result
+    let blocks = {
+        let mut blocks = state.blocks.lock();
+        blocks.insert(dataset_idx, block);
+        if blocks.len() == dataset_paths.len() {
+            let blocks = mem::take(blocks.deref_mut());
+            Some(blocks)
+        } else {
+            None
+        }
+    };
+
+    if let Some(blocks) = blocks {
+        (handler)(x, y, blocks);
+    }
+}
+
 /// Trait for spectral index calculators
 pub trait IndexCalculator: Send + Sync {
     /// Calculate the index from the provided input bands
@@ -790,10 +1394,96 @@ pub trait IndexCalculator: Send + Sync {
     /// Return the name of the index
     fn name(&self) -> &str;
 
+    /// Return the formula of the index, recorded in the output provenance (empty if none)
+    fn formula(&self) -> String {
+        String::new()
+    }
+
+    /// Return the parameters of the calculator (e.g. the SAVI soil factor) as name/value pairs,
+    /// recorded in the output provenance
+    fn parameters(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
     /// Return true if this index requires input scaling for proper results
     /// Indices with constants (EVI, SAVI, MSAVI2, OSAVI) need scaling
     /// Pure ratio indices (NDI, NDWI, NDSI, BSI) do not need scaling
     fn needs_input_scaling(&self) -> bool {
         false // Default: most indices are pure ratios that don't need scaling
     }
+
+    /// Return true if the calculator applies the scale/offset of its inputs itself (see
+    /// `StackInput`), so the processor passes raw values
+    fn applies_input_scaling(&self) -> bool {
+        false
+    }
+
+    /// Return the description of each output band
+    /// Most calculators write a single band named after the index
+    fn output_bands(&self) -> Vec<String> {
+        vec![self.name().to_string()]
+    }
+
+    /// Calculate all output bands, in the order of `output_bands`
+    fn calculate_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        vec![self.calculate(inputs)]
+    }
+
+    /// Return true if the output band holds index values that are scaled for fixed-point output
+    /// Bands holding counts or identifiers are written unscaled
+    fn is_scaled_band(&self, _band: usize) -> bool {
+        true
+    }
+
+    /// Return the fixed-point multiplier of a band whose values leave the index range (e.g. 100
+    /// for a ratio written in percent), used instead of the scale factor and its ±1 clamp
+    fn band_scale(&self, _band: usize) -> Option<f32> {
+        None
+    }
+}
+
+impl<T: IndexCalculator + ?Sized> IndexCalculator for Box<T> {
+    fn calculate(&self, inputs: &[TypedBuffer]) -> TypedBuffer {
+        (**self).calculate(inputs)
+    }
+
+    fn required_bands(&self) -> usize {
+        (**self).required_bands()
+    }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn formula(&self) -> String {
+        (**self).formula()
+    }
+
+    fn parameters(&self) -> Vec<(String, String)> {
+        (**self).parameters()
+    }
+
+    fn needs_input_scaling(&self) -> bool {
+        (**self).needs_input_scaling()
+    }
+
+    fn applies_input_scaling(&self) -> bool {
+        (**self).applies_input_scaling()
+    }
+
+    fn output_bands(&self) -> Vec<String> {
+        (**self).output_bands()
+    }
+
+    fn calculate_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        (**self).calculate_bands(inputs)
+    }
+
+    fn is_scaled_band(&self, band: usize) -> bool {
+        (**self).is_scaled_band(band)
+    }
+
+    fn band_scale(&self, band: usize) -> Option<f32> {
+        (**self).band_scale(band)
+    }
 }
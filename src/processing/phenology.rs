@@ -0,0 +1,419 @@
+// src/processing/phenology.rs
+use crate::processing::parallel::IndexCalculator;
+use crate::processing::timeseries::{self, interpolate, solve_linear, StackInput};
+use crate::utils::dates::Date;
+use crate::utils::gdal_ext::TypedBuffer;
+use anyhow::Result;
+
+/// Fewest valid observations needed to extract a season
+pub const MIN_OBSERVATIONS: usize = 6;
+
+/// Smoothing applied to each pixel's series before extracting phenometrics
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Savitzky-Golay filter over the series resampled to its median spacing
+    SavitzkyGolay { window: usize, order: usize },
+    /// Double-logistic curve fitted by Levenberg-Marquardt (Beck et al. 2006)
+    DoubleLogistic,
+}
+
+impl Smoothing {
+    /// Parse a method name (savgol, double_logistic); `window` and `order` apply to savgol
+    pub fn from_name(name: &str, window: usize, order: usize) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "savgol" | "sg" | "savitzky_golay" | "savitzky-golay" => {
+                if window % 2 != 1 || window <= order {
+                    return Err(anyhow::anyhow!(
+                        "Savitzky-Golay window must be odd and larger than the order (window {}, order {})",
+                        window,
+                        order
+                    ));
+                }
+                Ok(Smoothing::SavitzkyGolay { window, order })
+            }
+            "double_logistic" | "double-logistic" | "dl" => Ok(Smoothing::DoubleLogistic),
+            _ => Err(anyhow::anyhow!(
+                "Unknown smoothing method '{}'. Expected one of: savgol, double_logistic",
+                name
+            )),
+        }
+    }
+}
+
+/// Output bands, in order
+pub const PHENOMETRICS: [&str; 7] = [
+    "Start of season (day of year)",
+    "End of season (day of year)",
+    "Peak of season (day of year)",
+    "Peak value",
+    "Season length (days)",
+    "Amplitude",
+    "Integrated value over the season (value x days)",
+];
+
+/// Set up a [`Phenology`] calculator for the given stack paths and dates
+pub fn from_inputs(
+    paths: &[String],
+    smoothing: Smoothing,
+    threshold: f32,
+    dates: &[String],
+) -> Result<Phenology> {
+    if paths.len() < MIN_OBSERVATIONS {
+        return Err(anyhow::anyhow!(
+            "Phenology needs at least {} dated inputs, got {}",
+            MIN_OBSERVATIONS,
+            paths.len()
+        ));
+    }
+
+    let dates = timeseries::stack_dates(paths, dates)?.ok_or_else(|| {
+        anyhow::anyhow!("Acquisition dates not found for all inputs, pass them with --dates")
+    })?;
+    let inputs = paths
+        .iter()
+        .map(|p| StackInput::from_path(p))
+        .collect::<Result<Vec<_>>>()?;
+
+    Phenology::new(smoothing, threshold, &dates, inputs, None)
+}
+
+/// Per-pixel season metrics from a dated stack of index rasters (one season per stack)
+///
+/// Start and end of season are where the smoothed curve crosses `threshold` times the
+/// amplitude above the minimum on each side of the peak. Days of year count from
+/// January 1st of the earliest acquisition's year, so they exceed 365 for seasons
+/// crossing the new year.
+pub struct Phenology {
+    smoothing: Smoothing,
+    threshold: f64,
+    days: Vec<f64>,
+    inputs: Vec<StackInput>,
+    name: String,
+}
+
+impl Phenology {
+    pub fn new(
+        smoothing: Smoothing,
+        threshold: f32,
+        dates: &[Date],
+        inputs: Vec<StackInput>,
+        name: Option<String>,
+    ) -> Result<Self> {
+        if threshold <= 0.0 || threshold >= 1.0 {
+            return Err(anyhow::anyhow!(
+                "Season threshold must be between 0 and 1 (fraction of the amplitude), got {}",
+                threshold
+            ));
+        }
+        let Some(first) = dates.iter().min() else {
+            return Err(anyhow::anyhow!("Phenology needs dated inputs"));
+        };
+
+        // Day 1 is January 1st of the first year
+        let origin = Date::new(first.year, 1, 1)?.days_since_epoch() - 1;
+        let days = dates
+            .iter()
+            .map(|d| (d.days_since_epoch() - origin) as f64)
+            .collect();
+
+        Ok(Self {
+            smoothing,
+            threshold: threshold as f64,
+            days,
+            inputs,
+            name: name.unwrap_or_else(|| "Phenology".to_string()),
+        })
+    }
+
+    /// Smoothed curve of a time-sorted series, sampled on a regular grid
+    fn smooth(&self, series: &[(f64, f32)]) -> Option<Vec<(f64, f64)>> {
+        let (start, end) = (series[0].0, series[series.len() - 1].0);
+
+        match self.smoothing {
+            Smoothing::SavitzkyGolay { window, order } => {
+                let step = median_spacing(series).max(1.0);
+                let steps = ((end - start) / step).round() as usize;
+                let grid: Vec<f64> = (0..=steps).map(|i| start + i as f64 * step).collect();
+                let values: Vec<f64> = grid.iter().map(|&t| interpolate(series, t)).collect();
+                let smoothed = savitzky_golay(&values, window, order);
+                Some(grid.into_iter().zip(smoothed).collect())
+            }
+            Smoothing::DoubleLogistic => {
+                let params = fit_double_logistic(series)?;
+                let days = (end - start).round() as usize;
+                Some(
+                    (0..=days)
+                        .map(|d| {
+                            let t = start + d as f64;
+                            (t, double_logistic(&params, t))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    fn phenology_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        timeseries::stack_bands(inputs, &self.inputs, &self.days, PHENOMETRICS.len(), |mut series, results| {
+            if series.len() < MIN_OBSERVATIONS {
+                return;
+            }
+            series.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            if let Some(curve) = self.smooth(&series) {
+                season_metrics(&curve, self.threshold, results);
+            }
+        })
+    }
+}
+
+impl IndexCalculator for Phenology {
+    fn calculate(&self, inputs: &[TypedBuffer]) -> TypedBuffer {
+        self.phenology_bands(inputs).swap_remove(0)
+    }
+
+    fn required_bands(&self) -> usize {
+        self.days.len()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn applies_input_scaling(&self) -> bool {
+        true
+    }
+
+    fn output_bands(&self) -> Vec<String> {
+        PHENOMETRICS.iter().map(|m| m.to_string()).collect()
+    }
+
+    fn calculate_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        self.phenology_bands(inputs)
+    }
+
+    fn is_scaled_band(&self, band: usize) -> bool {
+        // Only index values are scaled, days and integrals are rounded
+        band == 3 || band == 5
+    }
+}
+
+/// Write the phenometrics of a smoothed curve into `out` (see [`PHENOMETRICS`]).
+/// Metrics that cannot be determined are left untouched
+fn season_metrics(curve: &[(f64, f64)], threshold: f64, out: &mut [f32]) {
+    let peak = (0..curve.len())
+        .max_by(|&a, &b| curve[a].1.total_cmp(&curve[b].1))
+        .unwrap();
+    let (peak_time, peak_value) = curve[peak];
+    let left_min = curve[..=peak].iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let right_min = curve[peak..].iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let amplitude = peak_value - (left_min + right_min) / 2.0;
+    if amplitude <= 0.0 {
+        return;
+    }
+
+    out[2] = peak_time as f32;
+    out[3] = peak_value as f32;
+    out[5] = amplitude as f32;
+
+    // Walk away from the peak until the curve drops below the threshold level
+    let sos_level = left_min + threshold * (peak_value - left_min);
+    let sos = (0..peak)
+        .rev()
+        .find(|&i| curve[i].1 < sos_level)
+        .map(|i| crossing(curve[i], curve[i + 1], sos_level));
+    let eos_level = right_min + threshold * (peak_value - right_min);
+    let eos = (peak + 1..curve.len())
+        .find(|&i| curve[i].1 < eos_level)
+        .map(|i| crossing(curve[i - 1], curve[i], eos_level));
+
+    if let Some(sos) = sos {
+        out[0] = sos as f32;
+    }
+    if let Some(eos) = eos {
+        out[1] = eos as f32;
+    }
+    if let (Some(sos), Some(eos)) = (sos, eos) {
+        out[4] = (eos - sos) as f32;
+
+        // Trapezoidal integral of the curve between start and end of season
+        let mut points = vec![(sos, sos_level)];
+        points.extend(curve.iter().copied().filter(|&(t, _)| t > sos && t < eos));
+        points.push((eos, eos_level));
+        let integral: f64 = points
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0) * (w[0].1 + w[1].1) / 2.0)
+            .sum();
+        out[6] = integral as f32;
+    }
+}
+
+/// Time at which the segment between two curve points reaches `level`
+fn crossing(a: (f64, f64), b: (f64, f64), level: f64) -> f64 {
+    if b.1 == a.1 {
+        return a.0;
+    }
+    a.0 + (level - a.1) / (b.1 - a.1) * (b.0 - a.0)
+}
+
+fn median_spacing(series: &[(f64, f32)]) -> f64 {
+    let mut gaps: Vec<f64> = series.windows(2).map(|w| w[1].0 - w[0].0).collect();
+    gaps.sort_by(|a, b| a.total_cmp(b));
+    gaps[gaps.len() / 2]
+}
+
+/// Savitzky-Golay smoothing of regularly spaced values, edges repeat the end values.
+/// The window shrinks to fit short series
+pub fn savitzky_golay(values: &[f64], window: usize, order: usize) -> Vec<f64> {
+    let window = if window > values.len() {
+        values.len() - (1 - values.len() % 2)
+    } else {
+        window
+    };
+    if window <= order {
+        return values.to_vec();
+    }
+
+    let half = (window / 2) as isize;
+    let Some(coefficients) = savitzky_golay_coefficients(half, order) else {
+        return values.to_vec();
+    };
+
+    let last = values.len() as isize - 1;
+    (0..values.len() as isize)
+        .map(|i| {
+            (-half..=half)
+                .zip(&coefficients)
+                .map(|(offset, c)| c * values[(i + offset).clamp(0, last) as usize])
+                .sum()
+        })
+        .collect()
+}
+
+/// Convolution coefficients giving the value at the window centre of a least squares
+/// polynomial fit: first row of (JᵀJ)⁻¹Jᵀ with J the Vandermonde matrix of the offsets
+fn savitzky_golay_coefficients(half: isize, order: usize) -> Option<Vec<f64>> {
+    let terms = order + 1;
+    let mut normal = vec![vec![0.0; terms]; terms];
+    for offset in -half..=half {
+        for (j, row) in normal.iter_mut().enumerate() {
+            for (k, value) in row.iter_mut().enumerate() {
+                *value += (offset as f64).powi((j + k) as i32);
+            }
+        }
+    }
+
+    let mut unit = vec![0.0; terms];
+    unit[0] = 1.0;
+    let row = solve_linear(normal, unit)?;
+
+    Some(
+        (-half..=half)
+            .map(|offset| {
+                row.iter()
+                    .enumerate()
+                    .map(|(j, r)| r * (offset as f64).powi(j as i32))
+                    .sum()
+            })
+            .collect(),
+    )
+}
+
+/// Double-logistic curve with parameters [minimum, maximum, green-up midpoint,
+/// green-up rate, senescence midpoint, senescence rate]
+pub fn double_logistic(params: &[f64; 6], t: f64) -> f64 {
+    let [min, max, sos, rate_s, eos, rate_e] = *params;
+    let rise = 1.0 / (1.0 + (-rate_s * (t - sos)).exp());
+    let fall = 1.0 / (1.0 + (rate_e * (t - eos)).exp());
+    min + (max - min) * (rise + fall - 1.0)
+}
+
+/// Fit a double-logistic curve to a time-sorted series with Levenberg-Marquardt
+pub fn fit_double_logistic(series: &[(f64, f32)]) -> Option<[f64; 6]> {
+    let (start, end) = (series[0].0, series[series.len() - 1].0);
+    let (peak_time, peak_value) = series
+        .iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|&(t, v)| (t, v as f64))?;
+    let min = series.iter().map(|&(_, v)| v as f64).fold(f64::INFINITY, f64::min);
+
+    let mut params = [
+        min,
+        peak_value,
+        (start + peak_time) / 2.0,
+        4.0 / (peak_time - start).max(10.0),
+        (peak_time + end) / 2.0,
+        4.0 / (end - peak_time).max(10.0),
+    ];
+
+    let residuals = |p: &[f64; 6]| -> f64 {
+        series
+            .iter()
+            .map(|&(t, v)| (v as f64 - double_logistic(p, t)).powi(2))
+            .sum()
+    };
+
+    let mut error = residuals(&params);
+    let mut lambda = 1e-3;
+    for _ in 0..100 {
+        // Normal equations JᵀJ and Jᵀr with the analytical Jacobian
+        let mut jtj = [[0.0; 6]; 6];
+        let mut jtr = [0.0; 6];
+        for &(t, v) in series {
+            let [min, max, sos, rate_s, eos, rate_e] = params;
+            let rise = 1.0 / (1.0 + (-rate_s * (t - sos)).exp());
+            let fall = 1.0 / (1.0 + (rate_e * (t - eos)).exp());
+            let range = max - min;
+            let jacobian = [
+                2.0 - rise - fall,
+                rise + fall - 1.0,
+                -range * rise * (1.0 - rise) * rate_s,
+                range * rise * (1.0 - rise) * (t - sos),
+                range * fall * (1.0 - fall) * rate_e,
+                -range * fall * (1.0 - fall) * (t - eos),
+            ];
+            let residual = v as f64 - double_logistic(&params, t);
+            for j in 0..6 {
+                jtr[j] += jacobian[j] * residual;
+                for k in 0..6 {
+                    jtj[j][k] += jacobian[j] * jacobian[k];
+                }
+            }
+        }
+
+        let damped: Vec<Vec<f64>> = (0..6)
+            .map(|j| {
+                let mut row = jtj[j].to_vec();
+                row[j] += lambda * jtj[j][j].max(1e-9);
+                row
+            })
+            .collect();
+        let Some(step) = solve_linear(damped, jtr.to_vec()) else {
+            lambda *= 10.0;
+            continue;
+        };
+
+        let mut candidate = params;
+        for (p, s) in candidate.iter_mut().zip(&step) {
+            *p += s;
+        }
+        let candidate_error = residuals(&candidate);
+
+        if candidate_error.is_finite() && candidate_error < error {
+            let converged = (error - candidate_error) < 1e-10 * error.max(1e-12);
+            params = candidate;
+            error = candidate_error;
+            lambda = (lambda / 10.0).max(1e-12);
+            if converged {
+                break;
+            }
+        } else {
+            lambda *= 10.0;
+            if lambda > 1e10 {
+                break;
+            }
+        }
+    }
+
+    params.iter().all(|p| p.is_finite()).then_some(params)
+}
@@ -0,0 +1,414 @@
+// src/processing/render.rs
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use gdal::raster::{Buffer, RasterCreationOptions};
+use gdal::{Dataset, DriverManager, Metadata};
+
+use crate::processing::classify::parse_color;
+use crate::processing::parallel::NODATA_VALUE_FLOAT;
+use crate::utils::cache::RasterCache;
+use crate::utils::vrt::escape_xml;
+use crate::utils::output::OutputFormat;
+
+/// Default longest side of quicklooks, in pixels
+pub const DEFAULT_QUICKLOOK_SIZE: usize = 1024;
+
+const RDYLGN: [&str; 11] = [
+    "#a50026", "#d73027", "#f46d43", "#fdae61", "#fee08b", "#ffffbf", "#d9ef8b", "#a6d96a",
+    "#66bd63", "#1a9850", "#006837",
+];
+const VIRIDIS: [&str; 9] = [
+    "#440154", "#472d7b", "#3b528b", "#2c728e", "#21918c", "#28ae80", "#5ec962", "#addc30",
+    "#fde725",
+];
+const GREYS: [&str; 2] = ["#000000", "#ffffff"];
+
+/// Named colormaps, a `_r` suffix reverses them
+pub const COLORMAPS: [&str; 3] = ["RdYlGn", "viridis", "greys"];
+
+/// Piecewise linear color ramp
+///
+/// Stops are either relative (0..1, stretched over the render range) or absolute values.
+#[derive(Debug, Clone)]
+pub struct Colormap {
+    stops: Vec<(f32, [u8; 4])>,
+    relative: bool,
+    /// Color of nodata pixels, transparent unless the color file has an `nv` entry
+    pub nodata: [u8; 4],
+}
+
+impl Colormap {
+    pub fn new(stops: Vec<(f32, [u8; 4])>, relative: bool) -> Result<Self> {
+        if stops.is_empty() {
+            return Err(anyhow::anyhow!("A colormap needs at least one color stop"));
+        }
+        if stops.windows(2).any(|w| w[0].0 > w[1].0) {
+            return Err(anyhow::anyhow!("Colormap stops must be sorted by value"));
+        }
+        Ok(Self {
+            stops,
+            relative,
+            nodata: [0, 0, 0, 0],
+        })
+    }
+
+    /// Named colormap (case-insensitive), e.g. `RdYlGn` or `viridis_r`
+    pub fn from_name(name: &str) -> Result<Self> {
+        let lower = name.to_lowercase();
+        let (base, reversed) = match lower.strip_suffix("_r") {
+            Some(base) => (base, true),
+            None => (lower.as_str(), false),
+        };
+        let colors: &[&str] = match base {
+            "rdylgn" => &RDYLGN,
+            "viridis" => &VIRIDIS,
+            "greys" | "grays" | "gray" => &GREYS,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unknown colormap '{}'. Available colormaps: {} (add _r to reverse) or a color file",
+                    name,
+                    COLORMAPS.join(", ")
+                ))
+            }
+        };
+
+        let mut colors = colors.iter().map(|c| parse_color(c)).collect::<Result<Vec<_>>>()?;
+        if reversed {
+            colors.reverse();
+        }
+        let last = (colors.len() - 1).max(1) as f32;
+        let stops = colors
+            .into_iter()
+            .enumerate()
+            .map(|(i, color)| (i as f32 / last, color))
+            .collect();
+        Self::new(stops, true)
+    }
+
+    /// Read a gdaldem-style color file: `value r g b [a]` per line.
+    ///
+    /// Values are absolute or percentages of the render range (`50%`), `nv` sets the nodata color.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read color file {}", path))?;
+
+        let mut stops = Vec::new();
+        let mut nodata = None;
+        let mut relative = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || anyhow::anyhow!("{}:{}: expected 'value r g b [a]', got '{}'", path, number + 1, line);
+
+            let fields: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+                .filter(|f| !f.is_empty())
+                .collect();
+            if fields.len() != 4 && fields.len() != 5 {
+                return Err(invalid());
+            }
+            let mut color = [0, 0, 0, 255];
+            for (channel, field) in color.iter_mut().zip(&fields[1..]) {
+                *channel = field.parse().map_err(|_| invalid())?;
+            }
+
+            let value = fields[0];
+            if value.eq_ignore_ascii_case("nv") {
+                nodata = Some(color);
+                continue;
+            }
+            let (value, is_percent) = match value.strip_suffix('%') {
+                Some(percent) => (percent.parse::<f32>().map_err(|_| invalid())? / 100.0, true),
+                None => (value.parse::<f32>().map_err(|_| invalid())?, false),
+            };
+            if *relative.get_or_insert(is_percent) != is_percent {
+                return Err(anyhow::anyhow!(
+                    "{}: mixes absolute values and percentages",
+                    path
+                ));
+            }
+            stops.push((value, color));
+        }
+
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut colormap = Self::new(stops, relative.unwrap_or(false))
+            .with_context(|| format!("Invalid color file {}", path))?;
+        if let Some(nodata) = nodata {
+            colormap.nodata = nodata;
+        }
+        Ok(colormap)
+    }
+
+    /// A colormap name or the path of a color file
+    pub fn parse(spec: &str) -> Result<Self> {
+        if Path::new(spec).is_file() {
+            Self::from_file(spec)
+        } else {
+            Self::from_name(spec)
+        }
+    }
+
+    /// True if stops are stretched over the render range
+    pub fn is_relative(&self) -> bool {
+        self.relative
+    }
+
+    /// Interpolated color at `position` (a fraction for relative ramps, a value otherwise)
+    pub fn color_at(&self, position: f32) -> [u8; 4] {
+        let first = self.stops[0];
+        let last = self.stops[self.stops.len() - 1];
+        if position <= first.0 {
+            return first.1;
+        }
+        if position >= last.0 {
+            return last.1;
+        }
+
+        let upper = self.stops.partition_point(|stop| stop.0 <= position);
+        let (v0, c0) = self.stops[upper - 1];
+        let (v1, c1) = self.stops[upper];
+        let t = (position - v0) / (v1 - v0);
+        let mut color = [0; 4];
+        for (i, channel) in color.iter_mut().enumerate() {
+            *channel = (c0[i] as f32 + t * (c1[i] as f32 - c0[i] as f32)).round() as u8;
+        }
+        color
+    }
+}
+
+/// Maps index values to RGBA colors
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    pub colormap: Colormap,
+    /// Values mapped to the ends of relative colormaps
+    pub range: (f32, f32),
+}
+
+impl Renderer {
+    /// `range` defaults to -1..1, the range of normalized difference indices
+    pub fn new(colormap: Colormap, range: Option<(f32, f32)>) -> Result<Self> {
+        let range = range.unwrap_or((-1.0, 1.0));
+        if range.0.is_nan() || range.1.is_nan() || range.0 >= range.1 {
+            return Err(anyhow::anyhow!(
+                "Invalid render range {}..{}, the minimum must be below the maximum",
+                range.0,
+                range.1
+            ));
+        }
+        Ok(Self { colormap, range })
+    }
+
+    pub fn color(&self, value: f32) -> [u8; 4] {
+        if value == NODATA_VALUE_FLOAT || value.is_nan() {
+            return self.colormap.nodata;
+        }
+        if self.colormap.is_relative() {
+            self.colormap
+                .color_at((value - self.range.0) / (self.range.1 - self.range.0))
+        } else {
+            self.colormap.color_at(value)
+        }
+    }
+
+    /// Render values into the red, green, blue and alpha channels
+    pub fn render_values(&self, values: &[f32]) -> [Vec<u8>; 4] {
+        let mut channels: [Vec<u8>; 4] = Default::default();
+        for channel in &mut channels {
+            channel.reserve(values.len());
+        }
+        for &value in values {
+            for (channel, component) in channels.iter_mut().zip(self.color(value)) {
+                channel.push(component);
+            }
+        }
+        channels
+    }
+}
+
+/// Parse a `MIN,MAX` value range
+pub fn parse_range(range: &str) -> Result<(f32, f32)> {
+    let invalid = || anyhow::anyhow!("Invalid range '{}', expected MIN,MAX", range);
+    let (min, max) = range.split_once(',').ok_or_else(invalid)?;
+    Ok((
+        min.trim().parse().map_err(|_| invalid())?,
+        max.trim().parse().map_err(|_| invalid())?,
+    ))
+}
+
+/// RGBA GeoTIFF rendering of one calculator output band, with an optional quicklook
+pub struct RenderOutput {
+    pub renderer: Renderer,
+    pub path: String,
+    /// 0-based index of the calculator output band to render
+    pub band: usize,
+    pub quicklook: Option<String>,
+    /// Longest side of the quicklook in pixels
+    pub quicklook_size: usize,
+}
+
+impl RenderOutput {
+    pub fn new(renderer: Renderer, path: &str, band: usize) -> Self {
+        Self {
+            renderer,
+            path: path.to_string(),
+            band,
+            quicklook: None,
+            quicklook_size: DEFAULT_QUICKLOOK_SIZE,
+        }
+    }
+
+    /// Also write a downsampled PNG or JPEG (chosen by extension) with a world file
+    pub fn with_quicklook(mut self, path: Option<String>, size: usize) -> Result<Self> {
+        if let Some(path) = &path {
+            quicklook_driver(path)?;
+            if size == 0 {
+                return Err(anyhow::anyhow!("The quicklook size must be at least 1 pixel"));
+            }
+        }
+        self.quicklook = path;
+        self.quicklook_size = size;
+        Ok(self)
+    }
+
+    /// Create the RGBA GeoTIFF with the georeferencing of `reference`
+    #[allow(clippy::too_many_arguments)]
+    pub fn create(
+        &self,
+        reference: &Dataset,
+        width: usize,
+        height: usize,
+        compress: &str,
+        compress_level: u8,
+        tiled: bool,
+        description: &str,
+    ) -> Result<Dataset> {
+        let mut options = OutputFormat::GTiff.creation_options(compress, compress_level, tiled, true);
+        options.push("PHOTOMETRIC=RGB".to_string());
+        options.push("ALPHA=YES".to_string());
+        let options = RasterCreationOptions::from_iter(options);
+
+        let mut dataset = OutputFormat::GTiff
+            .driver()?
+            .create_with_band_type_with_options::<u8, _>(&self.path, width, height, 4, &options)
+            .with_context(|| format!("Failed to create {}", self.path))?;
+        dataset.set_projection(&reference.projection())?;
+        dataset.set_geo_transform(&reference.geo_transform()?)?;
+        dataset.set_description(description)?;
+        Ok(dataset)
+    }
+
+    /// Render one block of calculator results into the RGBA dataset
+    pub fn write_block(
+        &self,
+        dataset: &Dataset,
+        values: &[f32],
+        shape: (usize, usize),
+        offset: (isize, isize),
+    ) -> Result<()> {
+        for (i, channel) in self.renderer.render_values(values).into_iter().enumerate() {
+            let mut buffer = Buffer::new(shape, channel);
+            dataset.rasterband(i + 1)?.write(offset, shape, &mut buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Downsample the finished RGBA GeoTIFF into the quicklook, if one was requested, through a
+    /// VRT in the scratch directory of `cache`'s run
+    pub fn write_quicklook(&self, cache: &RasterCache) -> Result<()> {
+        let Some(quicklook) = &self.quicklook else {
+            return Ok(());
+        };
+        let (driver_name, bands) = quicklook_driver(quicklook)?;
+
+        let source = Dataset::open(&self.path)?;
+        let (width, height) = source.raster_size();
+        let scale = (width.max(height) as f64 / self.quicklook_size as f64).max(1.0);
+        let ql_width = ((width as f64 / scale).round() as usize).max(1);
+        let ql_height = ((height as f64 / scale).round() as usize).max(1);
+
+        // Downsampled VRT view of the rendering, copied with the PNG/JPEG driver
+        let mut vrt = format!(
+            "<VRTDataset rasterXSize=\"{}\" rasterYSize=\"{}\">\n",
+            ql_width, ql_height
+        );
+        let projection = source.projection();
+        if !projection.is_empty() {
+            vrt.push_str(&format!("  <SRS>{}</SRS>\n", escape_xml(&projection)));
+        }
+        if let Ok(gt) = source.geo_transform() {
+            let (sx, sy) = (width as f64 / ql_width as f64, height as f64 / ql_height as f64);
+            vrt.push_str(&format!(
+                "  <GeoTransform>{}, {}, {}, {}, {}, {}</GeoTransform>\n",
+                gt[0],
+                gt[1] * sx,
+                gt[2] * sy,
+                gt[3],
+                gt[4] * sx,
+                gt[5] * sy
+            ));
+        }
+        for (band, interpretation) in ["Red", "Green", "Blue", "Alpha"].iter().take(bands).enumerate() {
+            vrt.push_str(&format!("  <VRTRasterBand dataType=\"Byte\" band=\"{}\">\n", band + 1));
+            vrt.push_str(&format!("    <ColorInterp>{}</ColorInterp>\n", interpretation));
+            vrt.push_str("    <SimpleSource resampling=\"average\">\n");
+            vrt.push_str(&format!(
+                "      <SourceFilename relativeToVRT=\"0\">{}</SourceFilename>\n",
+                escape_xml(&self.path)
+            ));
+            vrt.push_str(&format!("      <SourceBand>{}</SourceBand>\n", band + 1));
+            vrt.push_str(&format!(
+                "      <SrcRect xOff=\"0\" yOff=\"0\" xSize=\"{}\" ySize=\"{}\" />\n",
+                width, height
+            ));
+            vrt.push_str(&format!(
+                "      <DstRect xOff=\"0\" yOff=\"0\" xSize=\"{}\" ySize=\"{}\" />\n",
+                ql_width, ql_height
+            ));
+            vrt.push_str("    </SimpleSource>\n");
+            vrt.push_str("  </VRTRasterBand>\n");
+        }
+        vrt.push_str("</VRTDataset>\n");
+
+        let mut options = vec!["WORLDFILE=YES".to_string()];
+        if driver_name == "JPEG" {
+            options.push("QUALITY=90".to_string());
+        }
+        let options = RasterCreationOptions::from_iter(options);
+        let driver = DriverManager::get_driver_by_name(driver_name)?;
+
+        // A view of its own, other renderings may be copying theirs at the same time
+        let vrt_path = cache.temp_path("quicklook", "vrt");
+        gdal::vsi::create_mem_file(&vrt_path, vrt.into_bytes())?;
+        let copied = Dataset::open(&vrt_path)
+            .and_then(|view| view.create_copy(&driver, quicklook, &options).map(|_| ()));
+        gdal::vsi::unlink_mem_file(&vrt_path)?;
+        copied.with_context(|| format!("Failed to write quicklook {}", quicklook))
+    }
+}
+
+/// GDAL driver and band count of a quicklook path (JPEG has no alpha channel)
+fn quicklook_driver(path: &str) -> Result<(&'static str, usize)> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("png") => Ok(("PNG", 4)),
+        Some("jpg") | Some("jpeg") => Ok(("JPEG", 3)),
+        _ => Err(anyhow::anyhow!(
+            "Cannot determine quicklook format from '{}', use a .png, .jpg or .jpeg path",
+            path
+        )),
+    }
+}
+
+/// `<stem>_rgba.tif` next to `output`
+pub fn default_render_path(output: &str) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().map_or("output".into(), |s| s.to_string_lossy());
+    path.with_file_name(format!("{}_rgba.tif", stem))
+        .to_string_lossy()
+        .to_string()
+}
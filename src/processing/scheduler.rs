@@ -0,0 +1,151 @@
+// src/processing/scheduler.rs
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use flume::Sender;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Block readers and in-flight memory shared by every processor of a batch, so concurrent
+/// operations do not each spawn a reader per core and read their whole input ahead
+pub struct Scheduler {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    budget: Arc<MemoryBudget>,
+    block_limit: Option<u64>,
+}
+
+impl Scheduler {
+    /// `io_threads` concurrent block reads and about `max_inflight_bytes` of blocks read and not
+    /// yet processed (exceeded by at most one block per processor)
+    pub fn new(io_threads: usize, max_inflight_bytes: u64) -> Self {
+        let (jobs, receiver) = flume::unbounded::<Job>();
+        let workers = (0..io_threads.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("block-reader-{}", i))
+                    .spawn(move || {
+                        for job in receiver {
+                            // Reads report their errors themselves; this only keeps the shared
+                            // reader alive where panics unwind (debug builds, release aborts)
+                            if let Err(e) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                                let message = e
+                                    .downcast_ref::<String>()
+                                    .map(String::as_str)
+                                    .or_else(|| e.downcast_ref::<&str>().copied())
+                                    .unwrap_or("unknown error");
+                                eprintln!("Block read failed: {}", message);
+                            }
+                        }
+                    })
+                    .expect("Failed to spawn block reader thread")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            workers,
+            budget: Arc::new(MemoryBudget::new(max_inflight_bytes)),
+            block_limit: None,
+        }
+    }
+
+    /// Working set of one block of each processor, native blocks are split to fit
+    pub fn with_block_limit(mut self, bytes: Option<u64>) -> Self {
+        self.block_limit = bytes;
+        self
+    }
+
+    pub fn block_limit(&self) -> Option<u64> {
+        self.block_limit
+    }
+
+    pub fn io_threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Memory of the blocks in flight
+    pub fn budget(&self) -> &Arc<MemoryBudget> {
+        &self.budget
+    }
+
+    /// Run a block read on one of the shared readers
+    pub fn submit(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(jobs) = &self.jobs {
+            // The readers only stop once the scheduler is dropped
+            let _ = jobs.send(Box::new(job));
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Bytes that may be held at once. `try_reserve` refuses what does not fit while `reserve` never
+/// waits, so a processor holding nothing always proceeds instead of waiting on memory held by
+/// work suspended beneath it on the same rayon thread
+#[derive(Debug)]
+pub struct MemoryBudget {
+    capacity: u64,
+    used: Mutex<u64>,
+}
+
+impl MemoryBudget {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            used: Mutex::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn used(&self) -> u64 {
+        *self.used.lock().unwrap()
+    }
+
+    /// Reserve `bytes`, beyond the capacity if needed
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> Reservation {
+        *self.used.lock().unwrap() += bytes;
+        Reservation { budget: Arc::clone(self), bytes }
+    }
+
+    /// Reserve `bytes` if they fit in the capacity
+    pub fn try_reserve(self: &Arc<Self>, bytes: u64) -> Option<Reservation> {
+        let mut used = self.used.lock().unwrap();
+        if *used + bytes > self.capacity {
+            return None;
+        }
+        *used += bytes;
+        Some(Reservation { budget: Arc::clone(self), bytes })
+    }
+}
+
+/// Memory reserved in a budget, given back when dropped
+#[derive(Debug)]
+pub struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: u64,
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.bytes;
+    }
+}
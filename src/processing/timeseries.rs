@@ -0,0 +1,455 @@
+// src/processing/timeseries.rs
+use crate::processing::parallel::{IndexCalculator, NODATA_VALUE_FLOAT};
+use crate::utils::dates::{self, Date};
+use crate::utils::gdal_ext::TypedBuffer;
+use anyhow::Result;
+use gdal::{Dataset, Metadata};
+use rayon::prelude::*;
+
+/// Per-pixel reduction over a time series
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    Mean,
+    Median,
+    /// Percentile between 0 and 100
+    Percentile(f32),
+    /// Number of valid observations
+    Count,
+    /// Ordinary least squares slope per time unit
+    Slope,
+    /// Theil-Sen slope (median of pairwise slopes) per time unit
+    SenSlope,
+    /// Mann-Kendall tau, between -1 and 1
+    MannKendallTau,
+    /// Two-sided Mann-Kendall p-value (significance of the trend)
+    MannKendallP,
+}
+
+impl Statistic {
+    /// Parse a statistic name: mean, median, pNN, count, slope, sen_slope, mk_tau, mk_p
+    pub fn from_name(name: &str) -> Result<Self> {
+        let name = name.trim().to_lowercase();
+        match name.as_str() {
+            "mean" => Ok(Statistic::Mean),
+            "median" => Ok(Statistic::Median),
+            "count" => Ok(Statistic::Count),
+            "slope" => Ok(Statistic::Slope),
+            "sen_slope" | "sen" => Ok(Statistic::SenSlope),
+            "mk_tau" => Ok(Statistic::MannKendallTau),
+            "mk_p" | "mk" => Ok(Statistic::MannKendallP),
+            _ => {
+                let percentile = name
+                    .strip_prefix('p')
+                    .and_then(|p| p.parse::<f32>().ok())
+                    .filter(|p| (0.0..=100.0).contains(p));
+                percentile.map(Statistic::Percentile).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Unknown statistic '{}'. Expected one of: mean, median, pNN, count, slope, sen_slope, mk_tau, mk_p",
+                        name
+                    )
+                })
+            }
+        }
+    }
+
+    /// Band description of the statistic
+    pub fn description(&self, time_unit: &str) -> String {
+        match self {
+            Statistic::Mean => "mean".to_string(),
+            Statistic::Median => "median".to_string(),
+            Statistic::Percentile(p) => format!("percentile {}", p),
+            Statistic::Count => "valid observations".to_string(),
+            Statistic::Slope => format!("linear slope (per {})", time_unit),
+            Statistic::SenSlope => format!("Sen's slope (per {})", time_unit),
+            Statistic::MannKendallTau => "Mann-Kendall tau".to_string(),
+            Statistic::MannKendallP => "Mann-Kendall p-value".to_string(),
+        }
+    }
+}
+
+/// Scale, offset and nodata of one raster in a stack, used to recover physical values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackInput {
+    pub nodata: Option<f32>,
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl StackInput {
+    /// Read nodata and scaling of the first band, including the SCALE/OFFSET
+    /// metadata written by raster-calc for fixed-point outputs
+    pub fn from_path(path: &str) -> Result<Self> {
+        let dataset = Dataset::open(path)?;
+        let band = dataset.rasterband(1)?;
+
+        let metadata_value = |key: &str| {
+            band.metadata_item(key, "")
+                .and_then(|v| v.trim().parse::<f64>().ok())
+        };
+        let scale = band.scale().or_else(|| metadata_value("SCALE")).unwrap_or(1.0);
+        let offset = band.offset().or_else(|| metadata_value("OFFSET")).unwrap_or(0.0);
+
+        Ok(Self {
+            nodata: band.no_data_value().map(|v| v as f32),
+            scale: scale as f32,
+            offset: offset as f32,
+        })
+    }
+
+    /// Physical value of a raw pixel, `None` for nodata
+    pub fn value(&self, raw: f32) -> Option<f32> {
+        if raw.is_nan() || self.nodata == Some(raw) || raw == NODATA_VALUE_FLOAT {
+            None
+        } else {
+            Some(raw * self.scale + self.offset)
+        }
+    }
+}
+
+/// Acquisition date of every raster of a stack
+///
+/// Uses the given dates, else dates from metadata or file names.
+/// Returns `None` when the date of some input is unknown.
+pub fn stack_dates(paths: &[String], dates: &[String]) -> Result<Option<Vec<Date>>> {
+    if dates.is_empty() {
+        return Ok(paths.iter().map(|p| dates::acquisition_date(p)).collect());
+    }
+
+    if dates.len() != paths.len() {
+        return Err(anyhow::anyhow!(
+            "Got {} dates for {} inputs",
+            dates.len(),
+            paths.len()
+        ));
+    }
+    let dates = dates.iter().map(|d| Date::parse(d)).collect::<Result<Vec<_>>>()?;
+    Ok(Some(dates))
+}
+
+/// Time coordinate of every raster of a stack
+///
+/// Uses the acquisition dates (see [`stack_dates`]); falls back to
+/// the observation number when some dates are unknown.
+/// Returns the times and their unit ("year" or "observation").
+pub fn stack_times(paths: &[String], dates: &[String]) -> Result<(Vec<f64>, &'static str)> {
+    match stack_dates(paths, dates)? {
+        Some(found) => Ok((found.iter().map(Date::decimal_year).collect(), "year")),
+        None => {
+            eprintln!("Warning: acquisition dates not found for all inputs, using observation order");
+            Ok(((0..paths.len()).map(|i| i as f64).collect(), "observation"))
+        }
+    }
+}
+
+/// Set up a [`TimeSeriesStats`] calculator for the given stack paths, statistic names and dates
+pub fn from_inputs(paths: &[String], statistics: &[String], dates: &[String]) -> Result<TimeSeriesStats> {
+    if paths.len() < 2 {
+        return Err(anyhow::anyhow!("A time series needs at least 2 inputs"));
+    }
+
+    let statistics = statistics
+        .iter()
+        .map(|s| Statistic::from_name(s))
+        .collect::<Result<Vec<_>>>()?;
+    let inputs = paths
+        .iter()
+        .map(|p| StackInput::from_path(p))
+        .collect::<Result<Vec<_>>>()?;
+    let (times, time_unit) = stack_times(paths, dates)?;
+
+    Ok(TimeSeriesStats::new(statistics, times, inputs, time_unit, None))
+}
+
+/// Per-pixel statistics and trends over a stack of index rasters, one input per date
+pub struct TimeSeriesStats {
+    statistics: Vec<Statistic>,
+    times: Vec<f64>,
+    inputs: Vec<StackInput>,
+    time_unit: String,
+    name: String,
+}
+
+impl TimeSeriesStats {
+    pub fn new(
+        statistics: Vec<Statistic>,
+        times: Vec<f64>,
+        inputs: Vec<StackInput>,
+        time_unit: &str,
+        name: Option<String>,
+    ) -> Self {
+        Self {
+            statistics,
+            times,
+            inputs,
+            time_unit: time_unit.to_string(),
+            name: name.unwrap_or_else(|| "Time series".to_string()),
+        }
+    }
+
+    fn reduce(&self, series: &[(f64, f32)], statistic: Statistic) -> f32 {
+        let n = series.len();
+        let value = match statistic {
+            Statistic::Count => return n as f32,
+            _ if n == 0 => return NODATA_VALUE_FLOAT,
+            Statistic::Mean => series.iter().map(|&(_, v)| v as f64).sum::<f64>() / n as f64,
+            Statistic::Median => {
+                let values = sorted_values(series);
+                percentile(&values, 50.0)
+            }
+            Statistic::Percentile(p) => {
+                let values = sorted_values(series);
+                percentile(&values, p as f64)
+            }
+            Statistic::Slope => match linear_slope(series) {
+                Some(slope) => slope,
+                None => return NODATA_VALUE_FLOAT,
+            },
+            Statistic::SenSlope => match sen_slope(series) {
+                Some(slope) => slope,
+                None => return NODATA_VALUE_FLOAT,
+            },
+            Statistic::MannKendallTau | Statistic::MannKendallP => match mann_kendall(series) {
+                Some((tau, _)) if statistic == Statistic::MannKendallTau => tau,
+                Some((_, p)) => p,
+                None => return NODATA_VALUE_FLOAT,
+            },
+        };
+        value as f32
+    }
+
+    fn statistics_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        stack_bands(inputs, &self.inputs, &self.times, self.statistics.len(), |series, results| {
+            for (result, &statistic) in results.iter_mut().zip(&self.statistics) {
+                *result = self.reduce(&series, statistic);
+            }
+        })
+    }
+}
+
+/// `bands` output bands computed per pixel of a stack: `per_pixel` gets the valid
+/// (time, physical value) observations in stack order and fills the pixel's value of each
+/// band, those it leaves untouched are nodata
+pub fn stack_bands<F>(
+    inputs: &[TypedBuffer],
+    stack: &[StackInput],
+    times: &[f64],
+    bands: usize,
+    per_pixel: F,
+) -> Vec<TypedBuffer>
+where
+    F: Fn(Vec<(f64, f32)>, &mut [f32]) + Sync,
+{
+    let data: Vec<&[f32]> = inputs
+        .iter()
+        .map(|b| match b {
+            TypedBuffer::F32(buf) => buf.data(),
+            _ => panic!("Unsupported input types for a raster stack"),
+        })
+        .collect();
+    let shape = inputs[0].shape();
+    let pixels = shape.0 * shape.1;
+
+    // Pixel-interleaved results, split into bands afterwards
+    let mut results = vec![NODATA_VALUE_FLOAT; pixels * bands];
+    results
+        .par_chunks_mut(bands)
+        .enumerate()
+        .for_each(|(i, pixel_results)| {
+            let series = (0..data.len())
+                .filter_map(|d| stack[d].value(data[d][i]).map(|v| (times[d], v)))
+                .collect();
+            per_pixel(series, pixel_results);
+        });
+
+    (0..bands)
+        .map(|b| {
+            let band: Vec<f32> = results.iter().skip(b).step_by(bands).copied().collect();
+            TypedBuffer::F32(gdal::raster::Buffer::new(shape, band))
+        })
+        .collect()
+}
+
+impl IndexCalculator for TimeSeriesStats {
+    fn calculate(&self, inputs: &[TypedBuffer]) -> TypedBuffer {
+        self.statistics_bands(inputs).swap_remove(0)
+    }
+
+    fn required_bands(&self) -> usize {
+        self.times.len()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn applies_input_scaling(&self) -> bool {
+        true
+    }
+
+    fn output_bands(&self) -> Vec<String> {
+        self.statistics
+            .iter()
+            .map(|s| s.description(&self.time_unit))
+            .collect()
+    }
+
+    fn calculate_bands(&self, inputs: &[TypedBuffer]) -> Vec<TypedBuffer> {
+        self.statistics_bands(inputs)
+    }
+
+    fn is_scaled_band(&self, band: usize) -> bool {
+        self.statistics[band] != Statistic::Count
+    }
+}
+
+fn sorted_values(series: &[(f64, f32)]) -> Vec<f64> {
+    let mut values: Vec<f64> = series.iter().map(|&(_, v)| v as f64).collect();
+    values.sort_by(|a, b| a.total_cmp(b));
+    values
+}
+
+/// Percentile of sorted values with linear interpolation between ranks
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.len() == 1 {
+        return sorted[0];
+    }
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Ordinary least squares slope of value against time
+pub fn linear_slope(series: &[(f64, f32)]) -> Option<f64> {
+    let n = series.len() as f64;
+    if series.len() < 2 {
+        return None;
+    }
+    let mean_t = series.iter().map(|&(t, _)| t).sum::<f64>() / n;
+    let mean_v = series.iter().map(|&(_, v)| v as f64).sum::<f64>() / n;
+    let (mut cov, mut var) = (0.0, 0.0);
+    for &(t, v) in series {
+        cov += (t - mean_t) * (v as f64 - mean_v);
+        var += (t - mean_t) * (t - mean_t);
+    }
+    if var == 0.0 {
+        None
+    } else {
+        Some(cov / var)
+    }
+}
+
+/// Theil-Sen slope: median of the slopes between all pairs of observations
+pub fn sen_slope(series: &[(f64, f32)]) -> Option<f64> {
+    let mut slopes = Vec::with_capacity(series.len() * series.len() / 2);
+    for (i, &(ti, vi)) in series.iter().enumerate() {
+        for &(tj, vj) in &series[i + 1..] {
+            if tj != ti {
+                slopes.push((vj as f64 - vi as f64) / (tj - ti));
+            }
+        }
+    }
+    if slopes.is_empty() {
+        return None;
+    }
+    slopes.sort_by(|a, b| a.total_cmp(b));
+    Some(percentile(&slopes, 50.0))
+}
+
+/// Mann-Kendall trend test, returns Kendall's tau and the two-sided p-value.
+/// Needs at least 3 observations; ties are not corrected for
+pub fn mann_kendall(series: &[(f64, f32)]) -> Option<(f64, f64)> {
+    let n = series.len();
+    if n < 3 {
+        return None;
+    }
+
+    let mut s = 0.0;
+    for (i, &(ti, vi)) in series.iter().enumerate() {
+        for &(tj, vj) in &series[i + 1..] {
+            // Orient every pair in time so input order does not matter
+            let time_sign = (tj - ti).signum();
+            let value_sign = if vj > vi {
+                1.0
+            } else if vj < vi {
+                -1.0
+            } else {
+                0.0
+            };
+            s += time_sign * value_sign;
+        }
+    }
+
+    let n = n as f64;
+    let variance = n * (n - 1.0) * (2.0 * n + 5.0) / 18.0;
+    let z = if s > 0.0 {
+        (s - 1.0) / variance.sqrt()
+    } else if s < 0.0 {
+        (s + 1.0) / variance.sqrt()
+    } else {
+        0.0
+    };
+
+    let tau = s / (n * (n - 1.0) / 2.0);
+    let p = 2.0 * (1.0 - normal_cdf(z.abs()));
+    Some((tau, p.clamp(0.0, 1.0)))
+}
+
+/// Linear interpolation of a time-sorted series at time `t`, clamped to the end values
+pub fn interpolate(series: &[(f64, f32)], t: f64) -> f64 {
+    let upper = series.partition_point(|&(ti, _)| ti < t);
+    if upper == 0 {
+        return series[0].1 as f64;
+    }
+    if upper == series.len() {
+        return series[series.len() - 1].1 as f64;
+    }
+    let (t0, v0) = series[upper - 1];
+    let (t1, v1) = series[upper];
+    if t1 == t0 {
+        return v1 as f64;
+    }
+    v0 as f64 + (v1 as f64 - v0 as f64) * (t - t0) / (t1 - t0)
+}
+
+/// Solve the square linear system `a x = b` by Gaussian elimination with partial pivoting.
+/// Returns `None` for singular systems
+pub fn solve_linear(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            let (pivot_rows, rows) = a.split_at_mut(row);
+            for (value, pivot_value) in rows[0][col..].iter_mut().zip(&pivot_rows[col][col..]) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Standard normal cumulative distribution (Abramowitz & Stegun 7.1.26)
+fn normal_cdf(z: f64) -> f64 {
+    let x = z / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-x * x).exp();
+    let erf = if x >= 0.0 { erf } else { -erf };
+    0.5 * (1.0 + erf)
+}
@@ -1,21 +1,64 @@
 // src/utils/cache.rs
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use gdal::Dataset;
 use anyhow::Result;
 
-/// Thread-safe cache for GDAL datasets
+use crate::utils::output::VSIMEM_PREFIX;
+
+/// Products and STAC items opened by a run, by type and path
+type Shared = HashMap<(TypeId, String), Arc<dyn Any + Send + Sync>>;
+
+/// Thread-safe cache for GDAL datasets, and the products, STAC items and `/vsimem/` VRTs of
+/// one run: runs in the same process each use their own cache and never see each other's files
 pub struct RasterCache {
     datasets: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<Dataset>>>>>,
+    /// Most datasets kept open, `None` for no limit
+    capacity: Option<usize>,
+    /// Last use of each dataset, for evicting the least recently used
+    used: Mutex<HashMap<PathBuf, u64>>,
+    clock: AtomicU64,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    /// Unique in the process, names the run's directory in `/vsimem/`
+    run_id: u64,
+    shared: Mutex<Shared>,
+    /// VRT last written for each key, unlinked when replaced or when the cache is dropped
+    vrts: Mutex<HashMap<u64, String>>,
+    temp_files: AtomicUsize,
 }
 
 impl RasterCache {
     pub fn new() -> Self {
+        static NEXT_RUN: AtomicU64 = AtomicU64::new(1);
         Self {
             datasets: Arc::new(Mutex::new(HashMap::new())),
+            capacity: None,
+            used: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            run_id: NEXT_RUN.fetch_add(1, Ordering::Relaxed),
+            shared: Mutex::new(HashMap::new()),
+            vrts: Mutex::new(HashMap::new()),
+            temp_files: AtomicUsize::new(0),
         }
     }
+
+    /// Cache keeping at most `capacity` datasets open: opening another closes the least
+    /// recently used one not being read (if all are, the limit is exceeded until they are not)
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut cache = Self::new();
+        cache.capacity = Some(capacity.max(1));
+        cache
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
     
     pub fn get_dataset<P: AsRef<Path>>(&self, path: P) -> Result<Arc<Mutex<Dataset>>> {
         let path_buf = path.as_ref().to_path_buf();
@@ -23,23 +66,133 @@ impl RasterCache {
         let mut cache = self.datasets.lock().unwrap();
         
         if let Some(dataset) = cache.get(&path_buf) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            self.touch(&path_buf);
             return Ok(Arc::clone(dataset));
         }
+        self.misses.fetch_add(1, Ordering::Relaxed);
         
         // Not in cache, open and add it
         let dataset = Arc::new(Mutex::new(Dataset::open(path.as_ref())?));
-        cache.insert(path_buf, Arc::clone(&dataset));
+        if let Some(capacity) = self.capacity {
+            self.evict(&mut cache, capacity - 1);
+        }
+        cache.insert(path_buf.clone(), Arc::clone(&dataset));
+        self.touch(&path_buf);
         
         Ok(dataset)
     }
+
+    fn touch(&self, path: &Path) {
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.used.lock().unwrap().insert(path.to_path_buf(), tick);
+    }
+
+    /// Close least recently used datasets nobody else holds until at most `keep` are open
+    fn evict(&self, cache: &mut HashMap<PathBuf, Arc<Mutex<Dataset>>>, keep: usize) {
+        let mut used = self.used.lock().unwrap();
+        while cache.len() > keep {
+            let idle = cache
+                .iter()
+                .filter(|(_, dataset)| Arc::strong_count(dataset) == 1)
+                .min_by_key(|(path, _)| used.get(*path).copied().unwrap_or(0))
+                .map(|(path, _)| path.clone());
+            let Some(path) = idle else {
+                break;
+            };
+            cache.remove(&path);
+            used.remove(&path);
+        }
+    }
     
+    /// True if the dataset of `path` is open in the cache
+    pub fn contains<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.datasets.lock().unwrap().contains_key(path.as_ref())
+    }
+
+    /// Drop the dataset of `path`, e.g. before deleting the file
+    pub fn remove<P: AsRef<Path>>(&self, path: P) {
+        let mut cache = self.datasets.lock().unwrap();
+        cache.remove(path.as_ref());
+        self.used.lock().unwrap().remove(path.as_ref());
+    }
+
     pub fn clear(&self) {
         let mut cache = self.datasets.lock().unwrap();
         cache.clear();
+        self.used.lock().unwrap().clear();
     }
     
+    /// Requests served from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Requests that opened a dataset
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
     pub fn len(&self) -> usize {
         let cache = self.datasets.lock().unwrap();
         cache.len()
     }
+
+    /// `/vsimem/` directory of this run's VRTs and intermediate outputs
+    pub fn scratch_dir(&self) -> String {
+        format!("{}raster-calc/run-{}", VSIMEM_PREFIX, self.run_id)
+    }
+
+    /// A new path in the scratch directory, e.g. for a VRT that is unlinked after use
+    pub fn temp_path(&self, prefix: &str, extension: &str) -> String {
+        let n = self.temp_files.fetch_add(1, Ordering::Relaxed);
+        format!("{}/{}_{}.{}", self.scratch_dir(), prefix, n, extension)
+    }
+
+    /// The `T` of `path` opened by an earlier call, or opened now with `open` and kept for later ones
+    pub fn shared<T: Any + Send + Sync>(&self, path: &str, open: impl FnOnce() -> Result<T>) -> Result<Arc<T>> {
+        let key = (TypeId::of::<T>(), path.to_string());
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(opened) = shared.get(&key) {
+            if let Ok(opened) = Arc::clone(opened).downcast::<T>() {
+                return Ok(opened);
+            }
+        }
+
+        let opened = Arc::new(open()?);
+        shared.insert(key, Arc::clone(&opened) as Arc<dyn Any + Send + Sync>);
+        Ok(opened)
+    }
+
+    /// Write the VRT built by `build` to `<scratch dir>/<name>.vrt` unless the VRT last written
+    /// for `key` already has that name, unlinking the one it replaces
+    pub fn build_vrt(&self, key: u64, name: &str, build: impl FnOnce() -> Result<String>) -> Result<String> {
+        let vrt_path = format!("{}/{}.vrt", self.scratch_dir(), name);
+
+        let mut vrts = self.vrts.lock().unwrap();
+        if vrts.get(&key) != Some(&vrt_path) {
+            gdal::vsi::create_mem_file(&vrt_path, build()?.into_bytes())?;
+            if let Some(stale) = vrts.insert(key, vrt_path.clone()) {
+                self.remove(&stale);
+                let _ = gdal::vsi::unlink_mem_file(&stale);
+            }
+        }
+
+        Ok(vrt_path)
+    }
+}
+
+impl Default for RasterCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RasterCache {
+    fn drop(&mut self) {
+        self.clear();
+        for vrt in self.vrts.lock().unwrap().values() {
+            let _ = gdal::vsi::unlink_mem_file(vrt);
+        }
+    }
 }
\ No newline at end of file
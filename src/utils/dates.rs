@@ -0,0 +1,156 @@
+// src/utils/dates.rs
+use anyhow::Result;
+use gdal::{Dataset, Metadata};
+
+/// Calendar date of an acquisition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Result<Self> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return Err(anyhow::anyhow!("Invalid date: {:04}-{:02}-{:02}", year, month, day));
+        }
+        Ok(Self { year, month, day })
+    }
+
+    /// Parse `YYYY-MM-DD`, `YYYYMMDD` or `YYYY:MM:DD` (optionally followed by a time)
+    pub fn parse(value: &str) -> Result<Self> {
+        let digits: String = value
+            .trim()
+            .chars()
+            .take(10)
+            .filter(|c| c.is_ascii_digit())
+            .collect();
+
+        if digits.len() < 8 {
+            return Err(anyhow::anyhow!("Cannot parse date '{}', expected YYYY-MM-DD", value));
+        }
+
+        Self::new(
+            digits[0..4].parse()?,
+            digits[4..6].parse()?,
+            digits[6..8].parse()?,
+        )
+    }
+
+    /// Find the first `YYYYMMDD` or `YYYY-MM-DD` date in a file name
+    pub fn from_path(path: &str) -> Option<Self> {
+        let name = std::path::Path::new(path).file_name()?.to_string_lossy().to_string();
+        let bytes = name.as_bytes();
+
+        for start in 0..bytes.len() {
+            // Dates must not be part of a longer number
+            if start > 0 && bytes[start - 1].is_ascii_digit() {
+                continue;
+            }
+            for len in [10, 8] {
+                let Some(candidate) = name.get(start..start + len) else {
+                    continue;
+                };
+                let compact = len == 8 && candidate.bytes().all(|b| b.is_ascii_digit());
+                let dashed = len == 10
+                    && candidate.bytes().enumerate().all(|(i, b)| {
+                        if i == 4 || i == 7 {
+                            b == b'-'
+                        } else {
+                            b.is_ascii_digit()
+                        }
+                    });
+                let followed_by_digit = bytes
+                    .get(start + len)
+                    .is_some_and(|b| b.is_ascii_digit());
+
+                if (compact || dashed) && !followed_by_digit {
+                    if let Ok(date) = Self::parse(candidate) {
+                        if (1950..=2100).contains(&date.year) {
+                            return Some(date);
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Days since 1970-01-01
+    pub fn days_since_epoch(&self) -> i64 {
+        // Howard Hinnant's days_from_civil
+        let m = self.month as i64;
+        let y = if m <= 2 { self.year as i64 - 1 } else { self.year as i64 };
+        let era = (if y >= 0 { y } else { y - 399 }) / 400;
+        let yoe = y - era * 400;
+        let mp = if m > 2 { m - 3 } else { m + 9 };
+        let doy = (153 * mp + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    /// Date from days since 1970-01-01
+    pub fn from_days_since_epoch(days: i64) -> Self {
+        // Howard Hinnant's civil_from_days
+        let z = days + 719468;
+        let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        Self { year, month, day }
+    }
+
+    /// Day of year, 1 for January 1st
+    pub fn day_of_year(&self) -> u32 {
+        (self.days_since_epoch() - Date { year: self.year, month: 1, day: 1 }.days_since_epoch())
+            as u32
+            + 1
+    }
+
+    /// Date as a fractional year (e.g. 2024.5 in early July)
+    pub fn decimal_year(&self) -> f64 {
+        let days_in_year = if is_leap_year(self.year) { 366.0 } else { 365.0 };
+        self.year as f64 + (self.day_of_year() - 1) as f64 / days_in_year
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Acquisition date of a raster, from its metadata or else from its file name
+pub fn acquisition_date(path: &str) -> Option<Date> {
+    if let Ok(dataset) = Dataset::open(path) {
+        for key in ["ACQUISITION_DATE", "DATETIME", "datetime"] {
+            if let Some(value) = dataset.metadata_item(key, "") {
+                if let Ok(date) = Date::parse(&value) {
+                    return Some(date);
+                }
+            }
+        }
+    }
+
+    Date::from_path(path)
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
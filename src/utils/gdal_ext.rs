@@ -285,6 +285,19 @@ impl TypedBuffer {
             TypedBuffer::F64(buf) => buf.shape(),
         }
     }
+
+    /// Size of the pixel data in bytes
+    #[must_use]
+    pub fn byte_size(&self) -> usize {
+        let (width, height) = self.shape();
+        let pixel_size = match self {
+            TypedBuffer::U8(_) | TypedBuffer::I8(_) => 1,
+            TypedBuffer::U16(_) | TypedBuffer::I16(_) => 2,
+            TypedBuffer::U32(_) | TypedBuffer::I32(_) | TypedBuffer::F32(_) => 4,
+            TypedBuffer::U64(_) | TypedBuffer::I64(_) | TypedBuffer::F64(_) => 8,
+        };
+        width * height * pixel_size
+    }
 }
 
 pub trait RasterBandExt {
@@ -338,3 +351,20 @@ impl RasterBandExt for RasterBand<'_> {
         }
     }
 }
+
+/// Read a whole file, also through GDAL virtual file systems such as `/vsizip/`
+pub fn read_vsi_file(path: &str) -> anyhow::Result<Vec<u8>> {
+    let c_path = std::ffi::CString::new(path)?;
+    let mut data: *mut u8 = std::ptr::null_mut();
+    let mut size: gdal_sys::vsi_l_offset = 0;
+    let ok = unsafe {
+        gdal_sys::VSIIngestFile(std::ptr::null_mut(), c_path.as_ptr(), &mut data, &mut size, -1)
+    };
+    if ok == 0 || data.is_null() {
+        return Err(anyhow::anyhow!("Failed to read {}", path));
+    }
+
+    let bytes = unsafe { std::slice::from_raw_parts(data, size as usize) }.to_vec();
+    unsafe { gdal_sys::VSIFree(data.cast()) };
+    Ok(bytes)
+}
@@ -0,0 +1,372 @@
+// src/utils/landsat.rs
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::utils::cache::RasterCache;
+
+const REFLECTANCE_L2: &str = "LEVEL2_SURFACE_REFLECTANCE_PARAMETERS";
+const REFLECTANCE_L1: &str = "LEVEL1_RADIOMETRIC_RESCALING";
+
+/// Documented Collection 2 surface reflectance factors, used when the MTL lacks them
+const SR_SCALE: f32 = 2.75e-5;
+const SR_OFFSET: f32 = -0.2;
+
+/// Band roles and their band numbers on OLI (Landsat 8/9) and TM/ETM+ (Landsat 4-7)
+const ROLES: [(&str, u32, u32); 11] = [
+    ("coastal", 1, 0),
+    ("blue", 2, 1),
+    ("green", 3, 2),
+    ("red", 4, 3),
+    ("nir", 5, 4),
+    ("swir1", 6, 5),
+    ("swir2", 7, 7),
+    ("pan", 8, 8),
+    ("cirrus", 9, 0),
+    ("thermal", 10, 6),
+    ("tirs2", 11, 0),
+];
+
+/// Metadata of a Landsat Collection 2 scene, read from its `_MTL.txt` or `_MTL.json` file
+#[derive(Debug, Clone, Default)]
+pub struct Mtl {
+    /// Values per group, e.g. `IMAGE_ATTRIBUTES` -> `SUN_ELEVATION`
+    groups: HashMap<String, HashMap<String, String>>,
+}
+
+impl Mtl {
+    /// Read an MTL file. Values are kept per innermost group, since Level-2 files repeat
+    /// keys such as `REFLECTANCE_MULT_BAND_4` in their Level-1 group.
+    pub fn from_path(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read Landsat metadata {}", path))?;
+        let mut groups: HashMap<String, HashMap<String, String>> = HashMap::new();
+
+        if path.to_lowercase().ends_with(".json") {
+            let json: Value = serde_json::from_str(&text)
+                .with_context(|| format!("Invalid Landsat metadata {}", path))?;
+            flatten_json("", &json, &mut groups);
+            return Ok(Self { groups });
+        }
+
+        let mut stack: Vec<String> = Vec::new();
+        for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+            let (key, value) = (key.trim(), value.trim().trim_matches('"'));
+            match key {
+                "GROUP" => stack.push(value.to_string()),
+                "END_GROUP" => {
+                    stack.pop();
+                }
+                _ => {
+                    let group = stack.last().cloned().unwrap_or_default();
+                    groups
+                        .entry(group)
+                        .or_default()
+                        .insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+        Ok(Self { groups })
+    }
+
+    /// Value of `key` in `group`
+    pub fn get(&self, group: &str, key: &str) -> Option<&str> {
+        self.groups.get(group)?.get(key).map(String::as_str)
+    }
+
+    /// Value of `key` in any group
+    pub fn find(&self, key: &str) -> Option<&str> {
+        self.groups
+            .values()
+            .find_map(|values| values.get(key))
+            .map(String::as_str)
+    }
+
+    fn get_f32(&self, group: &str, key: &str) -> Option<f32> {
+        self.get(group, key)?.parse().ok()
+    }
+}
+
+/// A Landsat Collection 2 Level-1 or Level-2 product directory
+#[derive(Debug)]
+pub struct LandsatProduct {
+    pub directory: String,
+    /// e.g. `LANDSAT_8`
+    pub spacecraft: String,
+    /// e.g. `L2SP` or `L1TP`
+    pub processing_level: String,
+    mtl: Mtl,
+}
+
+impl LandsatProduct {
+    /// Open a product from its directory or its MTL file (JSON preferred over txt)
+    pub fn open(path: &str) -> Result<Self> {
+        let path = path.trim_end_matches(['/', '\\']);
+        let (directory, mtl_path) = if is_mtl_path(path) {
+            let directory = Path::new(path)
+                .parent()
+                .map_or(".".to_string(), |p| p.to_string_lossy().to_string());
+            (directory, path.to_string())
+        } else {
+            let mut candidates: Vec<String> = std::fs::read_dir(path)
+                .with_context(|| format!("Landsat product {} not found", path))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().to_string_lossy().to_string())
+                .filter(|p| is_mtl_path(p))
+                .collect();
+            // JSON sorts before txt
+            candidates.sort_by_key(|p| !p.to_lowercase().ends_with(".json"));
+            let mtl_path = candidates
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("No _MTL.json or _MTL.txt file in {}", path))?;
+            (path.to_string(), mtl_path)
+        };
+
+        // Fall back to the product id (LC08_L2SP_...) for abridged metadata
+        let mtl = Mtl::from_path(&mtl_path)?;
+        let product_id = Path::new(&mtl_path)
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().to_uppercase());
+        let spacecraft = match mtl.find("SPACECRAFT_ID") {
+            Some(spacecraft) => spacecraft.to_string(),
+            None => format!("LANDSAT_{}", product_id.get(3..4).unwrap_or("8")),
+        };
+        let processing_level = match mtl.find("PROCESSING_LEVEL") {
+            Some(level) => level.to_string(),
+            None => product_id.get(5..9).unwrap_or("L1TP").to_string(),
+        };
+
+        Ok(Self {
+            directory,
+            spacecraft,
+            processing_level,
+            mtl,
+        })
+    }
+
+    /// True for Level-2 (surface reflectance) products
+    pub fn is_level2(&self) -> bool {
+        self.processing_level.starts_with("L2")
+    }
+
+    /// True for Landsat 8/9 (OLI/TIRS band numbering)
+    fn is_oli(&self) -> bool {
+        matches!(self.spacecraft.as_str(), "LANDSAT_8" | "LANDSAT_9")
+    }
+
+    /// Band number of a band name (`B4`, `SR_B4`, `ST_B10`) or role (`red`, `nir`, `swir1`)
+    pub fn band_number(&self, band: &str) -> Result<u32> {
+        let upper = band.to_uppercase();
+        let number = upper
+            .strip_prefix("SR_")
+            .or_else(|| upper.strip_prefix("ST_"))
+            .unwrap_or(&upper)
+            .strip_prefix('B')
+            .and_then(|n| n.parse().ok());
+        if let Some(number) = number {
+            return Ok(number);
+        }
+
+        let lower = band.to_lowercase();
+        ROLES
+            .iter()
+            .find(|(role, _, _)| *role == lower)
+            .map(|(_, oli, tm)| if self.is_oli() { *oli } else { *tm })
+            .filter(|number| *number > 0)
+            .ok_or_else(|| anyhow::anyhow!("Band '{}' not available on {}", band, self.spacecraft))
+    }
+
+    /// Path of a band (number or role) or of the `QA_PIXEL` quality band
+    pub fn band_path(&self, band: &str) -> Result<String> {
+        let keys = if band.eq_ignore_ascii_case("QA_PIXEL") {
+            vec!["FILE_NAME_QUALITY_L1_PIXEL".to_string()]
+        } else {
+            let number = self.band_number(band)?;
+            vec![
+                format!("FILE_NAME_BAND_{}", number),
+                format!("FILE_NAME_BAND_ST_B{}", number),
+            ]
+        };
+
+        let file_name = keys
+            .iter()
+            .find_map(|key| self.mtl.get("PRODUCT_CONTENTS", key))
+            .ok_or_else(|| {
+                anyhow::anyhow!("Band '{}' not listed in the metadata of {}", band, self.directory)
+            })?;
+        Ok(Path::new(&self.directory)
+            .join(file_name)
+            .to_string_lossy()
+            .to_string())
+    }
+
+    /// Scale and offset turning digital numbers of a band into reflectance: the surface
+    /// reflectance factors of Level-2 products, or the TOA reflectance rescaling of Level-1
+    /// products corrected for the sun elevation. `None` for thermal and quality bands.
+    pub fn reflectance_scaling(&self, band: &str) -> Option<(f32, f32)> {
+        let number = self.band_number(band).ok()?;
+        let mult = format!("REFLECTANCE_MULT_BAND_{}", number);
+        let add = format!("REFLECTANCE_ADD_BAND_{}", number);
+
+        if self.is_level2() {
+            let scale = self.mtl.get_f32(REFLECTANCE_L2, &mult);
+            let offset = self.mtl.get_f32(REFLECTANCE_L2, &add);
+            return match (scale, offset) {
+                (Some(scale), offset) => Some((scale, offset.unwrap_or(0.0))),
+                // Older files without the group: documented factors for reflective bands
+                (None, _) if self.mtl.get_f32(REFLECTANCE_L1, &mult).is_some() => {
+                    Some((SR_SCALE, SR_OFFSET))
+                }
+                (None, _) => None,
+            };
+        }
+
+        let scale = self.mtl.get_f32(REFLECTANCE_L1, &mult)?;
+        let offset = self.mtl.get_f32(REFLECTANCE_L1, &add).unwrap_or(0.0);
+        let sun_elevation = self.mtl.get_f32("IMAGE_ATTRIBUTES", "SUN_ELEVATION")?;
+        let sine = sun_elevation.to_radians().sin();
+        if sine <= 0.0 {
+            return None;
+        }
+        Some((scale / sine, offset / sine))
+    }
+}
+
+/// True for `_MTL.txt` / `_MTL.json` paths
+fn is_mtl_path(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    lower.ends_with("_mtl.txt") || lower.ends_with("_mtl.json")
+}
+
+/// True for Landsat product directories (`LC08_L2SP_...`) and MTL files, not STAC items
+/// such as `LC08_L2SP_..._SR_stac.json`
+pub fn is_product_path(path: &str) -> bool {
+    if is_mtl_path(path) {
+        return true;
+    }
+    let name = Path::new(path.trim_end_matches(['/', '\\']))
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().to_uppercase());
+    if name.ends_with(".JSON") {
+        return false;
+    }
+    let bytes = name.as_bytes();
+    bytes.len() > 9
+        && bytes[0] == b'L'
+        && b"COTEM".contains(&bytes[1])
+        && bytes[2] == b'0'
+        && bytes[3].is_ascii_digit()
+        && name[4..].starts_with("_L")
+}
+
+/// Returns `true` for Landsat band names (`B4`, `SR_B4`, `ST_B10`, `QA_PIXEL`) and roles
+pub fn is_band_name(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    if upper == "QA_PIXEL" || ROLES.iter().any(|(role, _, _)| role.eq_ignore_ascii_case(name)) {
+        return true;
+    }
+    let number = upper
+        .strip_prefix("SR_")
+        .or_else(|| upper.strip_prefix("ST_"))
+        .unwrap_or(&upper);
+    match number.strip_prefix('B') {
+        Some(n) => (1..=2).contains(&n.len()) && n.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Split a `<product directory or MTL>:<band>` reference
+pub fn parse_band_reference(input: &str) -> Option<(&str, &str)> {
+    let (product, band) = input.rsplit_once(':')?;
+    (is_product_path(product) && is_band_name(band)).then_some((product, band))
+}
+
+/// Turn a bare band name or role into a reference to `product`, anything else is left as is
+pub fn qualify_input(input: &str, product: &str) -> String {
+    if is_band_name(input) && !Path::new(input).exists() {
+        format!("{}:{}", product, input)
+    } else {
+        input.to_string()
+    }
+}
+
+/// Resolve a band reference to its file, `None` if `input` is not a Landsat band reference
+pub fn resolve_band(input: &str, cache: &RasterCache) -> Result<Option<String>> {
+    let Some((product, band)) = parse_band_reference(input) else {
+        return Ok(None);
+    };
+    open_cached(product, cache)?
+        .band_path(band)
+        .with_context(|| format!("Failed to resolve '{}'", input))
+        .map(Some)
+}
+
+/// Reflectance scale and offset of a band reference or of a band file with its MTL next to it
+pub fn band_scaling(input: &str, cache: &RasterCache) -> Result<Option<(f32, f32)>> {
+    if let Some((product, band)) = parse_band_reference(input) {
+        return Ok(open_cached(product, cache)?.reflectance_scaling(band));
+    }
+
+    let Some(file_name) = Path::new(input).file_name().map(|n| n.to_string_lossy().to_string()) else {
+        return Ok(None);
+    };
+    let Some((scene, band)) = split_band_name(&file_name) else {
+        return Ok(None);
+    };
+    let directory = Path::new(input).parent().unwrap_or(Path::new(""));
+    for suffix in ["_MTL.json", "_MTL.txt"] {
+        let mtl = directory.join(format!("{}{}", scene, suffix));
+        if mtl.exists() {
+            let product = open_cached(&mtl.to_string_lossy(), cache)?;
+            return Ok(product.reflectance_scaling(&format!("B{}", band)));
+        }
+    }
+    Ok(None)
+}
+
+/// Split `LC08_L2SP_192029_20230615_20230622_02_T1_SR_B4.TIF` into the scene id and band number
+pub fn split_band_name(file_name: &str) -> Option<(String, String)> {
+    let stem = file_name.split('.').next()?;
+    let (prefix, band) = stem.rsplit_once("_B")?;
+    if band.is_empty() || !band.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let scene = prefix
+        .strip_suffix("_SR")
+        .or_else(|| prefix.strip_suffix("_ST"))
+        .unwrap_or(prefix);
+    Some((scene.to_string(), band.to_string()))
+}
+
+/// Open a product once and share it with later calls of the run
+fn open_cached(path: &str, cache: &RasterCache) -> Result<Arc<LandsatProduct>> {
+    cache.shared(path, || LandsatProduct::open(path))
+}
+
+/// Collect the leaf values of a JSON MTL per innermost object
+fn flatten_json(group: &str, value: &Value, groups: &mut HashMap<String, HashMap<String, String>>) {
+    let Value::Object(map) = value else {
+        return;
+    };
+    for (key, value) in map {
+        match value {
+            Value::Object(_) => flatten_json(key, value, groups),
+            Value::String(s) => {
+                groups
+                    .entry(group.to_string())
+                    .or_default()
+                    .insert(key.clone(), s.clone());
+            }
+            other => {
+                groups
+                    .entry(group.to_string())
+                    .or_default()
+                    .insert(key.clone(), other.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// src/utils/manifest.rs
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::utils::provenance::timestamp_now;
+
+/// Last run of one batch operation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// Fingerprint of the operation's inputs and effective parameters
+    pub fingerprint: String,
+    pub succeeded: bool,
+    /// Every file the operation wrote
+    pub outputs: Vec<String>,
+    /// Completion time, RFC 3339 UTC
+    pub completed: String,
+}
+
+/// Record of the operations of earlier batch runs, by output path, used to skip up-to-date ones
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub version: String,
+    pub operations: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Read the manifest at `path`, empty if there is none yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| {
+            format!("Invalid manifest {}, delete it or run with --force", path.display())
+        })
+    }
+
+    /// Write the manifest, replacing the previous one only once fully written
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        self.version = crate::VERSION.to_string();
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write manifest {}", temporary.display()))?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Failed to write manifest {}", path.display()))?;
+        Ok(())
+    }
+
+    /// True if the operation writing `output` last succeeded with the same fingerprint and
+    /// its files still exist, none older than `inputs_modified`. Files in `/vsimem/` are not checked.
+    pub fn is_up_to_date(&self, output: &str, fingerprint: &str, inputs_modified: Option<SystemTime>) -> bool {
+        let Some(entry) = self.operations.get(output) else {
+            return false;
+        };
+        entry.succeeded
+            && entry.fingerprint == fingerprint
+            && entry
+                .outputs
+                .iter()
+                .filter(|path| !path.starts_with("/vsimem/"))
+                .all(|path| match file_stamp(path) {
+                    Some((_, modified)) => inputs_modified.is_none_or(|inputs| modified >= inputs),
+                    None => false,
+                })
+    }
+
+    /// Record a run of the operation writing `output`
+    pub fn record(&mut self, output: &str, fingerprint: &str, succeeded: bool, outputs: Vec<String>) {
+        self.operations.insert(
+            output.to_string(),
+            ManifestEntry {
+                fingerprint: fingerprint.to_string(),
+                succeeded,
+                outputs,
+                completed: timestamp_now(),
+            },
+        );
+    }
+}
+
+/// Manifest of a batch configuration: `batch.yaml` -> `batch.manifest.json`
+pub fn default_path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("manifest.json")
+}
+
+/// Size and modification time of a local file, `None` for missing or virtual files
+pub fn file_stamp(path: &str) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Seconds since the Unix epoch of a modification time
+pub fn stamp_seconds(modified: SystemTime) -> f64 {
+    modified.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// SHA-256 of a description of an operation, as lowercase hex
+pub fn fingerprint(description: &serde_json::Value) -> String {
+    let digest = Sha256::digest(description.to_string().as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
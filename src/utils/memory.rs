@@ -0,0 +1,156 @@
+// src/utils/memory.rs
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// Blocks are not split below this side to fit a memory budget
+pub const MIN_BLOCK_SIDE: usize = 64;
+
+/// Parse a size such as `12G`, `800M` or `1.5GiB` (binary units); plain numbers are MB
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let invalid = || anyhow::anyhow!("Invalid size '{}', expected e.g. 12G, 800M or 512 (MB)", text);
+    let value: f64 = number.parse().map_err(|_| invalid())?;
+    let factor: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "" | "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    Ok((value * factor as f64) as u64)
+}
+
+/// Byte count in binary units (`1.5 GiB`)
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Division of a memory budget between GDAL's block cache, read-ahead and processing
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryShares {
+    pub total: u64,
+    /// GDAL block cache (`GDAL_CACHEMAX`), which also holds output blocks not yet flushed
+    pub gdal_cache: u64,
+    /// Input blocks read ahead of processing
+    pub inflight: u64,
+    /// Blocks being processed: inputs, results and their output conversion
+    pub working: u64,
+}
+
+impl MemoryShares {
+    /// A quarter each for the GDAL cache and read-ahead, the rest for processing
+    pub fn split(total: u64) -> Self {
+        Self {
+            total,
+            gdal_cache: total / 4,
+            inflight: total / 4,
+            working: total - 2 * (total / 4),
+        }
+    }
+
+    /// Largest working set of one block with `operations` running at once, leaving room for
+    /// at least four blocks of each to be read ahead
+    pub fn block_limit(&self, operations: usize) -> u64 {
+        let operations = operations.max(1) as u64;
+        (self.working / operations).min(self.inflight / (4 * operations))
+    }
+}
+
+/// Bytes per pixel of a block being processed: float inputs, and float results with their output conversion
+pub fn block_bytes_per_pixel(inputs: usize, bands: usize) -> u64 {
+    (4 * (inputs + 2 * bands)) as u64
+}
+
+/// Region processed at once: `region` (the native block size) halved along its longer side
+/// until `bytes_per_pixel` for each of its pixels fit in `max_bytes`, `None` if that takes
+/// splitting below `MIN_BLOCK_SIDE`
+pub fn fit_region(region: (usize, usize), bytes_per_pixel: u64, max_bytes: u64) -> Option<(usize, usize)> {
+    let bytes = |(width, height): (usize, usize)| (width * height) as u64 * bytes_per_pixel;
+    let mut region = region;
+    while bytes(region) > max_bytes {
+        let (width, height) = region;
+        if width <= MIN_BLOCK_SIDE && height <= MIN_BLOCK_SIDE {
+            return None;
+        }
+        region = if width >= height {
+            ((width / 2).max(MIN_BLOCK_SIDE), height)
+        } else {
+            (width, (height / 2).max(MIN_BLOCK_SIDE))
+        };
+    }
+    Some(region)
+}
+
+/// Size GDAL's block cache, shrinking it right away if it holds more.
+///
+/// The cache is shared by the whole process: runs that may overlap with others claim a
+/// [`GdalCacheShare`] instead.
+pub fn set_gdal_cache(bytes: u64) {
+    unsafe { gdal_sys::GDALSetCacheMax64(bytes.min(i64::MAX as u64) as i64) };
+}
+
+/// Current size of GDAL's block cache
+pub fn gdal_cache() -> u64 {
+    unsafe { gdal_sys::GDALGetCacheMax64() }.max(0) as u64
+}
+
+/// Runs holding a share of GDAL's block cache, and its size before the first claimed one
+struct CacheClaims {
+    before: u64,
+    next: u64,
+    bytes: BTreeMap<u64, u64>,
+}
+
+static CACHE_CLAIMS: Mutex<CacheClaims> = Mutex::new(CacheClaims {
+    before: 0,
+    next: 0,
+    bytes: BTreeMap::new(),
+});
+
+/// One run's share of GDAL's process-wide block cache. While shares are held the cache is
+/// sized to the smallest of them, so no run exceeds its budget because of another; the size
+/// before the first share is restored when the last one is dropped.
+pub struct GdalCacheShare {
+    id: u64,
+}
+
+impl GdalCacheShare {
+    pub fn claim(bytes: u64) -> Self {
+        let mut claims = CACHE_CLAIMS.lock().unwrap();
+        if claims.bytes.is_empty() {
+            claims.before = gdal_cache();
+        }
+        let id = claims.next;
+        claims.next += 1;
+        claims.bytes.insert(id, bytes);
+        set_gdal_cache(claims.bytes.values().copied().min().unwrap_or(bytes));
+        Self { id }
+    }
+}
+
+impl Drop for GdalCacheShare {
+    fn drop(&mut self) {
+        let mut claims = CACHE_CLAIMS.lock().unwrap();
+        claims.bytes.remove(&self.id);
+        set_gdal_cache(claims.bytes.values().copied().min().unwrap_or(claims.before));
+    }
+}
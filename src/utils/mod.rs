@@ -1,3 +1,18 @@
 // src/utils/mod.rs
 pub mod gdal_ext;
-pub mod cache;
\ No newline at end of file
+pub mod cache;
+pub mod output;
+pub mod mosaic;
+pub mod dates;
+pub mod sentinel2;
+pub mod landsat;
+pub mod stac;
+pub mod product;
+pub mod scaling;
+pub mod provenance;
+pub mod template;
+pub mod manifest;
+pub mod report;
+pub mod retry;
+pub mod memory;
+pub mod vrt;
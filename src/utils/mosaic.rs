@@ -0,0 +1,241 @@
+// src/utils/mosaic.rs
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use gdal::Dataset;
+
+use crate::utils::cache::RasterCache;
+use crate::utils::vrt::{SingleBandVrt, VrtSource};
+use crate::utils::{manifest, product};
+
+/// GDAL version (`GDAL_VERSION_NUM`) adding the `max` pixel function
+const MAX_PIXEL_FUNCTION_VERSION: u32 = 3_080_000;
+
+/// Rule deciding which tile wins where tile footprints overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OverlapRule {
+    /// The first valid tile in input order wins
+    First,
+    /// The last valid tile in input order wins
+    #[default]
+    Last,
+    /// The largest valid value wins (requires GDAL >= 3.8)
+    MaxValid,
+}
+
+impl OverlapRule {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "first" => Ok(OverlapRule::First),
+            "last" => Ok(OverlapRule::Last),
+            "max" | "max_valid" | "max-valid" => Ok(OverlapRule::MaxValid),
+            _ => Err(anyhow::anyhow!(
+                "Unknown mosaic overlap rule '{}'. Expected one of: first, last, max",
+                name
+            )),
+        }
+    }
+}
+
+/// Returns `true` if the input refers to several tiles (comma-separated list or glob pattern).
+/// Existing files are never mosaics, whatever their name contains.
+pub fn is_mosaic_input(input: &str) -> bool {
+    (input.contains(',') || input.contains(['*', '?', '['])) && !Path::new(input).exists()
+}
+
+/// Expand a comma-separated list of paths and/or glob patterns into tile paths
+pub fn expand_tiles(input: &str) -> Result<Vec<String>> {
+    let mut tiles = Vec::new();
+
+    for part in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if part.contains(['*', '?', '[']) && !Path::new(part).exists() {
+            let mut matches = glob::glob(part)
+                .with_context(|| format!("Invalid glob pattern '{}'", part))?
+                .collect::<Result<Vec<_>, _>>()?;
+            matches.sort();
+
+            if matches.is_empty() {
+                return Err(anyhow::anyhow!("No tiles match pattern '{}'", part));
+            }
+            tiles.extend(matches.into_iter().map(|p| p.to_string_lossy().to_string()));
+        } else {
+            tiles.push(part.to_string());
+        }
+    }
+
+    if tiles.is_empty() {
+        return Err(anyhow::anyhow!("No tiles found in mosaic input '{}'", input));
+    }
+
+    Ok(tiles)
+}
+
+/// Resolve an input to a single openable path.
+///
+/// Plain paths are returned unchanged; tile lists and glob patterns are turned into
+/// a VRT mosaic in the run's `/vsimem/` directory that is built once and shared by later calls
+/// with the same `cache` until a tile changes. Sentinel-2 and Landsat band references
+/// (`<product>.SAFE:B04`, `<product>:red`) resolve to the band's image file.
+pub fn resolve_input(input: &str, rule: OverlapRule, cache: &RasterCache) -> Result<String> {
+    if let Some(path) = product::resolve_band(input, cache)? {
+        return Ok(path);
+    }
+    if !is_mosaic_input(input) {
+        return Ok(input.to_string());
+    }
+
+    let tiles = expand_tiles(input)?;
+
+    // The tiles' sizes and times are part of the name, so a changed tile rebuilds the mosaic
+    let mut hasher = DefaultHasher::new();
+    tiles.hash(&mut hasher);
+    rule.hash(&mut hasher);
+    let key = hasher.finish();
+    tiles
+        .iter()
+        .map(|tile| manifest::file_stamp(tile))
+        .collect::<Vec<_>>()
+        .hash(&mut hasher);
+    let name = format!("mosaic_{:016x}", hasher.finish());
+
+    cache.build_vrt(key, &name, || {
+        build_vrt(&tiles, rule).with_context(|| format!("Failed to build mosaic for '{}'", input))
+    })
+}
+
+/// `GDAL_VERSION_NUM` of the GDAL library in use, e.g. 3080000 for 3.8.0
+pub fn gdal_version() -> u32 {
+    gdal::version::version_info("VERSION_NUM").parse().unwrap_or(0)
+}
+
+/// Resolve every input path, see [`resolve_input`]
+pub fn resolve_inputs(inputs: &[String], rule: OverlapRule, cache: &RasterCache) -> Result<Vec<String>> {
+    inputs
+        .iter()
+        .map(|input| resolve_input(input, rule, cache))
+        .collect()
+}
+
+struct TileInfo {
+    path: String,
+    geo_transform: [f64; 6],
+    size: (usize, usize),
+}
+
+/// Build the VRT XML mosaicking the first band of all `tiles` on their common grid
+pub fn build_vrt(tiles: &[String], rule: OverlapRule) -> Result<String> {
+    if rule == OverlapRule::MaxValid && gdal_version() < MAX_PIXEL_FUNCTION_VERSION {
+        return Err(anyhow::anyhow!(
+            "The max mosaic rule needs GDAL >= 3.8, this is GDAL {}",
+            gdal::version::version_info("RELEASE_NAME")
+        ));
+    }
+
+    // The first tile defines the projection, resolution, data type and nodata value
+    let (projection, data_type, nodata, res_x, res_y) = {
+        let first = Dataset::open(&tiles[0])?;
+        let band = first.rasterband(1)?;
+        let gt = first.geo_transform()?;
+        (
+            first.projection(),
+            band.band_type().name(),
+            band.no_data_value(),
+            gt[1],
+            gt[5],
+        )
+    };
+
+    let mut infos = Vec::with_capacity(tiles.len());
+    for path in tiles {
+        let dataset = Dataset::open(path)?;
+        let gt = dataset.geo_transform()?;
+
+        if gt[2] != 0.0 || gt[4] != 0.0 {
+            return Err(anyhow::anyhow!("Rotated tiles are not supported: {}", path));
+        }
+        if (gt[1] - res_x).abs() > res_x.abs() * 1e-6 || (gt[5] - res_y).abs() > res_y.abs() * 1e-6 {
+            return Err(anyhow::anyhow!(
+                "Tile {} has resolution ({}, {}), expected ({}, {})",
+                path,
+                gt[1],
+                gt[5],
+                res_x,
+                res_y
+            ));
+        }
+        if dataset.projection() != projection {
+            return Err(anyhow::anyhow!("Tile {} uses a different projection", path));
+        }
+
+        infos.push(TileInfo {
+            path: path.clone(),
+            geo_transform: gt,
+            size: dataset.raster_size(),
+        });
+    }
+
+    // Union of all tile footprints
+    let min_x = infos.iter().map(|t| t.geo_transform[0]).fold(f64::INFINITY, f64::min);
+    let max_y = infos.iter().map(|t| t.geo_transform[3]).fold(f64::NEG_INFINITY, f64::max);
+    let max_x = infos
+        .iter()
+        .map(|t| t.geo_transform[0] + t.size.0 as f64 * res_x)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min_y = infos
+        .iter()
+        .map(|t| t.geo_transform[3] + t.size.1 as f64 * res_y)
+        .fold(f64::INFINITY, f64::min);
+
+    let width = ((max_x - min_x) / res_x).round() as usize;
+    let height = ((min_y - max_y) / res_y).round() as usize;
+
+    let mut sources = Vec::with_capacity(infos.len());
+    for tile in &infos {
+        let x_off = (tile.geo_transform[0] - min_x) / res_x;
+        let y_off = (tile.geo_transform[3] - max_y) / res_y;
+
+        if (x_off - x_off.round()).abs() > 0.01 || (y_off - y_off.round()).abs() > 0.01 {
+            return Err(anyhow::anyhow!(
+                "Tile {} is not aligned on the mosaic pixel grid",
+                tile.path
+            ));
+        }
+
+        let source_path = Path::new(&tile.path)
+            .canonicalize()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| tile.path.clone());
+
+        sources.push(VrtSource {
+            path: source_path,
+            size: tile.size,
+            dst_rect: (
+                x_off.round(),
+                y_off.round(),
+                tile.size.0 as f64,
+                tile.size.1 as f64,
+            ),
+            nodata,
+            resampling: None,
+        });
+    }
+
+    // Later sources paint over earlier ones, so reverse them for first-wins
+    if rule == OverlapRule::First {
+        sources.reverse();
+    }
+
+    let vrt = SingleBandVrt {
+        size: (width, height),
+        projection,
+        geo_transform: [min_x, res_x, 0.0, max_y, 0.0, res_y],
+        data_type,
+        nodata,
+        pixel_function: (rule == OverlapRule::MaxValid).then(|| "max".to_string()),
+        sources,
+    };
+
+    Ok(vrt.to_xml())
+}
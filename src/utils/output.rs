@@ -0,0 +1,303 @@
+// src/utils/output.rs
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use gdal::raster::RasterCreationOptions;
+use gdal::{Dataset, Driver, DriverManager, Metadata};
+
+use crate::utils::vrt::escape_xml;
+
+/// Prefix used by GDAL for in-memory files
+pub const VSIMEM_PREFIX: &str = "/vsimem/";
+
+/// Raster output formats supported by raster-calc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    GTiff,
+    GeoPackage,
+    NetCDF,
+    Zarr,
+    ErdasImg,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 5] = [
+        OutputFormat::GTiff,
+        OutputFormat::GeoPackage,
+        OutputFormat::NetCDF,
+        OutputFormat::Zarr,
+        OutputFormat::ErdasImg,
+    ];
+
+    /// GDAL short name of the driver behind this format
+    pub fn driver_name(&self) -> &'static str {
+        match self {
+            OutputFormat::GTiff => "GTiff",
+            OutputFormat::GeoPackage => "GPKG",
+            OutputFormat::NetCDF => "netCDF",
+            OutputFormat::Zarr => "Zarr",
+            OutputFormat::ErdasImg => "HFA",
+        }
+    }
+
+    /// File extensions recognised for this format (lowercase, without dot)
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            OutputFormat::GTiff => &["tif", "tiff"],
+            OutputFormat::GeoPackage => &["gpkg"],
+            OutputFormat::NetCDF => &["nc"],
+            OutputFormat::Zarr => &["zarr"],
+            OutputFormat::ErdasImg => &["img"],
+        }
+    }
+
+    /// Parse a format name as given to `--of` (driver short names and common aliases)
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "gtiff" | "geotiff" | "tif" | "tiff" => Ok(OutputFormat::GTiff),
+            "gpkg" | "geopackage" => Ok(OutputFormat::GeoPackage),
+            "netcdf" | "nc" => Ok(OutputFormat::NetCDF),
+            "zarr" => Ok(OutputFormat::Zarr),
+            "hfa" | "img" | "erdas" => Ok(OutputFormat::ErdasImg),
+            _ => Err(anyhow::anyhow!(
+                "Unknown output format '{}'. Supported formats: {}",
+                name,
+                supported_formats_list()
+            )),
+        }
+    }
+
+    /// Guess the output format from the file extension of `path`.
+    ///
+    /// `/vsimem/` targets without an extension default to GeoTIFF.
+    pub fn from_path(path: &str) -> Result<Self> {
+        let extension = Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+
+        match extension {
+            Some(ext) => Self::ALL
+                .into_iter()
+                .find(|format| format.extensions().contains(&ext.as_str()))
+                .ok_or_else(|| unknown_extension_error(path)),
+            None if path.starts_with(VSIMEM_PREFIX) => Ok(OutputFormat::GTiff),
+            None => Err(unknown_extension_error(path)),
+        }
+    }
+
+    /// Use the explicitly requested format if any, otherwise guess from the path
+    pub fn resolve(format: Option<OutputFormat>, path: &str) -> Result<Self> {
+        match format {
+            Some(format) => Ok(format),
+            None => Self::from_path(path),
+        }
+    }
+
+    /// Look up the GDAL driver for this format
+    pub fn driver(&self) -> Result<Driver> {
+        DriverManager::get_driver_by_name(self.driver_name()).map_err(|_| {
+            anyhow::anyhow!(
+                "GDAL driver '{}' is not available in this GDAL build. Available raster drivers: {}",
+                self.driver_name(),
+                available_raster_drivers().join(", ")
+            )
+        })
+    }
+
+    /// Translate the generic compression/tiling settings into driver creation options
+    pub fn creation_options(
+        &self,
+        compress: &str,
+        compress_level: u8,
+        tiled: bool,
+        use_fixed_point: bool,
+    ) -> Vec<String> {
+        let compress = compress.to_uppercase();
+        let compressed = compress != "NONE";
+        let mut options = Vec::new();
+
+        match self {
+            OutputFormat::GTiff => {
+                if compressed {
+                    options.push(format!("COMPRESS={}", compress));
+
+                    // Add compression level for supported algorithms
+                    match compress.as_str() {
+                        "DEFLATE" => options.push(format!("ZLEVEL={}", compress_level.min(9))),
+                        "ZSTD" => options.push(format!("ZSTD_LEVEL={}", compress_level.min(22))),
+                        _ => {}
+                    }
+                }
+
+                if tiled {
+                    options.push("TILED=YES".to_string());
+                }
+
+                // Always use multi-threading
+                options.push("NUM_THREADS=ALL_CPUS".to_string());
+            }
+            OutputFormat::GeoPackage => {
+                // Int16 tiles are stored as PNG, Float32 tiles need TIFF
+                if use_fixed_point {
+                    options.push("TILE_FORMAT=PNG".to_string());
+                    if compressed {
+                        options.push(format!("ZLEVEL={}", compress_level.min(9)));
+                    }
+                } else {
+                    options.push("TILE_FORMAT=TIFF".to_string());
+                }
+            }
+            OutputFormat::NetCDF => {
+                // netCDF-4 only supports DEFLATE
+                options.push("FORMAT=NC4C".to_string());
+                if compressed {
+                    options.push("COMPRESS=DEFLATE".to_string());
+                    options.push(format!("ZLEVEL={}", compress_level.min(9)));
+                }
+                if tiled {
+                    options.push("CHUNKING=YES".to_string());
+                }
+            }
+            OutputFormat::Zarr => {
+                match compress.as_str() {
+                    "NONE" => options.push("COMPRESS=NONE".to_string()),
+                    "ZSTD" => {
+                        options.push("COMPRESS=ZSTD".to_string());
+                        options.push(format!("ZSTD_LEVEL={}", compress_level.min(22)));
+                    }
+                    // DEFLATE, LZW and anything else map to zlib
+                    _ => {
+                        options.push("COMPRESS=ZLIB".to_string());
+                        options.push(format!("ZLIB_LEVEL={}", compress_level.min(9)));
+                    }
+                }
+                if tiled {
+                    options.push("BLOCKSIZE=256,256".to_string());
+                }
+            }
+            OutputFormat::ErdasImg => {
+                // ERDAS IMG only offers run-length compression and is always tiled
+                if compressed {
+                    options.push("COMPRESSED=YES".to_string());
+                }
+            }
+        }
+
+        options
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.driver_name())
+    }
+}
+
+/// Copy every band of the raster at `source` into its own file, band `i + 1` to `outputs[i]`.
+///
+/// Band descriptions, nodata and band metadata (e.g. SCALE/OFFSET) are kept.
+pub fn split_bands(
+    source: &str,
+    outputs: &[String],
+    format: OutputFormat,
+    creation_options: &[String],
+) -> Result<()> {
+    let dataset = Dataset::open(source)?;
+    let (width, height) = dataset.raster_size();
+    let projection = dataset.projection();
+    let gt = dataset.geo_transform()?;
+    let driver = format.driver()?;
+    let options = RasterCreationOptions::from_iter(creation_options.iter().cloned());
+
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let source_id = hasher.finish();
+
+    for (i, output) in outputs.iter().enumerate() {
+        let band = dataset.rasterband(i + 1)?;
+
+        // Single-band VRT view of the band, copied with the target driver
+        let mut vrt = format!(
+            "<VRTDataset rasterXSize=\"{}\" rasterYSize=\"{}\">\n",
+            width, height
+        );
+        if !projection.is_empty() {
+            vrt.push_str(&format!("  <SRS>{}</SRS>\n", escape_xml(&projection)));
+        }
+        vrt.push_str(&format!(
+            "  <GeoTransform>{}, {}, {}, {}, {}, {}</GeoTransform>\n",
+            gt[0], gt[1], gt[2], gt[3], gt[4], gt[5]
+        ));
+        vrt.push_str(&format!(
+            "  <VRTRasterBand dataType=\"{}\" band=\"1\">\n",
+            band.band_type().name()
+        ));
+        if let Ok(description) = band.description() {
+            vrt.push_str(&format!("    <Description>{}</Description>\n", escape_xml(&description)));
+        }
+        if let Some(nodata) = band.no_data_value() {
+            vrt.push_str(&format!("    <NoDataValue>{}</NoDataValue>\n", nodata));
+        }
+        let metadata: Vec<_> = band.metadata().filter(|entry| entry.domain.is_empty()).collect();
+        if !metadata.is_empty() {
+            vrt.push_str("    <Metadata>\n");
+            for entry in metadata {
+                vrt.push_str(&format!(
+                    "      <MDI key=\"{}\">{}</MDI>\n",
+                    escape_xml(&entry.key),
+                    escape_xml(&entry.value)
+                ));
+            }
+            vrt.push_str("    </Metadata>\n");
+        }
+        vrt.push_str("    <SimpleSource>\n");
+        vrt.push_str(&format!(
+            "      <SourceFilename relativeToVRT=\"0\">{}</SourceFilename>\n",
+            escape_xml(source)
+        ));
+        vrt.push_str(&format!("      <SourceBand>{}</SourceBand>\n", i + 1));
+        vrt.push_str("    </SimpleSource>\n");
+        vrt.push_str("  </VRTRasterBand>\n");
+        vrt.push_str("</VRTDataset>\n");
+
+        let vrt_path = format!("{}raster-calc/split_{:016x}_{}.vrt", VSIMEM_PREFIX, source_id, i + 1);
+        gdal::vsi::create_mem_file(&vrt_path, vrt.into_bytes())?;
+        let copied = Dataset::open(&vrt_path)
+            .and_then(|view| view.create_copy(&driver, output, &options).map(|_| ()));
+        gdal::vsi::unlink_mem_file(&vrt_path)?;
+        copied.with_context(|| format!("Failed to write band {} to {}", i + 1, output))?;
+    }
+
+    Ok(())
+}
+
+/// Short names of all registered GDAL drivers able to create rasters
+pub fn available_raster_drivers() -> Vec<String> {
+    DriverManager::all()
+        .filter(|d| {
+            d.metadata_item("DCAP_RASTER", "").is_some()
+                && d.metadata_item("DCAP_CREATE", "").is_some()
+        })
+        .map(|d| d.short_name())
+        .collect()
+}
+
+fn supported_formats_list() -> String {
+    OutputFormat::ALL
+        .iter()
+        .map(|format| format!("{} (.{})", format, format.extensions().join(", .")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn unknown_extension_error(path: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Cannot determine output format from '{}'. Use --of to select one of: {}. \
+         Available GDAL raster drivers: {}",
+        path,
+        supported_formats_list(),
+        available_raster_drivers().join(", ")
+    )
+}
@@ -0,0 +1,27 @@
+// src/utils/product.rs
+use anyhow::Result;
+
+use crate::utils::cache::RasterCache;
+use crate::utils::{landsat, sentinel2, stac};
+
+/// Turn a bare band name or role into a reference to `product` (Sentinel-2, Landsat or a
+/// STAC item), see [`sentinel2::qualify_input`], [`landsat::qualify_input`] and [`stac::qualify_input`].
+/// Fails if a STAC item cannot be read.
+pub fn qualify_input(input: &str, product: Option<&str>, resolution: Option<u32>, cache: &RasterCache) -> Result<String> {
+    match product {
+        Some(product) if landsat::is_product_path(product) => Ok(landsat::qualify_input(input, product)),
+        Some(product) if stac::is_item_path(product) => stac::qualify_input(input, product, cache),
+        _ => Ok(sentinel2::qualify_input(input, product, resolution)),
+    }
+}
+
+/// Resolve a Sentinel-2, Landsat or STAC band reference, `None` if `input` is not one
+pub fn resolve_band(input: &str, cache: &RasterCache) -> Result<Option<String>> {
+    if let Some(path) = sentinel2::resolve_band(input, cache)? {
+        return Ok(Some(path));
+    }
+    if let Some(path) = landsat::resolve_band(input, cache)? {
+        return Ok(Some(path));
+    }
+    stac::resolve_band(input, cache)
+}
@@ -0,0 +1,173 @@
+// src/utils/provenance.rs
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use gdal::{Dataset, Metadata};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::processing::parallel::IndexCalculator;
+use crate::utils::dates::Date;
+use crate::utils::manifest;
+
+/// Metadata domain of the provenance items (`gdalinfo -mdd RASTER_CALC`)
+pub const METADATA_DOMAIN: &str = "RASTER_CALC";
+
+/// One input of an output: as given, as read, and the SHA-256 of the file when it is a local file
+/// and checksums were requested
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRecord {
+    pub input: String,
+    pub path: String,
+    pub sha256: Option<String>,
+}
+
+/// How an output was made: software version, index, formula, inputs, parameters and time
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub version: String,
+    pub index: String,
+    pub formula: String,
+    pub inputs: Vec<InputRecord>,
+    pub parameters: Vec<(String, String)>,
+    /// Creation time, RFC 3339 UTC
+    pub created: String,
+}
+
+impl Provenance {
+    /// Provenance of `calculator` applied to `inputs` (as given) read from `paths`, without checksums
+    pub fn new<I: IndexCalculator>(calculator: &I, inputs: &[String], paths: &[String]) -> Self {
+        let inputs = inputs
+            .iter()
+            .zip(paths)
+            .map(|(input, path)| InputRecord {
+                input: input.clone(),
+                path: path.clone(),
+                sha256: None,
+            })
+            .collect();
+
+        Self {
+            version: crate::VERSION.to_string(),
+            index: calculator.name().to_string(),
+            formula: calculator.formula(),
+            inputs,
+            parameters: calculator.parameters(),
+            created: timestamp_now(),
+        }
+    }
+
+    /// Record the SHA-256 of every local input file, which reads them all in full
+    pub fn with_checksums(mut self) -> Result<Self> {
+        for input in &mut self.inputs {
+            input.sha256 = sha256_file(&input.path)?;
+        }
+        Ok(self)
+    }
+
+    /// Record one more parameter (scale factors, compression, mask...)
+    pub fn with_parameter(mut self, name: &str, value: impl ToString) -> Self {
+        self.parameters.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Write the provenance to the `RASTER_CALC` metadata domain of `dataset`
+    pub fn write_metadata(&self, dataset: &mut Dataset) -> Result<()> {
+        dataset.set_metadata_item("SOFTWARE", &format!("raster-calc {}", self.version), METADATA_DOMAIN)?;
+        dataset.set_metadata_item("INDEX", &self.index, METADATA_DOMAIN)?;
+        if !self.formula.is_empty() {
+            dataset.set_metadata_item("FORMULA", &self.formula, METADATA_DOMAIN)?;
+        }
+        dataset.set_metadata_item("CREATED", &self.created, METADATA_DOMAIN)?;
+        for (i, input) in self.inputs.iter().enumerate() {
+            dataset.set_metadata_item(&format!("INPUT_{}", i + 1), &input.input, METADATA_DOMAIN)?;
+            if input.path != input.input {
+                dataset.set_metadata_item(&format!("INPUT_{}_PATH", i + 1), &input.path, METADATA_DOMAIN)?;
+            }
+            if let Some(sha256) = &input.sha256 {
+                dataset.set_metadata_item(&format!("INPUT_{}_SHA256", i + 1), sha256, METADATA_DOMAIN)?;
+            }
+        }
+        for (name, value) in &self.parameters {
+            dataset.set_metadata_item(&format!("PARAM_{}", name.to_uppercase()), value, METADATA_DOMAIN)?;
+        }
+        Ok(())
+    }
+
+    /// JSON form used in STAC items
+    pub fn to_json(&self) -> Value {
+        let inputs: Vec<Value> = self
+            .inputs
+            .iter()
+            .map(|input| json!({"input": input.input, "path": input.path, "sha256": input.sha256}))
+            .collect();
+        let parameters: Map<String, Value> = self
+            .parameters
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+            .collect();
+        json!({
+            "version": self.version,
+            "index": self.index,
+            "formula": self.formula,
+            "inputs": inputs,
+            "parameters": parameters,
+            "created": self.created,
+        })
+    }
+}
+
+/// SHA-256 of a local file as lowercase hex, `None` for virtual (`/vsimem/`, `/vsizip/`...) paths.
+/// Checksums are kept per path, size and modification time, so a rewritten file is hashed again.
+pub fn sha256_file(path: &str) -> Result<Option<String>> {
+    if !Path::new(path).is_file() {
+        return Ok(None);
+    }
+
+    type Stamp = Option<(u64, SystemTime)>;
+    static CHECKSUMS: OnceLock<Mutex<HashMap<(String, Stamp), String>>> = OnceLock::new();
+    let checksums = CHECKSUMS.get_or_init(|| Mutex::new(HashMap::new()));
+    let key = (path.to_string(), manifest::file_stamp(path));
+    if let Some(checksum) = checksums.lock().unwrap().get(&key) {
+        return Ok(Some(checksum.clone()));
+    }
+
+    let mut file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    let checksum: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    checksums.lock().unwrap().insert(key, checksum.clone());
+    Ok(Some(checksum))
+}
+
+/// Current UTC time as RFC 3339 (`2024-05-01T12:30:00Z`)
+pub fn timestamp_now() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    format_timestamp(seconds)
+}
+
+/// Seconds since the Unix epoch as RFC 3339 UTC
+pub fn format_timestamp(seconds: i64) -> String {
+    let day = Date::from_days_since_epoch(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,254 @@
+// src/utils/report.rs
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::processing::parallel::NODATA_VALUE_FLOAT;
+use crate::utils::gdal_ext::TypedBuffer;
+
+/// Wall time, volume and output statistics of one processing run, filled in by the processor
+#[derive(Debug, Default)]
+pub struct ProcessStats {
+    read_nanos: AtomicU64,
+    compute_nanos: AtomicU64,
+    write_nanos: AtomicU64,
+    bytes_read: AtomicU64,
+    pixels: AtomicU64,
+    bands: Mutex<Vec<BandStatistics>>,
+}
+
+impl ProcessStats {
+    /// Time spent waiting for input blocks
+    pub fn add_read(&self, elapsed: Duration) {
+        self.read_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Time spent scaling, calculating and masking
+    pub fn add_compute(&self, elapsed: Duration) {
+        self.compute_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Time spent writing outputs, class maps, renderings and STAC items
+    pub fn add_write(&self, elapsed: Duration) {
+        self.write_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_inputs(&self, inputs: &[TypedBuffer]) {
+        let bytes: usize = inputs.iter().map(TypedBuffer::byte_size).sum();
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Accumulate the statistics of the result bands of one block
+    pub fn add_results(&self, names: &[String], results: &[TypedBuffer]) {
+        let mut bands = self.bands.lock().unwrap();
+        if bands.is_empty() {
+            bands.extend(names.iter().map(|name| BandStatistics::new(name)));
+        }
+        if let Some((width, height)) = results.first().map(TypedBuffer::shape) {
+            self.pixels.fetch_add((width * height) as u64, Ordering::Relaxed);
+        }
+        for (statistics, result) in bands.iter_mut().zip(results) {
+            if let Some(buffer) = result.as_f32() {
+                statistics.add(buffer.data());
+            }
+        }
+    }
+
+    pub fn read_seconds(&self) -> f64 {
+        seconds(&self.read_nanos)
+    }
+
+    pub fn compute_seconds(&self) -> f64 {
+        seconds(&self.compute_nanos)
+    }
+
+    pub fn write_seconds(&self) -> f64 {
+        seconds(&self.write_nanos)
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn pixels(&self) -> u64 {
+        self.pixels.load(Ordering::Relaxed)
+    }
+
+    pub fn bands(&self) -> Vec<BandStatistics> {
+        self.bands.lock().unwrap().clone()
+    }
+}
+
+fn seconds(nanos: &AtomicU64) -> f64 {
+    nanos.load(Ordering::Relaxed) as f64 / 1e9
+}
+
+/// Statistics of the valid (not nodata) values of a result band
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BandStatistics {
+    pub band: String,
+    pub valid: u64,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    pub mean: Option<f64>,
+    #[serde(skip)]
+    sum: f64,
+}
+
+impl BandStatistics {
+    pub fn new(band: &str) -> Self {
+        Self {
+            band: band.to_string(),
+            valid: 0,
+            min: None,
+            max: None,
+            mean: None,
+            sum: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, values: &[f32]) {
+        for &value in values {
+            if value.is_nan() || value == NODATA_VALUE_FLOAT {
+                continue;
+            }
+            self.valid += 1;
+            self.sum += value as f64;
+            self.min = Some(self.min.map_or(value, |min| min.min(value)));
+            self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        }
+        self.mean = (self.valid > 0).then(|| self.sum / self.valid as f64);
+    }
+}
+
+/// Outcome of a batch operation
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    Succeeded,
+    Failed,
+    /// Not run because an operation it depends on failed
+    Skipped,
+    /// Not run because the manifest records its outputs as current
+    UpToDate,
+    /// Not started because another operation failed with `on_error: fail_fast`
+    Cancelled,
+}
+
+/// Report of one batch operation
+#[derive(Serialize, Debug, Clone)]
+pub struct OperationReport {
+    /// 1-based position in the configuration
+    pub number: usize,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub op_type: String,
+    pub output: String,
+    pub status: OperationStatus,
+    /// Runs of the operation, retries included
+    pub attempts: u32,
+    /// Error of the last attempt and its causes, outermost first
+    pub errors: Vec<String>,
+    /// Start time, RFC 3339 UTC
+    pub started: String,
+    pub wall_seconds: f64,
+    pub read_seconds: f64,
+    pub compute_seconds: f64,
+    pub write_seconds: f64,
+    pub bytes_read: u64,
+    pub pixels: u64,
+    /// Inputs already open in the shared dataset cache when the operation started
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+    pub bands: Vec<BandStatistics>,
+}
+
+/// Totals of a batch run
+#[derive(Serialize, Debug, Clone)]
+pub struct BatchSummary {
+    pub config: String,
+    pub started: String,
+    pub finished: String,
+    pub wall_seconds: f64,
+    pub operations: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub up_to_date: usize,
+    pub cancelled: usize,
+    /// Datasets open in the shared cache at the end of the run
+    pub cached_datasets: usize,
+    /// Dataset requests served from the cache, and datasets opened
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum ReportRecord<'a> {
+    Operation(&'a OperationReport),
+    Summary(&'a BatchSummary),
+}
+
+/// Writes a batch report: one JSON document, or NDJSON (`.ndjson`, `.jsonl`) with a line per
+/// operation as it finishes followed by a summary line
+pub struct ReportWriter {
+    path: PathBuf,
+    lines: Option<Mutex<BufWriter<File>>>,
+    operations: Mutex<Vec<OperationReport>>,
+}
+
+impl ReportWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let ndjson = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("ndjson") || e.eq_ignore_ascii_case("jsonl"));
+        let lines = if ndjson {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create report {}", path.display()))?;
+            Some(Mutex::new(BufWriter::new(file)))
+        } else {
+            None
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            lines,
+            operations: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Record a finished operation (written at once in NDJSON reports)
+    pub fn operation(&self, report: OperationReport) -> Result<()> {
+        if let Some(lines) = &self.lines {
+            let mut lines = lines.lock().unwrap();
+            serde_json::to_writer(&mut *lines, &ReportRecord::Operation(&report))?;
+            writeln!(lines)?;
+            lines.flush()?;
+        }
+        self.operations.lock().unwrap().push(report);
+        Ok(())
+    }
+
+    /// Write the summary (and, for JSON reports, every operation in configuration order)
+    pub fn finish(&self, summary: &BatchSummary) -> Result<()> {
+        if let Some(lines) = &self.lines {
+            let mut lines = lines.lock().unwrap();
+            serde_json::to_writer(&mut *lines, &ReportRecord::Summary(summary))?;
+            writeln!(lines)?;
+            lines.flush()?;
+            return Ok(());
+        }
+
+        let mut operations = self.operations.lock().unwrap().clone();
+        operations.sort_by_key(|report| report.number);
+        let document = serde_json::json!({ "summary": summary, "operations": operations });
+        std::fs::write(&self.path, serde_json::to_string_pretty(&document)?)
+            .with_context(|| format!("Failed to write report {}", self.path.display()))
+    }
+}
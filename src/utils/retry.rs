@@ -0,0 +1,66 @@
+// src/utils/retry.rs
+use std::time::Duration;
+
+use anyhow::Result;
+use gdal::errors::GdalError;
+
+/// What a batch does once an operation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    /// Keep running the operations that do not depend on the failed one
+    #[default]
+    Continue,
+    /// Start no further operation, those running finish
+    FailFast,
+}
+
+impl OnError {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "continue" => Ok(OnError::Continue),
+            "fail_fast" | "fail-fast" | "failfast" => Ok(OnError::FailFast),
+            _ => Err(anyhow::anyhow!(
+                "Unknown on_error policy '{}'. Expected one of: continue, fail_fast",
+                name
+            )),
+        }
+    }
+}
+
+/// Retries of a failing operation: `delay` before the first, doubling before each next one
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Longest wait between two attempts
+    pub const MAX_DELAY: Duration = Duration::from_secs(600);
+
+    pub fn new(retries: u32, delay_seconds: f64) -> Result<Self> {
+        let delay = Duration::try_from_secs_f64(delay_seconds)
+            .map_err(|_| anyhow::anyhow!("Invalid retry delay {}, expected seconds >= 0", delay_seconds))?;
+        Ok(Self { retries, delay })
+    }
+
+    /// Wait before retry `retry` (1-based)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.delay.saturating_mul(factor).min(Self::MAX_DELAY)
+    }
+
+    /// Wait before the attempt after failed attempt `attempt` (0-based), `None` when no retry
+    /// is left or the error would fail again (see [`is_transient`])
+    pub fn next_delay(&self, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+        (attempt < self.retries && is_transient(error)).then(|| self.delay(attempt + 1))
+    }
+}
+
+/// True for errors a retry can fix: IO and GDAL failures anywhere in the chain. Invalid
+/// params or configurations fail the same way every time.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.is::<std::io::Error>() || cause.is::<GdalError>())
+}
@@ -0,0 +1,128 @@
+// src/utils/scaling.rs
+use anyhow::Result;
+use gdal::Dataset;
+
+use crate::utils::cache::RasterCache;
+use crate::utils::gdal_ext::TypedBuffer;
+use crate::utils::{landsat, sentinel2, stac};
+
+/// Where the scale and offset of an input were found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalingSource {
+    /// Nothing found, raw values are used
+    None,
+    /// Band scale/offset of the raster itself
+    Gdal,
+    /// Quantification value and BOA/RADIO_ADD_OFFSET of the Sentinel-2 product metadata
+    Sentinel2,
+    /// REFLECTANCE_MULT/ADD_BAND of the Landsat MTL file
+    Landsat,
+    /// `raster:bands` scale/offset of a STAC item asset
+    Stac,
+}
+
+impl std::fmt::Display for ScalingSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ScalingSource::None => "none",
+            ScalingSource::Gdal => "band metadata",
+            ScalingSource::Sentinel2 => "Sentinel-2 product metadata",
+            ScalingSource::Landsat => "Landsat MTL",
+            ScalingSource::Stac => "STAC item",
+        })
+    }
+}
+
+/// Scale and offset turning the raw values of one input into physical values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputScaling {
+    pub scale: f32,
+    pub offset: f32,
+    /// Raw nodata value, passed through unscaled
+    pub nodata: Option<f32>,
+    pub source: ScalingSource,
+}
+
+impl InputScaling {
+    pub const NONE: InputScaling = InputScaling {
+        scale: 1.0,
+        offset: 0.0,
+        nodata: None,
+        source: ScalingSource::None,
+    };
+
+    pub fn new(scale: f32, offset: f32, nodata: Option<f32>, source: ScalingSource) -> Self {
+        Self {
+            scale,
+            offset,
+            nodata,
+            source,
+        }
+    }
+
+    /// Detect the scaling of an input.
+    ///
+    /// `input` is the input as given (e.g. a Sentinel-2 band reference), `path` the resolved
+    /// raster. Band scale/offset take precedence over STAC, Sentinel-2 and Landsat product
+    /// metadata, read through the run's `cache`. Sentinel-2 and Landsat digital number 0 is nodata.
+    pub fn detect(input: &str, path: &str, cache: &RasterCache) -> Result<Self> {
+        let dataset = Dataset::open(path)?;
+        let band = dataset.rasterband(1)?;
+        let nodata = band.no_data_value().map(|v| v as f32);
+
+        let scale = band.scale().unwrap_or(1.0);
+        let offset = band.offset().unwrap_or(0.0);
+        if scale != 1.0 || offset != 0.0 {
+            return Ok(Self::new(scale as f32, offset as f32, nodata, ScalingSource::Gdal));
+        }
+
+        if let Some((scale, offset, stac_nodata)) = stac::band_scaling(input, cache)? {
+            return Ok(Self::new(scale, offset, stac_nodata.or(nodata), ScalingSource::Stac));
+        }
+        if let Some((scale, offset)) = sentinel2::band_scaling(input, cache)? {
+            return Ok(Self::new(scale, offset, nodata.or(Some(0.0)), ScalingSource::Sentinel2));
+        }
+        if let Some((scale, offset)) = landsat::band_scaling(input, cache)? {
+            return Ok(Self::new(scale, offset, nodata.or(Some(0.0)), ScalingSource::Landsat));
+        }
+
+        Ok(Self {
+            nodata,
+            ..Self::NONE
+        })
+    }
+
+    /// True if a scale or offset was found
+    pub fn is_detected(&self) -> bool {
+        self.source != ScalingSource::None
+    }
+
+    /// Scale raw values in place, leaving nodata and NaN untouched
+    pub fn apply(&self, values: &mut [f32]) {
+        for value in values {
+            if !value.is_nan() && self.nodata != Some(*value) {
+                *value = *value * self.scale + self.offset;
+            }
+        }
+    }
+}
+
+/// Scale every input buffer with its detected scaling; inputs without one are divided by
+/// `fallback_divisor` if given (the global input scale factor)
+pub fn scale_inputs(inputs: &mut [TypedBuffer], scalings: &[InputScaling], fallback_divisor: Option<f32>) {
+    for (i, buffer) in inputs.iter_mut().enumerate() {
+        let TypedBuffer::F32(buffer) = buffer else {
+            continue;
+        };
+        match scalings.get(i) {
+            Some(scaling) if scaling.is_detected() => scaling.apply(buffer.data_mut()),
+            _ => {
+                if let Some(divisor) = fallback_divisor {
+                    for value in buffer.data_mut() {
+                        *value /= divisor;
+                    }
+                }
+            }
+        }
+    }
+}
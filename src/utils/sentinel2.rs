@@ -0,0 +1,422 @@
+// src/utils/sentinel2.rs
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use gdal::Dataset;
+
+use crate::utils::cache::RasterCache;
+use crate::utils::gdal_ext::read_vsi_file;
+use crate::utils::vrt::{SingleBandVrt, VrtSource};
+
+/// Resolution (m) bands are delivered at when none is requested, the grid of B02/B03/B04/B08
+pub const DEFAULT_RESOLUTION: u32 = 10;
+
+const METADATA_FILES: [&str; 2] = ["MTD_MSIL2A.xml", "MTD_MSIL1C.xml"];
+
+/// Spectral bands in the order of the `band_id` attributes of the product metadata
+const SPECTRAL_BANDS: [&str; 13] = [
+    "B01", "B02", "B03", "B04", "B05", "B06", "B07", "B08", "B8A", "B09", "B10", "B11", "B12",
+];
+
+/// Band roles shared with Landsat products, so the same commands work on both
+const ROLES: [(&str, &str); 13] = [
+    ("coastal", "B01"),
+    ("blue", "B02"),
+    ("green", "B03"),
+    ("red", "B04"),
+    ("rededge1", "B05"),
+    ("rededge2", "B06"),
+    ("rededge3", "B07"),
+    ("nir", "B08"),
+    ("nir08", "B8A"),
+    ("watervapor", "B09"),
+    ("cirrus", "B10"),
+    ("swir1", "B11"),
+    ("swir2", "B12"),
+];
+
+/// A Sentinel-2 L1C or L2A product (`.SAFE` directory or zipped product)
+#[derive(Debug)]
+pub struct Sentinel2Product {
+    /// Product level, `L2A` or `L1C`
+    pub level: String,
+    /// Image files per band name, finest resolution first
+    files: HashMap<String, Vec<(u32, String)>>,
+    /// Digital number of reflectance 1.0 (BOA or TOA quantification value)
+    pub quantification: Option<f32>,
+    /// Radiometric offsets (in digital numbers) per spectral band, from processing baseline 04.00
+    offsets: HashMap<String, f32>,
+}
+
+impl Sentinel2Product {
+    /// Open a product from its `.SAFE` directory, its zip file or its `MTD_MSIL*.xml`
+    pub fn open(path: &str) -> Result<Self> {
+        let path = path.trim_end_matches(['/', '\\']);
+        let lower = path.to_lowercase();
+
+        let root = if lower.ends_with(".zip") {
+            let absolute = std::fs::canonicalize(path)
+                .with_context(|| format!("Sentinel-2 product {} not found", path))?;
+            let archive = format!("/vsizip/{}", absolute.to_string_lossy());
+            let safe = gdal::vsi::read_dir(&archive, false)?
+                .into_iter()
+                .find(|entry| entry.to_string_lossy().to_lowercase().ends_with(".safe"))
+                .ok_or_else(|| anyhow::anyhow!("No .SAFE directory in {}", path))?;
+            format!("{}/{}", archive, safe.to_string_lossy())
+        } else if lower.ends_with(".xml") {
+            Path::new(path)
+                .parent()
+                .map_or(".".to_string(), |p| p.to_string_lossy().to_string())
+        } else {
+            path.to_string()
+        };
+
+        let (level, metadata) = METADATA_FILES
+            .iter()
+            .find_map(|name| {
+                let bytes = read_vsi_file(&format!("{}/{}", root, name)).ok()?;
+                Some((name[7..10].to_string(), String::from_utf8_lossy(&bytes).to_string()))
+            })
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} is not a Sentinel-2 product: no {} found",
+                    path,
+                    METADATA_FILES.join(" or ")
+                )
+            })?;
+
+        let mut files: HashMap<String, Vec<(u32, String)>> = HashMap::new();
+        for tag in ["IMAGE_FILE", "IMAGE_FILE_2A"] {
+            for image in xml_values(&metadata, tag) {
+                let Some((band, resolution)) = parse_image_name(&image) else {
+                    continue;
+                };
+                files
+                    .entry(band)
+                    .or_default()
+                    .push((resolution, format!("{}/{}.jp2", root, image)));
+            }
+        }
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("No image files listed in the metadata of {}", path));
+        }
+        for candidates in files.values_mut() {
+            candidates.sort();
+        }
+
+        let quantification = ["BOA_QUANTIFICATION_VALUE", "QUANTIFICATION_VALUE"]
+            .iter()
+            .find_map(|tag| xml_values(&metadata, tag).first()?.parse().ok());
+
+        let mut offsets = HashMap::new();
+        for tag in ["BOA_ADD_OFFSET", "RADIO_ADD_OFFSET"] {
+            for (attributes, value) in xml_elements(&metadata, tag) {
+                let band = xml_attribute(&attributes, "band_id")
+                    .and_then(|id| id.parse::<usize>().ok())
+                    .and_then(|id| SPECTRAL_BANDS.get(id));
+                if let (Some(band), Ok(value)) = (band, value.parse()) {
+                    offsets.insert(band.to_string(), value);
+                }
+            }
+        }
+
+        Ok(Self {
+            level,
+            files,
+            quantification,
+            offsets,
+        })
+    }
+
+    /// Scale and offset turning digital numbers of a spectral band into reflectance,
+    /// `None` for other bands (SCL, AOT, WVP, TCI) or without a quantification value
+    pub fn reflectance_scaling(&self, band: &str) -> Option<(f32, f32)> {
+        let band = normalize_band(band);
+        if !SPECTRAL_BANDS.contains(&band.as_str()) {
+            return None;
+        }
+        let quantification = self.quantification.filter(|q| *q > 0.0)?;
+        let offset = self.offsets.get(&band).copied().unwrap_or(0.0);
+        Some((1.0 / quantification, offset / quantification))
+    }
+
+    /// Band names available in the product, sorted
+    pub fn bands(&self) -> Vec<String> {
+        let mut bands: Vec<String> = self.files.keys().cloned().collect();
+        bands.sort();
+        bands
+    }
+
+    /// Path of `band` at `resolution` metres (default 10 m).
+    ///
+    /// Resolutions not delivered in the product are resampled from the finest one through a
+    /// VRT of the run of `cache`: nearest for the scene classification, average when coarsening,
+    /// bilinear otherwise.
+    pub fn band_path(&self, band: &str, resolution: Option<u32>, cache: &RasterCache) -> Result<String> {
+        let band = normalize_band(band);
+        let resolution = resolution.unwrap_or(DEFAULT_RESOLUTION);
+        let candidates = self.files.get(&band).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Band {} not found in Sentinel-2 {} product. Available bands: {}",
+                band,
+                self.level,
+                self.bands().join(", ")
+            )
+        })?;
+
+        if let Some((_, path)) = candidates.iter().find(|(r, _)| *r == resolution) {
+            return Ok(path.clone());
+        }
+
+        let (source_resolution, source) = &candidates[0];
+        let method = if band == "SCL" {
+            "nearest"
+        } else if resolution > *source_resolution {
+            "average"
+        } else {
+            "bilinear"
+        };
+        resample(source, *source_resolution, resolution, method, cache)
+    }
+}
+
+/// Split a `<product>.SAFE:<band>` (or `.zip:`) reference, the band may end in `_<res>m`
+pub fn parse_band_reference(input: &str) -> Option<(&str, &str, Option<u32>)> {
+    let (product, band) = input.rsplit_once(':')?;
+    let lower = product.trim_end_matches(['/', '\\']).to_lowercase();
+    if !(lower.ends_with(".safe") || lower.ends_with(".zip") || lower.ends_with(".xml")) {
+        return None;
+    }
+    if !is_band_name(band) {
+        return None;
+    }
+
+    match band.split_once('_') {
+        Some((band, resolution)) => {
+            let resolution = resolution.strip_suffix('m')?.parse().ok()?;
+            Some((product, band, Some(resolution)))
+        }
+        None => Some((product, band, None)),
+    }
+}
+
+/// Returns `true` for Sentinel-2 band names like `B04`, `B8A`, `SCL` or `B11_20m` and roles (`nir`)
+pub fn is_band_name(name: &str) -> bool {
+    let band = match name.split_once('_') {
+        Some((band, resolution)) => {
+            let Some(metres) = resolution.strip_suffix('m') else {
+                return false;
+            };
+            if metres.is_empty() || !metres.bytes().all(|b| b.is_ascii_digit()) {
+                return false;
+            }
+            band
+        }
+        None => name,
+    };
+
+    if ROLES.iter().any(|(role, _)| role.eq_ignore_ascii_case(band)) {
+        return true;
+    }
+    let band = band.to_uppercase();
+    if matches!(band.as_str(), "SCL" | "AOT" | "WVP" | "TCI") {
+        return true;
+    }
+    match band.strip_prefix('B') {
+        Some(number) => {
+            let number = number.strip_suffix('A').unwrap_or(number);
+            (1..=2).contains(&number.len()) && number.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Turn a bare band name into a reference to `product`, and add `resolution` to references
+/// without one. Anything else (including existing files named like a band) is left as is.
+pub fn qualify_input(input: &str, product: Option<&str>, resolution: Option<u32>) -> String {
+    let suffix = resolution.map_or(String::new(), |r| format!("_{}m", r));
+
+    if let Some((product, band, None)) = parse_band_reference(input) {
+        return format!("{}:{}{}", product, band, suffix);
+    }
+    match product {
+        Some(product) if is_band_name(input) && !Path::new(input).exists() => {
+            if input.contains('_') {
+                format!("{}:{}", product, input)
+            } else {
+                format!("{}:{}{}", product, input, suffix)
+            }
+        }
+        _ => input.to_string(),
+    }
+}
+
+/// Resolve a band reference to an openable path, `None` if `input` is not a band reference.
+///
+/// Products are opened once and shared by later calls with the same `cache`.
+pub fn resolve_band(input: &str, cache: &RasterCache) -> Result<Option<String>> {
+    let Some((product, band, resolution)) = parse_band_reference(input) else {
+        return Ok(None);
+    };
+
+    open_cached(product, cache)?
+        .band_path(band, resolution, cache)
+        .with_context(|| format!("Failed to resolve '{}'", input))
+        .map(Some)
+}
+
+/// Reflectance scale and offset of a Sentinel-2 band, from a band reference or an image file
+/// inside a `.SAFE` directory. `None` for other inputs.
+pub fn band_scaling(input: &str, cache: &RasterCache) -> Result<Option<(f32, f32)>> {
+    if let Some((product, band, _)) = parse_band_reference(input) {
+        return Ok(open_cached(product, cache)?.reflectance_scaling(band));
+    }
+
+    let Some(end) = input.to_lowercase().find(".safe/").map(|i| i + ".safe".len()) else {
+        return Ok(None);
+    };
+    let stem = Path::new(input)
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().to_string());
+    match parse_image_name(&stem) {
+        Some((band, _)) => Ok(open_cached(&input[..end], cache)?.reflectance_scaling(&band)),
+        None => Ok(None),
+    }
+}
+
+/// Open a product once and share it with later calls of the run
+fn open_cached(path: &str, cache: &RasterCache) -> Result<Arc<Sentinel2Product>> {
+    cache.shared(path, || Sentinel2Product::open(path))
+}
+
+/// `B4` -> `B04`, `b8a` -> `B8A`, `nir` -> `B08`
+fn normalize_band(band: &str) -> String {
+    if let Some((_, name)) = ROLES.iter().find(|(role, _)| role.eq_ignore_ascii_case(band)) {
+        return name.to_string();
+    }
+    let band = band.to_uppercase();
+    match band.strip_prefix('B') {
+        Some(number) if number.len() == 1 => format!("B0{}", number),
+        _ => band,
+    }
+}
+
+/// Native resolution of a band, for L1C file names that do not carry one
+fn native_resolution(band: &str) -> u32 {
+    match band {
+        "B01" | "B09" | "B10" => 60,
+        "B05" | "B06" | "B07" | "B8A" | "B11" | "B12" | "SCL" => 20,
+        _ => 10,
+    }
+}
+
+/// Band name and resolution of an `IMAGE_FILE` entry,
+/// e.g. `GRANULE/.../R10m/T32TQM_20230615T101559_B04_10m` or `.../T32TQM_20230615T101559_B04`
+fn parse_image_name(image: &str) -> Option<(String, u32)> {
+    let name = image.rsplit('/').next()?;
+    let mut parts = name.rsplit('_');
+    let last = parts.next()?;
+
+    if let Some(metres) = last.strip_suffix('m') {
+        if let Ok(resolution) = metres.parse() {
+            let band = parts.next()?;
+            return is_band_name(band).then(|| (normalize_band(band), resolution));
+        }
+    }
+    is_band_name(last).then(|| {
+        let band = normalize_band(last);
+        let resolution = native_resolution(&band);
+        (band, resolution)
+    })
+}
+
+/// Text content of every `<tag>` element
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    xml_elements(xml, tag).into_iter().map(|(_, value)| value).collect()
+}
+
+/// Attributes and text content of every `<tag>` element
+fn xml_elements(xml: &str, tag: &str) -> Vec<(String, String)> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut elements = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        // Skip longer tag names sharing the prefix, e.g. IMAGE_FILE_2A for IMAGE_FILE
+        if !rest.starts_with(['>', ' ', '\t', '\n', '\r']) {
+            continue;
+        }
+        let Some(content_start) = rest.find('>') else {
+            break;
+        };
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        if content_start < end {
+            elements.push((
+                rest[..content_start].trim().to_string(),
+                rest[content_start + 1..end].trim().to_string(),
+            ));
+        }
+        rest = &rest[end + close.len()..];
+    }
+
+    elements
+}
+
+/// Value of `name="..."` in the attributes of an element
+fn xml_attribute(attributes: &str, name: &str) -> Option<String> {
+    let start = attributes.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = attributes[start..].find('"')?;
+    Some(attributes[start..start + end].to_string())
+}
+
+/// VRT in the run's `/vsimem/` directory resampling `source` from `source_resolution` to
+/// `resolution`, built once
+fn resample(source: &str, source_resolution: u32, resolution: u32, method: &str, cache: &RasterCache) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    (source, resolution, method).hash(&mut hasher);
+    let key = hasher.finish();
+
+    cache.build_vrt(key, &format!("s2_{:016x}_{}m", key, resolution), || {
+        let dataset = Dataset::open(source)?;
+        let (width, height) = dataset.raster_size();
+        let gt = dataset.geo_transform()?;
+        let band = dataset.rasterband(1)?;
+        let ratio = source_resolution as f64 / resolution as f64;
+        let (new_width, new_height) = (
+            ((width as f64 * ratio).round() as usize).max(1),
+            ((height as f64 * ratio).round() as usize).max(1),
+        );
+
+        let vrt = SingleBandVrt {
+            size: (new_width, new_height),
+            projection: dataset.projection(),
+            geo_transform: [
+                gt[0],
+                gt[1] / ratio,
+                gt[2] / ratio,
+                gt[3],
+                gt[4] / ratio,
+                gt[5] / ratio,
+            ],
+            data_type: band.band_type().name(),
+            nodata: band.no_data_value(),
+            pixel_function: None,
+            sources: vec![VrtSource {
+                path: source.to_string(),
+                size: (width, height),
+                dst_rect: (0.0, 0.0, new_width as f64, new_height as f64),
+                nodata: None,
+                resampling: Some(method.to_string()),
+            }],
+        };
+        Ok(vrt.to_xml())
+    })
+}
@@ -0,0 +1,369 @@
+// src/utils/stac.rs
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
+use gdal::Dataset;
+use serde_json::{json, Value};
+
+use crate::utils::cache::RasterCache;
+use crate::utils::dates;
+use crate::utils::landsat;
+use crate::utils::provenance::Provenance;
+
+const STAC_VERSION: &str = "1.0.0";
+const STAC_EXTENSIONS: [&str; 3] = [
+    "https://stac-extensions.github.io/projection/v1.1.0/schema.json",
+    "https://stac-extensions.github.io/raster/v1.1.0/schema.json",
+    "https://stac-extensions.github.io/processing/v1.1.0/schema.json",
+];
+
+/// Band roles of Sentinel-2/Landsat products and the matching STAC common names
+const COMMON_NAME_ALIASES: [(&str, &str); 4] = [
+    ("swir1", "swir16"),
+    ("swir2", "swir22"),
+    ("thermal", "lwir11"),
+    ("tirs2", "lwir12"),
+];
+
+/// One asset of a STAC item
+#[derive(Debug, Clone, PartialEq)]
+pub struct StacAsset {
+    pub key: String,
+    pub href: String,
+    /// Local path of the asset (relative hrefs resolved against the item), `None` for remote hrefs
+    pub path: Option<String>,
+    /// `eo:bands` names and common names of the first band
+    pub band_names: Vec<String>,
+    /// `raster:bands` scale and offset of the first band
+    pub scale: Option<f32>,
+    pub offset: Option<f32>,
+    pub nodata: Option<f32>,
+}
+
+impl StacAsset {
+    fn matches(&self, name: &str) -> bool {
+        self.key.eq_ignore_ascii_case(name)
+            || self.band_names.iter().any(|band| band.eq_ignore_ascii_case(name))
+    }
+}
+
+/// A STAC item read from a local JSON file
+#[derive(Debug, Clone)]
+pub struct StacItem {
+    pub id: String,
+    pub assets: Vec<StacAsset>,
+}
+
+impl StacItem {
+    pub fn open(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read STAC item {}", path))?;
+        let json: Value = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse STAC item {}", path))?;
+        if json.get("type").and_then(Value::as_str) != Some("Feature") {
+            return Err(anyhow::anyhow!("{} is not a STAC item (type must be \"Feature\")", path));
+        }
+
+        let base = Path::new(path).parent().unwrap_or(Path::new(""));
+        let id = json.get("id").and_then(Value::as_str).unwrap_or_default().to_string();
+        let mut assets = Vec::new();
+        if let Some(Value::Object(map)) = json.get("assets") {
+            for (key, asset) in map {
+                let Some(href) = asset.get("href").and_then(Value::as_str) else {
+                    continue;
+                };
+                assets.push(read_asset(key, href, asset, base));
+            }
+        }
+
+        Ok(Self { id, assets })
+    }
+
+    /// Asset by key, `eo:bands` name or common name (`nir`, `swir16`); band roles such as
+    /// `swir1` also match their common name
+    pub fn asset(&self, name: &str) -> Option<&StacAsset> {
+        let alias = COMMON_NAME_ALIASES
+            .iter()
+            .find(|(role, _)| role.eq_ignore_ascii_case(name))
+            .map(|(_, common)| *common);
+        self.assets
+            .iter()
+            .find(|asset| asset.matches(name))
+            .or_else(|| alias.and_then(|alias| self.assets.iter().find(|asset| asset.matches(alias))))
+    }
+
+    pub fn asset_path(&self, name: &str) -> Result<String> {
+        let asset = self.asset(name).ok_or_else(|| {
+            let keys: Vec<&str> = self.assets.iter().map(|a| a.key.as_str()).collect();
+            anyhow::anyhow!(
+                "STAC item {} has no asset or band '{}' (assets: {})",
+                self.id,
+                name,
+                keys.join(", ")
+            )
+        })?;
+        asset.path.clone().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Asset '{}' of STAC item {} is remote ({}); only local assets are read, download it first",
+                asset.key,
+                self.id,
+                asset.href
+            )
+        })
+    }
+}
+
+/// Path, band names and `raster:bands` values of an asset. Also reads the STAC 1.1 `bands` array.
+fn read_asset(key: &str, href: &str, asset: &Value, base: &Path) -> StacAsset {
+    let mut band_names = Vec::new();
+    for field in ["eo:bands", "bands"] {
+        if let Some(band) = asset.get(field).and_then(|b| b.get(0)) {
+            for name in ["name", "common_name", "eo:common_name"] {
+                if let Some(value) = band.get(name).and_then(Value::as_str) {
+                    band_names.push(value.to_string());
+                }
+            }
+        }
+    }
+
+    let raster = asset
+        .get("raster:bands")
+        .and_then(|b| b.get(0))
+        .or_else(|| asset.get("bands").and_then(|b| b.get(0)));
+    let number = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| raster.and_then(|r| r.get(*name)).and_then(Value::as_f64))
+            .map(|v| v as f32)
+    };
+    let nodata = number(&["nodata"]).or_else(|| asset.get("nodata").and_then(Value::as_f64).map(|v| v as f32));
+
+    StacAsset {
+        key: key.to_string(),
+        href: href.to_string(),
+        path: resolve_href(href, base),
+        band_names,
+        scale: number(&["scale", "raster:scale"]),
+        offset: number(&["offset", "raster:offset"]),
+        nodata,
+    }
+}
+
+/// Local path of an href: relative to the item or `file://`; `None` for remote (URL) hrefs
+fn resolve_href(href: &str, base: &Path) -> Option<String> {
+    if let Some(path) = href.strip_prefix("file://") {
+        Some(path.to_string())
+    } else if href.contains("://") {
+        None
+    } else if Path::new(href).is_absolute() || href.starts_with("/vsi") {
+        Some(href.to_string())
+    } else {
+        Some(base.join(href.trim_start_matches("./")).to_string_lossy().to_string())
+    }
+}
+
+/// True for a STAC item JSON file (Landsat `_MTL.json` files are products of their own)
+pub fn is_item_path(path: &str) -> bool {
+    path.to_lowercase().ends_with(".json") && !landsat::is_product_path(path)
+}
+
+/// Split `item.json:nir` into the item and the asset
+pub fn parse_band_reference(input: &str) -> Option<(&str, &str)> {
+    let (item, asset) = input.rsplit_once(':')?;
+    (is_item_path(item) && !asset.is_empty()).then_some((item, asset))
+}
+
+/// Turn an asset key or band name of `item` into a reference, anything else is left as is.
+/// Fails if the item cannot be read.
+pub fn qualify_input(input: &str, item: &str, cache: &RasterCache) -> Result<String> {
+    if Path::new(input).exists() {
+        return Ok(input.to_string());
+    }
+    let opened = open_cached(item, cache)?;
+    Ok(if opened.asset(input).is_some() {
+        format!("{}:{}", item, input)
+    } else {
+        input.to_string()
+    })
+}
+
+/// Resolve an asset reference to its file, `None` if `input` is not a STAC reference
+pub fn resolve_band(input: &str, cache: &RasterCache) -> Result<Option<String>> {
+    let Some((item, asset)) = parse_band_reference(input) else {
+        return Ok(None);
+    };
+    open_cached(item, cache)?.asset_path(asset).map(Some)
+}
+
+/// `raster:bands` scale, offset and nodata of an asset reference
+pub fn band_scaling(input: &str, cache: &RasterCache) -> Result<Option<(f32, f32, Option<f32>)>> {
+    let Some((item, asset)) = parse_band_reference(input) else {
+        return Ok(None);
+    };
+    let opened = open_cached(item, cache)?;
+    let Some(asset) = opened.asset(asset) else {
+        return Ok(None);
+    };
+    if asset.scale.is_none() && asset.offset.is_none() {
+        return Ok(None);
+    }
+    Ok(Some((
+        asset.scale.unwrap_or(1.0),
+        asset.offset.unwrap_or(0.0),
+        asset.nodata,
+    )))
+}
+
+/// Open an item once and share it with later calls of the run
+fn open_cached(path: &str, cache: &RasterCache) -> Result<Arc<StacItem>> {
+    cache.shared(path, || StacItem::open(path))
+}
+
+/// STAC item path of an output: the output path with a `.json` extension
+pub fn default_item_path(output: &str) -> String {
+    Path::new(output).with_extension("json").to_string_lossy().to_string()
+}
+
+/// Write a STAC item next to `output` describing it (projection and raster extensions, plus the
+/// provenance), with `extra_assets` (key, path, role) such as the class map or rendering.
+/// Returns the item path.
+pub fn write_item(output: &str, extra_assets: &[(&str, &str, &str)], provenance: &Provenance) -> Result<String> {
+    let dataset = Dataset::open(output).with_context(|| format!("Failed to open {} for its STAC item", output))?;
+    let (width, height) = dataset.raster_size();
+    let gt = dataset.geo_transform()?;
+    let item_path = default_item_path(output);
+    let item_dir = Path::new(&item_path).parent().unwrap_or(Path::new(""));
+
+    // Corners in the output projection, then in WGS84 for bbox and geometry
+    let mut xs: Vec<f64> = [(0.0, 0.0), (width as f64, 0.0), (width as f64, height as f64), (0.0, height as f64)]
+        .iter()
+        .map(|(col, row)| gt[0] + col * gt[1] + row * gt[2])
+        .collect();
+    let mut ys: Vec<f64> = [(0.0, 0.0), (width as f64, 0.0), (width as f64, height as f64), (0.0, height as f64)]
+        .iter()
+        .map(|(col, row)| gt[3] + col * gt[4] + row * gt[5])
+        .collect();
+    let native_bbox = bounds(&xs, &ys);
+
+    let mut properties = serde_json::Map::new();
+    let wkt = dataset.projection();
+    if !wkt.is_empty() {
+        let srs = SpatialRef::from_wkt(&wkt)?;
+        match (srs.auth_name(), srs.auth_code()) {
+            (Ok(name), Ok(code)) if name == "EPSG" => properties.insert("proj:epsg".into(), json!(code)),
+            _ => properties.insert("proj:wkt2".into(), json!(wkt)),
+        };
+        let mut wgs84 = SpatialRef::from_epsg(4326)?;
+        wgs84.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        let mut source = srs;
+        source.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        CoordTransform::new(&source, &wgs84)?.transform_coords(&mut xs, &mut ys, &mut [])?;
+    }
+    let bbox = bounds(&xs, &ys);
+    let ring: Vec<[f64; 2]> = (0..=4).map(|i| [xs[i % 4], ys[i % 4]]).collect();
+
+    let datetime = provenance
+        .inputs
+        .first()
+        .and_then(|input| dates::acquisition_date(&input.path))
+        .map_or_else(|| provenance.created.clone(), |date| format!("{}T00:00:00Z", date));
+    properties.insert("datetime".into(), json!(datetime));
+    properties.insert("created".into(), json!(provenance.created));
+    properties.insert("proj:shape".into(), json!([height, width]));
+    properties.insert("proj:transform".into(), json!([gt[1], gt[2], gt[0], gt[4], gt[5], gt[3]]));
+    properties.insert("proj:bbox".into(), json!(native_bbox));
+    properties.insert("processing:software".into(), json!({"raster-calc": provenance.version}));
+    properties.insert("raster_calc:provenance".into(), provenance.to_json());
+
+    let mut raster_bands = Vec::new();
+    for i in 1..=dataset.raster_count() {
+        let band = dataset.rasterband(i)?;
+        let mut values = serde_json::Map::new();
+        values.insert("data_type".into(), json!(stac_data_type(&band.band_type().name())));
+        if let Some(nodata) = band.no_data_value() {
+            values.insert("nodata".into(), json!(nodata));
+        }
+        if let Some(scale) = band.scale() {
+            values.insert("scale".into(), json!(scale));
+        }
+        if let Some(offset) = band.offset() {
+            values.insert("offset".into(), json!(offset));
+        }
+        raster_bands.push(Value::Object(values));
+    }
+
+    let mut assets = serde_json::Map::new();
+    assets.insert(
+        "data".into(),
+        json!({
+            "href": relative_href(output, item_dir),
+            "type": media_type(output),
+            "title": provenance.index,
+            "roles": ["data"],
+            "raster:bands": raster_bands,
+        }),
+    );
+    for (key, path, role) in extra_assets {
+        assets.insert(
+            key.to_string(),
+            json!({"href": relative_href(path, item_dir), "type": media_type(path), "roles": [role]}),
+        );
+    }
+
+    let id = Path::new(output)
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().to_string());
+    let item = json!({
+        "type": "Feature",
+        "stac_version": STAC_VERSION,
+        "stac_extensions": STAC_EXTENSIONS,
+        "id": id,
+        "bbox": bbox,
+        "geometry": {"type": "Polygon", "coordinates": [ring]},
+        "properties": properties,
+        "links": [],
+        "assets": assets,
+    });
+    std::fs::write(&item_path, serde_json::to_string_pretty(&item)?)
+        .with_context(|| format!("Failed to write STAC item {}", item_path))?;
+    Ok(item_path)
+}
+
+fn bounds(xs: &[f64], ys: &[f64]) -> [f64; 4] {
+    let min = |v: &[f64]| v.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = |v: &[f64]| v.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    [min(xs), min(ys), max(xs), max(ys)]
+}
+
+/// Href of `path` relative to the item directory when it lies inside it
+fn relative_href(path: &str, item_dir: &Path) -> String {
+    Path::new(path)
+        .strip_prefix(item_dir)
+        .map_or(path.to_string(), |p| format!("./{}", p.to_string_lossy()))
+}
+
+/// Media type of an asset from its extension
+pub fn media_type(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .map_or(String::new(), |e| e.to_string_lossy().to_lowercase());
+    match extension.as_str() {
+        "tif" | "tiff" => "image/tiff; application=geotiff",
+        "gpkg" => "application/geopackage+sqlite3",
+        "nc" => "application/netcdf",
+        "zarr" => "application/vnd+zarr",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// STAC `raster:bands` data type of a GDAL data type name
+fn stac_data_type(gdal_type: &str) -> String {
+    match gdal_type {
+        "Byte" => "uint8".to_string(),
+        other => other.to_lowercase(),
+    }
+}
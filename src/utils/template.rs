@@ -0,0 +1,146 @@
+// src/utils/template.rs
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde_json::Value;
+
+/// Values of the placeholders of one scene, e.g. `tile` -> `T32TQM`
+pub type Variables = BTreeMap<String, String>;
+
+enum Part<'a> {
+    Literal(&'a str),
+    Variable(&'a str),
+}
+
+/// Split `data/{tile}/S2_{date}.tif` into literals and placeholders
+fn parts(pattern: &str) -> Result<Vec<Part<'_>>> {
+    let mut parts = Vec::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| anyhow::anyhow!("Unclosed placeholder in '{}'", pattern))?;
+        if start > 0 {
+            parts.push(Part::Literal(&rest[..start]));
+        }
+        let name = &rest[start + 1..end];
+        if !is_variable_name(name) {
+            return Err(anyhow::anyhow!("Invalid placeholder '{{{}}}' in '{}'", name, pattern));
+        }
+        parts.push(Part::Variable(name));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest));
+    }
+    Ok(parts)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Glob pattern matching the files of a scene pattern (placeholders become `*`, glob
+/// metacharacters in the rest are matched literally)
+pub fn glob_pattern(pattern: &str) -> Result<String> {
+    Ok(parts(pattern)?
+        .iter()
+        .map(|part| match part {
+            Part::Literal(text) => glob::Pattern::escape(text),
+            Part::Variable(_) => "*".to_string(),
+        })
+        .collect())
+}
+
+/// Values of the placeholders of `pattern` in `path`, `None` if it does not match.
+/// Placeholders never span directories; a placeholder used twice must match the same text.
+/// `\` and `/` separators are the same, so Windows glob results match `/` patterns.
+pub fn capture(pattern: &str, path: &str) -> Result<Option<Variables>> {
+    let pattern = pattern.replace('\\', "/");
+    let parts = parts(&pattern)?;
+    let mut variables = Variables::new();
+    Ok(match_parts(&parts, &path.replace('\\', "/"), &mut variables).then_some(variables))
+}
+
+fn match_parts(parts: &[Part], text: &str, variables: &mut Variables) -> bool {
+    let Some((first, rest)) = parts.split_first() else {
+        return text.is_empty();
+    };
+    match first {
+        Part::Literal(literal) => text
+            .strip_prefix(literal)
+            .is_some_and(|remaining| match_parts(rest, remaining, variables)),
+        Part::Variable(name) => {
+            if let Some(value) = variables.get(*name).cloned() {
+                return text
+                    .strip_prefix(value.as_str())
+                    .is_some_and(|remaining| match_parts(rest, remaining, variables));
+            }
+            // Shortest match first, backtracking on failure
+            let limit = text.find('/').unwrap_or(text.len());
+            for end in 1..=limit {
+                if !text.is_char_boundary(end) {
+                    continue;
+                }
+                variables.insert(name.to_string(), text[..end].to_string());
+                if match_parts(rest, &text[end..], variables) {
+                    return true;
+                }
+            }
+            variables.remove(*name);
+            false
+        }
+    }
+}
+
+/// Replace the `{name}` placeholders of `text` by their values; unknown placeholders are an error
+pub fn substitute(text: &str, variables: &Variables) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find('}').map(|end| start + end);
+        match end.map(|end| (&rest[start + 1..end], end)) {
+            Some((name, end)) if is_variable_name(name) => {
+                let value = variables.get(name).ok_or_else(|| {
+                    let known: Vec<&str> = variables.keys().map(String::as_str).collect();
+                    anyhow::anyhow!(
+                        "Unknown placeholder '{{{}}}' in '{}' (scene variables: {})",
+                        name,
+                        text,
+                        known.join(", ")
+                    )
+                })?;
+                result.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            // Not a placeholder (e.g. JSON in a string), keep the brace
+            _ => {
+                result.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Substitute placeholders in every string (and object key) of a JSON value
+pub fn substitute_value(value: &Value, variables: &Variables) -> Result<Value> {
+    Ok(match value {
+        Value::String(text) => Value::String(substitute(text, variables)?),
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| substitute_value(value, variables))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| Ok((substitute(key, variables)?, substitute_value(value, variables)?)))
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
@@ -0,0 +1,108 @@
+// src/utils/vrt.rs
+/// One source window of a [`SingleBandVrt`]
+#[derive(Debug, Clone)]
+pub struct VrtSource {
+    pub path: String,
+    /// Source raster size read in full (width, height)
+    pub size: (usize, usize),
+    /// Destination window in VRT pixels (x offset, y offset, width, height)
+    pub dst_rect: (f64, f64, f64, f64),
+    /// Source values treated as transparent, written as a `ComplexSource`
+    pub nodata: Option<f64>,
+    /// Resampling method, GDAL's default (nearest) when `None`
+    pub resampling: Option<String>,
+}
+
+/// A VRT with a single band made of the first band of each source
+#[derive(Debug, Clone)]
+pub struct SingleBandVrt {
+    pub size: (usize, usize),
+    pub projection: String,
+    pub geo_transform: [f64; 6],
+    pub data_type: String,
+    pub nodata: Option<f64>,
+    /// Pixel function combining the sources instead of painting them in order
+    pub pixel_function: Option<String>,
+    pub sources: Vec<VrtSource>,
+}
+
+impl SingleBandVrt {
+    /// VRT XML of the dataset
+    pub fn to_xml(&self) -> String {
+        let gt = self.geo_transform;
+        let mut vrt = format!(
+            "<VRTDataset rasterXSize=\"{}\" rasterYSize=\"{}\">\n",
+            self.size.0, self.size.1
+        );
+        if !self.projection.is_empty() {
+            vrt.push_str(&format!("  <SRS>{}</SRS>\n", escape_xml(&self.projection)));
+        }
+        vrt.push_str(&format!(
+            "  <GeoTransform>{}, {}, {}, {}, {}, {}</GeoTransform>\n",
+            gt[0], gt[1], gt[2], gt[3], gt[4], gt[5]
+        ));
+
+        match &self.pixel_function {
+            Some(function) => {
+                vrt.push_str(&format!(
+                    "  <VRTRasterBand dataType=\"{}\" band=\"1\" subClass=\"VRTDerivedRasterBand\">\n",
+                    self.data_type
+                ));
+                vrt.push_str(&format!(
+                    "    <PixelFunctionType>{}</PixelFunctionType>\n",
+                    escape_xml(function)
+                ));
+            }
+            None => vrt.push_str(&format!(
+                "  <VRTRasterBand dataType=\"{}\" band=\"1\">\n",
+                self.data_type
+            )),
+        }
+        if let Some(nodata) = self.nodata {
+            vrt.push_str(&format!("    <NoDataValue>{}</NoDataValue>\n", nodata));
+        }
+
+        for source in &self.sources {
+            let element = if source.nodata.is_some() { "ComplexSource" } else { "SimpleSource" };
+            match &source.resampling {
+                Some(method) => vrt.push_str(&format!(
+                    "    <{} resampling=\"{}\">\n",
+                    element,
+                    escape_xml(method)
+                )),
+                None => vrt.push_str(&format!("    <{}>\n", element)),
+            }
+            vrt.push_str(&format!(
+                "      <SourceFilename relativeToVRT=\"0\">{}</SourceFilename>\n",
+                escape_xml(&source.path)
+            ));
+            vrt.push_str("      <SourceBand>1</SourceBand>\n");
+            vrt.push_str(&format!(
+                "      <SrcRect xOff=\"0\" yOff=\"0\" xSize=\"{}\" ySize=\"{}\"/>\n",
+                source.size.0, source.size.1
+            ));
+            let (x, y, width, height) = source.dst_rect;
+            vrt.push_str(&format!(
+                "      <DstRect xOff=\"{}\" yOff=\"{}\" xSize=\"{}\" ySize=\"{}\"/>\n",
+                x, y, width, height
+            ));
+            if let Some(nodata) = source.nodata {
+                vrt.push_str(&format!("      <NODATA>{}</NODATA>\n", nodata));
+            }
+            vrt.push_str(&format!("    </{}>\n", element));
+        }
+
+        vrt.push_str("  </VRTRasterBand>\n");
+        vrt.push_str("</VRTDataset>\n");
+        vrt
+    }
+}
+
+/// Escape a value for use in VRT XML
+pub fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
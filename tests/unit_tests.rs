@@ -1,8 +1,33 @@
 // tests/unit_tests.rs
 use gdal::raster::Buffer;
 use raster_calc::processing::indices::{NDI, EVI, SAVI, NDWI, BSI, NDSI};
-use raster_calc::processing::parallel::IndexCalculator;
+use raster_calc::processing::composite::{Composite, CompositeRule};
+use raster_calc::processing::change::ChangeDetection;
+use raster_calc::processing::classify::{default_class_path, parse_color, Classification, NODATA_CLASS};
+use raster_calc::processing::parallel::{fixed_point_value, IndexCalculator, NODATA_VALUE_FLOAT};
+use raster_calc::processing::scheduler::Scheduler;
+use raster_calc::processing::gapfill::{per_date_paths, whittaker, FillMethod, GapFill};
+use raster_calc::processing::mask::{parse_values, Mask, MaskType};
+use raster_calc::processing::render::{default_render_path, parse_range, Colormap, Renderer};
+use raster_calc::processing::phenology::{savitzky_golay, Phenology, Smoothing};
+use raster_calc::processing::timeseries::{linear_slope, mann_kendall, percentile, sen_slope, StackInput, Statistic};
+use raster_calc::batch::{self, BatchGraph, GlobalParams};
+use raster_calc::utils::cache::RasterCache;
+use raster_calc::utils::dates::Date;
 use raster_calc::utils::gdal_ext::TypedBuffer;
+use raster_calc::utils::mosaic::{expand_tiles, is_mosaic_input, OverlapRule};
+use raster_calc::utils::output::OutputFormat;
+use raster_calc::utils::landsat::{self, split_band_name, LandsatProduct};
+use raster_calc::utils::scaling::{scale_inputs, InputScaling, ScalingSource};
+use raster_calc::utils::provenance::{format_timestamp, sha256_file};
+use raster_calc::utils::stac::{self, StacItem};
+use raster_calc::utils::template::{self, Variables};
+use raster_calc::utils::manifest::{self, Manifest};
+use raster_calc::utils::memory::{self, MemoryShares};
+use raster_calc::utils::retry::{self, OnError, RetryPolicy};
+use raster_calc::utils::report::{BandStatistics, BatchSummary, OperationReport, OperationStatus, ReportWriter};
+use raster_calc::utils::sentinel2::{is_band_name, parse_band_reference, qualify_input};
+use raster_calc::utils::vrt::{SingleBandVrt, VrtSource};
 
 /// Helper function to create test data with specific dimensions
 fn create_test_data(
@@ -368,4 +393,1078 @@ fn test_bsi_calculation() {
                 "Expected {}, got {} at index {}", expected, result_values[i], i);
         }
     }
-}
\ No newline at end of file
+}
+/// Test output format selection from names and file extensions
+#[test]
+fn test_output_format_selection() {
+    assert_eq!(OutputFormat::from_name("GTiff").unwrap(), OutputFormat::GTiff);
+    assert_eq!(OutputFormat::from_name("gpkg").unwrap(), OutputFormat::GeoPackage);
+    assert_eq!(OutputFormat::from_name("netCDF").unwrap(), OutputFormat::NetCDF);
+    assert_eq!(OutputFormat::from_name("HFA").unwrap(), OutputFormat::ErdasImg);
+    assert!(OutputFormat::from_name("not-a-format").is_err());
+
+    assert_eq!(OutputFormat::from_path("ndvi.TIF").unwrap(), OutputFormat::GTiff);
+    assert_eq!(OutputFormat::from_path("ndvi.zarr").unwrap(), OutputFormat::Zarr);
+    assert_eq!(OutputFormat::from_path("ndvi.img").unwrap(), OutputFormat::ErdasImg);
+    assert_eq!(OutputFormat::from_path("/vsimem/ndvi").unwrap(), OutputFormat::GTiff);
+    assert!(OutputFormat::from_path("ndvi.unknown").is_err());
+
+    // Explicit format wins over the extension
+    let format = OutputFormat::resolve(Some(OutputFormat::NetCDF), "ndvi.tif").unwrap();
+    assert_eq!(format, OutputFormat::NetCDF);
+    let options = format.creation_options("ZSTD", 12, true, true);
+    assert!(options.contains(&"COMPRESS=DEFLATE".to_string()));
+    assert!(options.contains(&"ZLEVEL=9".to_string()));
+}
+
+/// Test detection of tile list / glob mosaic inputs and overlap rule parsing
+#[test]
+fn test_mosaic_input_detection() {
+    assert!(!is_mosaic_input("tiles/T32TQM_B08.tif"));
+    assert!(is_mosaic_input("tiles/T32TQM_B08.tif,tiles/T32TQN_B08.tif"));
+    assert!(is_mosaic_input("tiles/*_B08.tif"));
+
+    // Existing files are read as they are, even with commas or brackets in their name
+    let dir = std::env::temp_dir().join(format!("raster_calc_mosaic_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let odd = dir.join("scene[1],b08.tif");
+    std::fs::write(&odd, b"").unwrap();
+    assert!(!is_mosaic_input(&odd.to_string_lossy()));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let tiles = expand_tiles("a.tif, b.tif,,c.tif").unwrap();
+    assert_eq!(tiles, vec!["a.tif", "b.tif", "c.tif"]);
+    assert!(expand_tiles("does/not/exist/*.tif").is_err());
+
+    assert_eq!(OverlapRule::from_name("first").unwrap(), OverlapRule::First);
+    assert_eq!(OverlapRule::from_name("MAX").unwrap(), OverlapRule::MaxValid);
+    assert_eq!(OverlapRule::default(), OverlapRule::Last);
+    assert!(OverlapRule::from_name("median").is_err());
+}
+
+/// Test the shared single-band VRT writer
+#[test]
+fn test_single_band_vrt() {
+    let source = VrtSource {
+        path: "tiles/a&b.tif".to_string(),
+        size: (10, 20),
+        dst_rect: (5.0, 0.0, 10.0, 20.0),
+        nodata: Some(0.0),
+        resampling: None,
+    };
+    let vrt = SingleBandVrt {
+        size: (15, 20),
+        projection: String::new(),
+        geo_transform: [100.0, 10.0, 0.0, 200.0, 0.0, -10.0],
+        data_type: "UInt16".to_string(),
+        nodata: Some(0.0),
+        pixel_function: Some("max".to_string()),
+        sources: vec![source.clone(), VrtSource { nodata: None, resampling: Some("average".to_string()), ..source }],
+    };
+    let xml = vrt.to_xml();
+
+    assert!(xml.contains("<VRTDataset rasterXSize=\"15\" rasterYSize=\"20\">"));
+    assert!(!xml.contains("<SRS>"));
+    assert!(xml.contains("<GeoTransform>100, 10, 0, 200, 0, -10</GeoTransform>"));
+    assert!(xml.contains("subClass=\"VRTDerivedRasterBand\""));
+    assert!(xml.contains("<PixelFunctionType>max</PixelFunctionType>"));
+    assert!(xml.contains("tiles/a&amp;b.tif"));
+    // Sources with nodata are complex sources, the others simple sources
+    assert!(xml.contains("<ComplexSource>") && xml.contains("<NODATA>0</NODATA>"));
+    assert!(xml.contains("<SimpleSource resampling=\"average\">"));
+    assert!(xml.contains("<DstRect xOff=\"5\" yOff=\"0\" xSize=\"10\" ySize=\"20\"/>"));
+}
+
+/// Test best-pixel composites over two acquisitions
+#[test]
+fn test_composite_rules() {
+    // Scene 1: NDVI 0.333, 0.0 ; Scene 2: NDVI 0.6, nodata
+    let scene1 = create_test_data(2, 1, &[5000.0, 3000.0], &[2500.0, 3000.0], None);
+    let scene2 = create_test_data(2, 1, &[4000.0, 0.0], &[1000.0, 0.0], None);
+    let clouds = create_test_data(2, 1, &[80.0, 10.0], &[5.0, 50.0], None);
+
+    let mut inputs = Vec::new();
+    inputs.extend(scene1);
+    inputs.extend(scene2);
+
+    let max = Composite::new(NDI::new(0, 1, None), 2, CompositeRule::MaxIndex, true, None);
+    assert_eq!(max.required_bands(), 4);
+    let bands = max.calculate_bands(&inputs);
+    let values = get_results(&bands[0]);
+    let sources = get_results(&bands[1]);
+    assert!((values[0] - 0.6).abs() < 0.0001);
+    assert_eq!(sources[0], 2.0);
+    assert_eq!(values[1], 0.0); // only scene 1 is valid
+    assert_eq!(sources[1], 1.0);
+    assert!(!max.is_scaled_band(1));
+
+    let median = Composite::new(NDI::new(0, 1, None), 2, CompositeRule::Median, false, None);
+    let values = get_results(&median.calculate(&inputs));
+    assert!((values[0] - 0.46667).abs() < 0.0001);
+
+    // Cloud bands follow the scene bands: scene 1 = [80, 10], scene 2 = [5, 50]
+    inputs.extend(clouds);
+    let clear = Composite::new(NDI::new(0, 1, None), 2, CompositeRule::LeastCloudy, true, None);
+    let bands = clear.calculate_bands(&inputs);
+    assert_eq!(get_results(&bands[1]), vec![2.0, 1.0]);
+}
+
+/// Test pre/post change detection and change classes
+#[test]
+fn test_change_detection() {
+    // Pre: NDVI 0.333, 0.0 ; Post: NDVI 0.6, nodata
+    let mut inputs = create_test_data(2, 1, &[5000.0, 3000.0], &[2500.0, 3000.0], None);
+    inputs.extend(create_test_data(2, 1, &[4000.0, 0.0], &[1000.0, 0.0], None));
+
+    let change = ChangeDetection::new(NDI::new(0, 1, None));
+    assert_eq!(change.required_bands(), 4);
+    assert_eq!(change.output_bands().len(), 2);
+    let bands = change.calculate_bands(&inputs);
+    let difference = get_results(&bands[0]);
+    let relative = get_results(&bands[1]);
+    assert!((difference[0] - 0.26667).abs() < 0.0001);
+    assert!((relative[0] - 0.8).abs() < 0.0001);
+    assert_eq!(difference[1], -999.0);
+
+    // Relative changes above 100% are kept, in percent in fixed-point outputs
+    let mut inputs = create_test_data(1, 1, &[3000.0], &[2500.0], None);
+    inputs.extend(create_test_data(1, 1, &[5000.0], &[1000.0], None));
+    let relative = get_results(&change.calculate_bands(&inputs)[1]);
+    assert!((relative[0] - 6.3333).abs() < 0.001);
+    assert_eq!(change.band_scale(1), Some(100.0));
+    assert_eq!(fixed_point_value(relative[0], change.band_scale(1), false), 633);
+    // Differences above 1 round-trip, e.g. a severe burn with a dNBR of 1.3
+    let severe = 1.3;
+    let written = fixed_point_value(severe, change.band_scale(0), change.band_scale(0).is_none());
+    assert_eq!(written, 13000);
+    assert!((written as f32 / change.band_scale(0).unwrap() - severe).abs() < 1e-4);
+    assert_eq!(fixed_point_value(-1.9, change.band_scale(0), false), -19000);
+    assert_eq!(fixed_point_value(-999.0, Some(100.0), false), -10000);
+
+    let classes = Classification::from_thresholds(&[0.1]).unwrap();
+    assert_eq!(classes, Classification::change(-0.1, 0.1).unwrap());
+    assert_eq!(classes.classify_values(&[-0.2, 0.05, 0.1, difference[0], -999.0]), vec![1, 2, 3, 3, NODATA_CLASS]);
+    assert_eq!(classes.category_names(), vec!["No data", "Decrease", "Stable", "Increase"]);
+    assert!(Classification::change(0.1, -0.1).is_err());
+    assert_eq!(default_class_path("out/ndvi_change.tif"), "out/ndvi_change_classes.tif");
+}
+
+/// Test date parsing from strings and file names
+#[test]
+fn test_acquisition_dates() {
+    let date = Date::parse("2024-07-02").unwrap();
+    assert_eq!(date, Date::new(2024, 7, 2).unwrap());
+    assert_eq!(date.day_of_year(), 184);
+    assert_eq!(Date::new(1970, 1, 1).unwrap().days_since_epoch(), 0);
+    assert_eq!(Date::new(2000, 3, 1).unwrap().days_since_epoch(), 11017);
+    assert!(Date::parse("2023-02-29").is_err());
+
+    let from_name = Date::from_path("S2B_MSIL2A_20230815T101559_N0509_R065_T32TQM_NDVI.tif");
+    assert_eq!(from_name, Some(Date::new(2023, 8, 15).unwrap()));
+    assert_eq!(Date::from_path("ndvi_2021-05-30.tif"), Some(Date::new(2021, 5, 30).unwrap()));
+    assert_eq!(Date::from_path("ndvi_tile_123456789.tif"), None);
+}
+
+/// Test per-pixel time series reductions and trend statistics
+#[test]
+fn test_timeseries_statistics() {
+    let series = [(2019.0, 0.30f32), (2020.0, 0.35), (2021.0, 0.32), (2022.0, 0.45), (2023.0, 0.50)];
+
+    let mut values: Vec<f64> = series.iter().map(|&(_, v)| v as f64).collect();
+    values.sort_by(|a, b| a.total_cmp(b));
+    assert!((percentile(&values, 50.0) - 0.35).abs() < 1e-6);
+    assert!((percentile(&values, 25.0) - 0.32).abs() < 1e-6);
+
+    let slope = linear_slope(&series).unwrap();
+    assert!((slope - 0.05).abs() < 1e-4, "slope {}", slope);
+
+    let sen = sen_slope(&series).unwrap();
+    assert!((sen - 0.05).abs() < 1e-4, "Sen's slope {}", sen);
+
+    let (tau, p) = mann_kendall(&series).unwrap();
+    assert!((tau - 0.8).abs() < 1e-6);
+    assert!(p > 0.05 && p < 0.1, "p-value {}", p);
+
+    assert!(mann_kendall(&series[..2]).is_none());
+    assert_eq!(Statistic::from_name("p90").unwrap(), Statistic::Percentile(90.0));
+    assert!(Statistic::from_name("p120").is_err());
+}
+
+/// Test season metrics of a synthetic NDVI season peaking on July 1st
+#[test]
+fn test_phenology_metrics() {
+    // Constant and linear series pass through Savitzky-Golay unchanged
+    let line: Vec<f64> = (0..9).map(|i| 0.1 * i as f64).collect();
+    for (smoothed, value) in savitzky_golay(&line, 7, 2).iter().zip(&line).skip(3).take(3) {
+        assert!((smoothed - value).abs() < 1e-9);
+    }
+
+    // Twice a month over 2023, Gaussian season of amplitude 0.6 around day 182
+    let dates: Vec<Date> = (1..=12)
+        .flat_map(|month| [1, 16].map(|day| Date::new(2023, month, day).unwrap()))
+        .collect();
+    let inputs: Vec<TypedBuffer> = dates
+        .iter()
+        .map(|d| {
+            let t = d.day_of_year() as f32;
+            TypedBuffer::F32(Buffer::new((1, 1), vec![0.2 + 0.6 * (-((t - 182.0) / 50.0).powi(2)).exp()]))
+        })
+        .collect();
+    let stack = vec![StackInput { nodata: None, scale: 1.0, offset: 0.0 }; dates.len()];
+
+    for method in ["savgol", "double_logistic"] {
+        let smoothing = Smoothing::from_name(method, 7, 2).unwrap();
+        let phenology = Phenology::new(smoothing, 0.5, &dates, stack.clone(), None).unwrap();
+        assert_eq!(phenology.output_bands().len(), 7);
+
+        let bands: Vec<f32> = phenology.calculate_bands(&inputs).iter().map(|b| get_results(b)[0]).collect();
+        // Half-amplitude crossings of the Gaussian are at 182 -/+ 41.6
+        assert!((bands[0] - 140.4).abs() < 5.0, "{} start of season {}", method, bands[0]);
+        assert!((bands[1] - 223.6).abs() < 5.0, "{} end of season {}", method, bands[1]);
+        assert!((bands[2] - 182.0).abs() < 8.0, "{} peak {}", method, bands[2]);
+        assert!((bands[5] - 0.6).abs() < 0.05, "{} amplitude {}", method, bands[5]);
+        assert!((bands[4] - (bands[1] - bands[0])).abs() < 1e-3);
+    }
+
+    assert!(Smoothing::from_name("savgol", 6, 2).is_err());
+}
+
+/// Test temporal gap filling of a series with missing observations
+#[test]
+fn test_gap_filling() {
+    let epoch = Date::new(2024, 1, 1).unwrap().days_since_epoch();
+    assert_eq!(Date::from_days_since_epoch(epoch + 59), Date::new(2024, 2, 29).unwrap());
+    assert_eq!(Date::from_days_since_epoch(0), Date::new(1970, 1, 1).unwrap());
+
+    // Every 10 days over 2024 with an annual cycle, every fourth observation is nodata
+    let dates: Vec<Date> = (0..36).map(|i| Date::from_days_since_epoch(epoch + i * 10)).collect();
+    let truth = |i: usize| 0.4 + 0.3 * (2.0 * std::f32::consts::PI * (i * 10) as f32 / 365.25).cos();
+    let inputs: Vec<TypedBuffer> = (0..dates.len())
+        .map(|i| {
+            let value = if i % 4 == 1 { -999.0 } else { truth(i) };
+            TypedBuffer::F32(Buffer::new((1, 1), vec![value]))
+        })
+        .collect();
+    let stack = vec![StackInput { nodata: Some(-999.0), scale: 1.0, offset: 0.0 }; dates.len()];
+
+    for (method, tolerance) in [("linear", 0.01), ("harmonic", 1e-4), ("whittaker", 0.01)] {
+        let method = FillMethod::from_name(method, 1, 100.0).unwrap();
+        let gapfill = GapFill::new(method, &dates, dates.clone(), stack.clone(), None);
+        assert_eq!(gapfill.output_bands()[1], "2024-01-11");
+
+        let filled: Vec<f32> = gapfill.calculate_bands(&inputs).iter().map(|b| get_results(b)[0]).collect();
+        for i in [1, 5, 17] {
+            assert!((filled[i] - truth(i)).abs() < tolerance, "{:?} day {}: {}", method, i * 10, filled[i]);
+        }
+    }
+
+    // Two observations: the Whittaker smoother reduces to the line through them
+    let mut values = vec![0.0; 11];
+    values[0] = 1.0;
+    values[10] = 2.0;
+    let mut weights = vec![0.0; 11];
+    weights[0] = 1.0;
+    weights[10] = 1.0;
+    let smoothed = whittaker(&values, &weights, 100.0).unwrap();
+    assert!((smoothed[5] - 1.5).abs() < 1e-6);
+
+    assert_eq!(per_date_paths("out/ndvi.tif", &dates[..1]), vec!["out/ndvi_2024-01-01.tif"]);
+}
+
+/// Test classification rules given as breaks, inline rules and JSON
+#[test]
+fn test_classification_rules() {
+    let breaks = Classification::parse("0,0.2,0.5").unwrap();
+    assert_eq!(breaks.classify_values(&[-0.3, 0.0, 0.3, 0.8, -999.0]), vec![1, 2, 3, 4, NODATA_CLASS]);
+    assert_eq!(breaks.category_names()[1], "< 0");
+    assert!(Classification::parse("0.5,0.2").is_err());
+
+    let inline = Classification::parse(":0=1:water:#2166ac,0:0.2=2:bare,0.2:=3:vegetation").unwrap();
+    assert_eq!(inline.classify_values(&[-0.1, 0.1, 0.9]), vec![1, 2, 3]);
+    assert_eq!(inline.rules[0].color, [0x21, 0x66, 0xac, 255]);
+    assert_eq!(inline.category_names(), vec!["No data", "water", "bare", "vegetation"]);
+
+    let json = Classification::parse(
+        r##"{"breaks": [0, 0.2, 0.5], "names": ["water", "bare", "sparse", "dense"]}"##,
+    )
+    .unwrap();
+    assert_eq!(json.classify(0.6), 4);
+    assert_eq!(json.rules[3].name, "dense");
+
+    let list = Classification::parse(r##"[{"min": 0.5, "class": 7, "name": "forest", "color": "#00440080"}]"##).unwrap();
+    assert_eq!(list.classify_values(&[0.7, 0.1]), vec![7, NODATA_CLASS]);
+    assert_eq!(list.rules[0].color, [0, 0x44, 0, 0x80]);
+
+    assert!(parse_color("#12345").is_err());
+    assert!(Classification::parse("0:1=0:reserved").is_err());
+}
+
+#[test]
+fn test_color_ramp_rendering() {
+    let renderer = Renderer::new(Colormap::from_name("RdYlGn").unwrap(), Some((0.0, 1.0))).unwrap();
+    assert_eq!(renderer.color(0.0), [0xa5, 0x00, 0x26, 255]);
+    assert_eq!(renderer.color(0.5), [0xff, 0xff, 0xbf, 255]);
+    assert_eq!(renderer.color(2.0), [0x00, 0x68, 0x37, 255]);
+    assert_eq!(renderer.color(-999.0), [0, 0, 0, 0]);
+
+    let greys = Renderer::new(Colormap::from_name("greys_r").unwrap(), Some((-1.0, 1.0))).unwrap();
+    assert_eq!(greys.color(0.0), [128, 128, 128, 255]);
+    let [red, _, _, alpha] = greys.render_values(&[-1.0, -999.0]);
+    assert_eq!((red, alpha), (vec![255, 0], vec![255, 0]));
+
+    let path = std::env::temp_dir().join("raster_calc_test_colors.txt");
+    std::fs::write(&path, "# water to vegetation\n-0.5 0 0 255\n0.5 0 255 0 128\nnv 255 0 0\n").unwrap();
+    let file = Renderer::new(Colormap::parse(&path.to_string_lossy()).unwrap(), None).unwrap();
+    assert_eq!(file.color(0.0), [0, 128, 128, 192]);
+    assert_eq!(file.color(-999.0), [255, 0, 0, 255]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(Colormap::from_name("rainbow").is_err());
+    assert!(Renderer::new(Colormap::from_name("viridis").unwrap(), Some((1.0, 0.0))).is_err());
+    assert_eq!(parse_range("-0.2, 0.8").unwrap(), (-0.2, 0.8));
+    assert_eq!(default_render_path("out/ndvi.tif"), "out/ndvi_rgba.tif");
+}
+
+#[test]
+fn test_sentinel2_band_references() {
+    assert!(is_band_name("B04") && is_band_name("B8A") && is_band_name("SCL") && is_band_name("B11_20m"));
+    assert!(!is_band_name("B123") && !is_band_name("ndvi.tif") && !is_band_name("B04_20"));
+
+    assert_eq!(
+        parse_band_reference("S2A_MSIL2A_20230615.SAFE:B11_20m"),
+        Some(("S2A_MSIL2A_20230615.SAFE", "B11", Some(20)))
+    );
+    assert_eq!(parse_band_reference("/data/S2B.zip:SCL"), Some(("/data/S2B.zip", "SCL", None)));
+    assert_eq!(parse_band_reference("C:/data/B04.tif"), None);
+
+    assert_eq!(qualify_input("B08", Some("S2.SAFE"), None), "S2.SAFE:B08");
+    assert_eq!(qualify_input("B11", Some("S2.SAFE"), Some(20)), "S2.SAFE:B11_20m");
+    assert_eq!(qualify_input("S2.SAFE:SCL", None, Some(20)), "S2.SAFE:SCL_20m");
+    assert_eq!(qualify_input("red.tif", Some("S2.SAFE"), Some(20)), "red.tif");
+}
+
+#[test]
+fn test_input_scaling() {
+    // Sentinel-2 processing baseline 04.00: reflectance = (DN - 1000) / 10000, DN 0 is nodata
+    let s2 = InputScaling::new(1.0 / 10000.0, -1000.0 / 10000.0, Some(0.0), ScalingSource::Sentinel2);
+    let mut inputs = vec![
+        TypedBuffer::F32(Buffer::new((3, 1), vec![0.0, 1000.0, 4000.0])),
+        TypedBuffer::F32(Buffer::new((3, 1), vec![0.0, 5000.0, 10000.0])),
+    ];
+    scale_inputs(&mut inputs, &[s2, InputScaling::NONE], Some(10000.0));
+    let scaled = inputs[0].as_f32().unwrap().data();
+    assert_eq!(scaled[0], 0.0);
+    assert!(scaled[1].abs() < 1e-6 && (scaled[2] - 0.3).abs() < 1e-6);
+    assert_eq!(inputs[1].as_f32().unwrap().data(), &[0.0, 0.5, 1.0]);
+
+    assert_eq!(
+        split_band_name("LC08_L2SP_192029_20230615_20230622_02_T1_SR_B4.TIF"),
+        Some(("LC08_L2SP_192029_20230615_20230622_02_T1".to_string(), "4".to_string()))
+    );
+    let dir = std::env::temp_dir().join("raster_calc_test_landsat");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("LC08_L2SP_192029_20230615_20230622_02_T1_MTL.txt"),
+        // Level-2 files repeat the reflectance keys with their TOA factors in the Level-1 group
+        "GROUP = LANDSAT_METADATA_FILE\n\
+         GROUP = LEVEL2_SURFACE_REFLECTANCE_PARAMETERS\n  REFLECTANCE_MULT_BAND_4 = 2.75E-05\n  REFLECTANCE_ADD_BAND_4 = -0.2\nEND_GROUP = LEVEL2_SURFACE_REFLECTANCE_PARAMETERS\n\
+         GROUP = LEVEL1_RADIOMETRIC_RESCALING\n  REFLECTANCE_MULT_BAND_4 = 2.0000E-05\n  REFLECTANCE_ADD_BAND_4 = -0.100000\nEND_GROUP = LEVEL1_RADIOMETRIC_RESCALING\n\
+         END_GROUP = LANDSAT_METADATA_FILE\n",
+    )
+    .unwrap();
+    let band = dir.join("LC08_L2SP_192029_20230615_20230622_02_T1_SR_B4.TIF");
+    let cache = RasterCache::new();
+    assert_eq!(landsat::band_scaling(&band.to_string_lossy(), &cache).unwrap(), Some((2.75e-5, -0.2)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_landsat_products() {
+    let dir = std::env::temp_dir().join("LC09_L1TP_192029_20230615_20230616_02_T1");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("LC09_L1TP_192029_20230615_20230616_02_T1_MTL.json"),
+        r#"{"LANDSAT_METADATA_FILE": {
+            "PRODUCT_CONTENTS": {"PROCESSING_LEVEL": "L1TP",
+                "FILE_NAME_BAND_4": "LC09_L1TP_192029_20230615_20230616_02_T1_B4.TIF",
+                "FILE_NAME_QUALITY_L1_PIXEL": "LC09_L1TP_192029_20230615_20230616_02_T1_QA_PIXEL.TIF"},
+            "IMAGE_ATTRIBUTES": {"SPACECRAFT_ID": "LANDSAT_9", "SUN_ELEVATION": "30.0"},
+            "LEVEL1_RADIOMETRIC_RESCALING": {"REFLECTANCE_MULT_BAND_4": "2.0000E-05", "REFLECTANCE_ADD_BAND_4": "-0.100000"}
+        }}"#,
+    )
+    .unwrap();
+
+    let path = dir.to_string_lossy().to_string();
+    assert!(landsat::is_product_path(&path));
+    let product = LandsatProduct::open(&path).unwrap();
+    assert!(!product.is_level2());
+    assert_eq!(product.band_number("nir").unwrap(), 5);
+    assert_eq!(product.band_number("SR_B4").unwrap(), 4);
+    assert!(product.band_path("red").unwrap().ends_with("_B4.TIF"));
+    assert!(product.band_path("QA_PIXEL").unwrap().ends_with("_QA_PIXEL.TIF"));
+
+    // TOA reflectance corrected for a sun elevation of 30 degrees: (M * DN + A) / sin(30)
+    let (scale, offset) = product.reflectance_scaling("red").unwrap();
+    assert!((scale - 4.0e-5).abs() < 1e-9 && (offset + 0.2).abs() < 1e-6);
+    assert_eq!(product.reflectance_scaling("thermal"), None);
+
+    assert_eq!(qualify_input("nir", Some("S2.SAFE"), None), "S2.SAFE:nir");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cloud_masks() {
+    // Sentinel-2 SCL: clouds (8, 9, 10), shadow (3) and snow (11) by default
+    let scl = Mask::new("S2.SAFE:SCL", None, &[]).unwrap();
+    assert_eq!(scl.mask_type, MaskType::Scl);
+    assert_eq!(scl.values, vec![3, 8, 9, 10, 11]);
+    assert!(scl.is_masked(9.0) && !scl.is_masked(4.0));
+
+    // Landsat QA_PIXEL bits: dilated cloud (1), cirrus (2), cloud (3), shadow (4)
+    let qa = Mask::new("LC08_QA_PIXEL.TIF", None, &parse_values("cloud, shadow")).unwrap();
+    assert_eq!(qa.values, vec![1, 2, 3, 4]);
+    assert!(qa.is_masked(21824.0 + 8.0) && !qa.is_masked(21824.0));
+    assert!(Mask::new("qa.tif", Some("bits"), &["40".to_string()]).is_err());
+    assert!(Mask::new("qa.tif", None, &[]).is_err());
+
+    // MODIS state_1km cloud state (bits 0-1): cloudy (01) and mixed (10), not assumed clear (11)
+    let modis = Mask::new("MOD09GA.state_1km.tif", None, &["cloud".to_string()]).unwrap();
+    assert!(modis.is_masked(1.0) && modis.is_masked(2.0));
+    assert!(!modis.is_masked(0.0) && !modis.is_masked(3.0));
+    assert!(modis.is_masked(3.0 + 1024.0));
+
+    let mut results = vec![TypedBuffer::F32(Buffer::new((3, 1), vec![0.1, 0.2, 0.3]))];
+    scl.apply(&TypedBuffer::F32(Buffer::new((3, 1), vec![4.0, 8.0, 11.0])), &mut results);
+    assert_eq!(results[0].as_f32().unwrap().data(), &[0.1, NODATA_VALUE_FLOAT, NODATA_VALUE_FLOAT]);
+}
+
+#[test]
+fn test_stac_items() {
+    let dir = std::env::temp_dir().join("raster_calc_test_stac");
+    std::fs::create_dir_all(&dir).unwrap();
+    let item_path = dir.join("S2B_32TQM_20230620_0_L2A.json");
+    std::fs::write(
+        &item_path,
+        r#"{"type": "Feature", "id": "S2B_32TQM_20230620_0_L2A", "assets": {
+            "nir": {"href": "./B08.tif", "eo:bands": [{"name": "B08", "common_name": "nir"}],
+                    "raster:bands": [{"nodata": 0, "scale": 0.0001, "offset": -0.1}]},
+            "swir16": {"href": "file:///data/B11.tif", "eo:bands": [{"name": "B11", "common_name": "swir16"}]},
+            "thumbnail": {"href": "https://example.com/thumb.jpg"}
+        }}"#,
+    )
+    .unwrap();
+    let item = item_path.to_string_lossy().to_string();
+
+    let opened = StacItem::open(&item).unwrap();
+    assert_eq!(opened.asset_path("B08").unwrap(), dir.join("B08.tif").to_string_lossy());
+    assert_eq!(opened.asset_path("swir1").unwrap(), "/data/B11.tif");
+    // Remote assets are never fetched
+    let remote = opened.asset_path("thumbnail").unwrap_err().to_string();
+    assert!(remote.contains("remote (https://example.com/thumb.jpg)"));
+    assert!(opened.asset_path("red").is_err());
+
+    let cache = RasterCache::new();
+    let reference = stac::qualify_input("nir", &item, &cache).unwrap();
+    assert_eq!(reference, format!("{}:nir", item));
+    assert_eq!(stac::qualify_input("red.tif", &item, &cache).unwrap(), "red.tif");
+    assert!(stac::qualify_input("nir", &dir.join("missing.json").to_string_lossy(), &cache).is_err());
+    assert_eq!(stac::band_scaling(&reference, &cache).unwrap(), Some((0.0001, -0.1, Some(0.0))));
+    assert_eq!(stac::band_scaling(&format!("{}:swir16", item), &cache).unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_provenance() {
+    let savi = SAVI::new(0, 1, 0.5, None);
+    assert_eq!(savi.formula(), "(NIR - RED) / (NIR + RED + L) * (1 + L)");
+    assert_eq!(savi.parameters(), vec![("soil_factor".to_string(), "0.5".to_string())]);
+    let change = ChangeDetection::new(NDI::new(0, 1, None));
+    assert_eq!(change.formula(), "post - pre of (A - B) / (A + B)");
+
+    assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+    assert_eq!(format_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+
+    let path = std::env::temp_dir().join("raster_calc_test_checksum.txt");
+    std::fs::write(&path, "abc").unwrap();
+    assert_eq!(
+        sha256_file(&path.to_string_lossy()).unwrap().as_deref(),
+        Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    );
+    // A rewritten file is hashed again
+    std::fs::write(&path, "abcd").unwrap();
+    assert_eq!(
+        sha256_file(&path.to_string_lossy()).unwrap().as_deref(),
+        Some("88d4266fd4e6338d13b845fcf289579d209c897823b9217da3e161936f031589")
+    );
+    assert_eq!(sha256_file("/vsimem/raster-calc/missing.vrt").unwrap(), None);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(stac::default_item_path("out/ndvi.tif"), "out/ndvi.json");
+    assert_eq!(stac::media_type("ndvi.tif"), "image/tiff; application=geotiff");
+}
+
+#[test]
+fn test_scene_templates() {
+    let pattern = "data/{tile}/S2_{date}_B04.tif";
+    assert_eq!(template::glob_pattern(pattern).unwrap(), "data/*/S2_*_B04.tif");
+    let scene = template::capture(pattern, "data/T32TQM/S2_20230615_B04.tif").unwrap().unwrap();
+    assert_eq!(scene["tile"], "T32TQM");
+    assert_eq!(scene["date"], "20230615");
+    assert!(template::capture(pattern, "data/T32TQM/old/S2_20230615_B04.tif").unwrap().is_none());
+    assert!(template::capture("{a}/{a}.tif", "x/y.tif").unwrap().is_none());
+    // Windows separators in glob results, glob metacharacters in literal parts
+    let scene = template::capture(pattern, "data\\T32TQM\\S2_20230615_B04.tif").unwrap().unwrap();
+    assert_eq!(scene["tile"], "T32TQM");
+    assert_eq!(template::glob_pattern("data[1]/{tile}_*.tif").unwrap(), "data[[]1[]]/*_[*].tif");
+    let scene = template::capture("data[1]/{tile}.tif", "data[1]/T32TQM.tif").unwrap().unwrap();
+    assert_eq!(scene["tile"], "T32TQM");
+
+    let vars: Variables = [("tile".to_string(), "T32TQM".to_string())].into();
+    assert_eq!(template::substitute("out/ndvi_{tile}.tif", &vars).unwrap(), "out/ndvi_T32TQM.tif");
+    assert_eq!(template::substitute("{not a placeholder}", &vars).unwrap(), "{not a placeholder}");
+    assert!(template::substitute("{date}", &vars).is_err());
+    let op = serde_json::json!({"params": {"a": "{tile}_B08.tif", "scale": 2}, "depends_on": ["{tile}"]});
+    assert_eq!(
+        template::substitute_value(&op, &vars).unwrap(),
+        serde_json::json!({"params": {"a": "T32TQM_B08.tif", "scale": 2}, "depends_on": ["T32TQM"]})
+    );
+}
+
+#[test]
+fn test_manifest() {
+    let dir = std::env::temp_dir().join("raster_calc_test_manifest");
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("ndvi.tif").to_string_lossy().to_string();
+    let path = manifest::default_path(&dir.join("batch.yaml"));
+    assert_eq!(path, dir.join("batch.manifest.json"));
+    let _ = std::fs::remove_file(&path);
+
+    let mut manifest = Manifest::load(&path).unwrap();
+    assert!(!manifest.is_up_to_date(&output, "abc", None));
+    manifest.record(&output, "abc", true, vec![output.clone()]);
+    // The output file does not exist yet
+    assert!(!manifest.is_up_to_date(&output, "abc", None));
+
+    std::fs::write(&output, "data").unwrap();
+    manifest.save(&path).unwrap();
+    let manifest = Manifest::load(&path).unwrap();
+    assert!(manifest.is_up_to_date(&output, "abc", None));
+    assert!(!manifest.is_up_to_date(&output, "changed", None));
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+    assert!(!manifest.is_up_to_date(&output, "abc", Some(later)));
+
+    let description = serde_json::json!({"type": "ndi"});
+    assert_eq!(manifest::fingerprint(&description), manifest::fingerprint(&description));
+    assert_eq!(manifest::fingerprint(&description).len(), 64);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_run_report() {
+    let mut statistics = BandStatistics::new("ndvi");
+    statistics.add(&[0.2, f32::NAN, NODATA_VALUE_FLOAT, 0.6]);
+    statistics.add(&[-0.2]);
+    assert_eq!(statistics.valid, 3);
+    assert_eq!(statistics.min, Some(-0.2));
+    assert_eq!(statistics.max, Some(0.6));
+    assert!((statistics.mean.unwrap() - 0.2).abs() < 1e-6);
+
+    let dir = std::env::temp_dir().join("raster_calc_test_report");
+    std::fs::create_dir_all(&dir).unwrap();
+    let operation = |number, status| OperationReport {
+        number,
+        id: None,
+        op_type: "ndi".to_string(),
+        output: format!("out{}.tif", number),
+        status,
+        attempts: 1,
+        errors: if status == OperationStatus::Failed { vec!["Failed".to_string()] } else { Vec::new() },
+        started: "2024-01-01T00:00:00Z".to_string(),
+        wall_seconds: 1.0,
+        read_seconds: 0.5,
+        compute_seconds: 0.3,
+        write_seconds: 0.2,
+        bytes_read: 1024,
+        pixels: 256,
+        cache_hits: 1,
+        cache_misses: 1,
+        bands: vec![statistics.clone()],
+    };
+    let summary = BatchSummary {
+        config: "batch.json".to_string(),
+        started: "2024-01-01T00:00:00Z".to_string(),
+        finished: "2024-01-01T00:00:02Z".to_string(),
+        wall_seconds: 2.0,
+        operations: 2,
+        succeeded: 1,
+        failed: 1,
+        skipped: 0,
+        up_to_date: 0,
+        cancelled: 0,
+        cached_datasets: 2,
+        cache_hits: 3,
+        cache_misses: 2,
+    };
+
+    // JSON: one document, operations in configuration order
+    let path = dir.join("run.json");
+    let writer = ReportWriter::create(&path).unwrap();
+    writer.operation(operation(2, OperationStatus::Failed)).unwrap();
+    writer.operation(operation(1, OperationStatus::Succeeded)).unwrap();
+    writer.finish(&summary).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(report["summary"]["failed"], 1);
+    assert_eq!(report["operations"][0]["status"], "succeeded");
+    assert_eq!(report["operations"][1]["errors"][0], "Failed");
+    assert_eq!(report["operations"][0]["bands"][0]["valid"], 3);
+
+    // NDJSON: a line per operation as it finishes, then the summary
+    let path = dir.join("run.ndjson");
+    let writer = ReportWriter::create(&path).unwrap();
+    writer.operation(operation(2, OperationStatus::Failed)).unwrap();
+    writer.finish(&summary).unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["record"], "operation");
+    assert_eq!(lines[0]["status"], "failed");
+    assert_eq!(lines[1]["record"], "summary");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_retry_policy() {
+    assert_eq!(OnError::from_name("fail_fast").unwrap(), OnError::FailFast);
+    assert_eq!(OnError::from_name("Continue").unwrap(), OnError::Continue);
+    assert!(OnError::from_name("stop").is_err());
+    assert!(RetryPolicy::new(1, -1.0).is_err());
+
+    let policy = RetryPolicy::new(3, 1.0).unwrap();
+    assert_eq!(policy.delay(1), std::time::Duration::from_secs(1));
+    assert_eq!(policy.delay(3), std::time::Duration::from_secs(4));
+    assert_eq!(policy.delay(30), RetryPolicy::MAX_DELAY);
+
+    // IO and GDAL failures are retried until none is left, others fail at once
+    let policy = RetryPolicy::new(2, 1.0).unwrap();
+    let io = anyhow::Error::from(std::io::Error::other("NFS hiccup")).context("Failed to read b08.tif");
+    assert!(retry::is_transient(&io));
+    assert_eq!(policy.next_delay(0, &io), Some(std::time::Duration::from_secs(1)));
+    assert_eq!(policy.next_delay(1, &io), Some(std::time::Duration::from_secs(2)));
+    assert_eq!(policy.next_delay(2, &io), None);
+    let gdal = anyhow::Error::from(gdal::errors::GdalError::NullPointer { method_name: "GDALOpen", msg: String::new() });
+    assert!(policy.next_delay(0, &gdal).is_some());
+    let params = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+    assert_eq!(policy.next_delay(0, &anyhow::Error::from(params).context("Error parsing ndi params")), None);
+    assert_eq!(policy.next_delay(0, &anyhow::anyhow!("class_band is 1-based")), None);
+}
+
+#[test]
+fn test_batch_thread_pools() {
+    let global = |threads| GlobalParams { threads: Some(threads), ..Default::default() };
+    let small = batch::thread_pool(&global(2)).unwrap();
+    let large = batch::thread_pool(&global(5)).unwrap();
+    assert_eq!(small.current_num_threads(), 2);
+    assert_eq!(large.current_num_threads(), 5);
+    // Work inside a run's pool uses its threads, whatever the global pool is
+    assert_eq!(small.install(rayon::current_num_threads), 2);
+    assert_eq!(large.install(|| small.install(rayon::current_num_threads)), 2);
+    assert_eq!(large.install(rayon::current_num_threads), 5);
+}
+
+#[test]
+fn test_scheduler() {
+    let scheduler = Scheduler::new(3, 100);
+    assert_eq!(scheduler.io_threads(), 3);
+
+    let budget = scheduler.budget();
+    let first = budget.try_reserve(60).unwrap();
+    assert!(budget.try_reserve(60).is_none());
+    // A processor holding nothing always gets its next block
+    let second = budget.reserve(60);
+    assert_eq!(budget.used(), 120);
+    drop(first);
+    drop(second);
+    assert_eq!(budget.used(), 0);
+
+    // Reads run on the shared readers, a failing one does not stop them
+    let (tx, rx) = flume::unbounded();
+    scheduler.submit(|| panic!("Unreadable block"));
+    for i in 0..10 {
+        let tx = tx.clone();
+        scheduler.submit(move || tx.send(i).unwrap());
+    }
+    drop(tx);
+    let mut done: Vec<i32> = rx.iter().collect();
+    done.sort();
+    assert_eq!(done, (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_memory_budget() {
+    assert_eq!(memory::parse_size("12G").unwrap(), 12 << 30);
+    assert_eq!(memory::parse_size("1.5GiB").unwrap(), 3 << 29);
+    assert_eq!(memory::parse_size("512").unwrap(), 512 << 20);
+    assert!(memory::parse_size("lots").is_err());
+    assert_eq!(memory::format_bytes(3 << 29), "1.5 GiB");
+
+    let shares = MemoryShares::split(1 << 30);
+    assert_eq!(shares.gdal_cache + shares.inflight + shares.working, 1 << 30);
+    assert_eq!(shares.block_limit(1), (256 << 20) / 4);
+    assert_eq!(shares.block_limit(4), (256 << 20) / 16);
+
+    // Native blocks are halved along their longer side until they fit, never below 64x64
+    let bytes_per_pixel = memory::block_bytes_per_pixel(2, 1);
+    assert_eq!(memory::fit_region((512, 512), bytes_per_pixel, 1 << 20), Some((256, 256)));
+    assert_eq!(memory::fit_region((10980, 1), bytes_per_pixel, 1 << 20), Some((10980, 1)));
+    assert_eq!(memory::fit_region((512, 512), bytes_per_pixel, 1 << 10), None);
+
+    // GDAL's cache is process-wide: the smallest share of the runs at once, restored after the last
+    let before = memory::gdal_cache();
+    let first = memory::GdalCacheShare::claim(64 << 20);
+    let second = memory::GdalCacheShare::claim(32 << 20);
+    assert_eq!(memory::gdal_cache(), 32 << 20);
+    drop(second);
+    assert_eq!(memory::gdal_cache(), 64 << 20);
+    drop(first);
+    assert_eq!(memory::gdal_cache(), before);
+}
+
+/// Operations of a batch config given as JSON
+fn batch_operations(operations: serde_json::Value) -> Vec<batch::Operation> {
+    batch::BatchConfig::from_value(serde_json::json!({ "operations": operations }))
+        .unwrap()
+        .operations
+}
+
+/// Test reference resolution, run order and the errors of the batch dependency graph
+#[test]
+fn test_batch_graph() {
+    let mut operations = batch_operations(serde_json::json!([
+        {"id": "dnbr", "type": "change", "output": "dnbr.tif",
+         "params": {"index": "raster", "pre": {"input": "@pre"}, "post": {"input": "@post"}}},
+        {"id": "pre", "type": "ndi", "params": {"a": "pre_b08.tif", "b": "pre_b12.tif"},
+         "output": "nbr_pre.tif", "intermediate": true},
+        {"id": "post", "type": "ndi", "params": {"a": "post_b08.tif", "b": "post_b12.tif"}, "output": "nbr_post.tif"},
+        {"type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif"}, "output": "ndvi.tif", "depends_on": ["dnbr"]}
+    ]));
+    let cache = RasterCache::new();
+    let graph = BatchGraph::build(&mut operations, &cache.scratch_dir()).unwrap();
+
+    // `@id` becomes the referenced output, intermediates move to the run's directory in memory
+    assert_eq!(operations[0].params["post"]["input"], "nbr_post.tif");
+    let pre = operations[0].params["pre"]["input"].as_str().unwrap();
+    assert!(pre.starts_with(&format!("{}/", cache.scratch_dir())) && pre.ends_with("nbr_pre.tif"));
+    assert_ne!(cache.scratch_dir(), RasterCache::new().scratch_dir());
+    assert_eq!(operations[1].output, pre);
+
+    assert_eq!(graph.dependencies, vec![vec![1, 2], vec![], vec![], vec![0]]);
+    assert_eq!(graph.dependents, vec![vec![3], vec![0], vec![0], vec![]]);
+    let mut roots = graph.roots();
+    roots.sort();
+    assert_eq!(roots, vec![1, 2]);
+    // Every operation runs after its dependencies
+    assert_eq!(graph.order.len(), 4);
+    let position = |i: usize| graph.order.iter().position(|&o| o == i).unwrap();
+    for (i, dependencies) in graph.dependencies.iter().enumerate() {
+        assert!(dependencies.iter().all(|&d| position(d) < position(i)));
+    }
+
+    let error = |operations: serde_json::Value| {
+        BatchGraph::build(&mut batch_operations(operations), &cache.scratch_dir()).unwrap_err().to_string()
+    };
+    let ndi = |id: &str, a: &str| {
+        serde_json::json!({"id": id, "type": "ndi", "params": {"a": a, "b": "b04.tif"}, "output": format!("{}.tif", id)})
+    };
+    let cycle = error(serde_json::json!([ndi("a", "@c"), ndi("b", "@a"), ndi("c", "@b"), ndi("d", "b08.tif")]));
+    assert_eq!(cycle, "Dependency cycle between operations: a, b, c");
+    assert_eq!(error(serde_json::json!([ndi("a", "b08.tif"), ndi("a", "b8a.tif")])), "Duplicate operation id 'a'");
+    let unknown = BatchGraph::build(&mut batch_operations(serde_json::json!([ndi("a", "@missing")])), &cache.scratch_dir())
+        .unwrap_err();
+    assert!(format!("{:#}", unknown).contains("refers to unknown operation 'missing'"));
+    let mut depends = ndi("a", "b08.tif");
+    depends["depends_on"] = serde_json::json!(["missing"]);
+    assert_eq!(error(serde_json::json!([depends])), "Operation 1 depends on unknown operation 'missing'");
+
+    // An intermediate nothing reads would never be written anywhere
+    let mut unused = ndi("a", "b08.tif");
+    unused["intermediate"] = serde_json::json!(true);
+    assert_eq!(
+        error(serde_json::json!([unused, ndi("b", "b8a.tif")])),
+        "Intermediate operation a is not used by any other operation"
+    );
+}
+
+/// Test that the same batch config reads the same from JSON, YAML and TOML
+#[test]
+fn test_batch_config_formats() {
+    let dir = std::env::temp_dir().join(format!("raster_calc_config_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let configs = [
+        (
+            "batch.json",
+            r#"{"global": {"threads": 2, "compress": "zstd"},
+                "operations": [{"type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif"},
+                                "output": "ndvi.tif", "float": true}]}"#,
+        ),
+        (
+            "batch.yaml",
+            "global:\n  threads: 2\n  compress: zstd\noperations:\n  - type: ndi\n    params:\n      a: b08.tif\n      b: b04.tif\n    output: ndvi.tif\n    float: true\n",
+        ),
+        (
+            "batch.toml",
+            "[global]\nthreads = 2\ncompress = \"zstd\"\n\n[[operations]]\ntype = \"ndi\"\noutput = \"ndvi.tif\"\nfloat = true\nparams = { a = \"b08.tif\", b = \"b04.tif\" }\n",
+        ),
+    ];
+
+    let loaded: Vec<serde_json::Value> = configs
+        .iter()
+        .map(|(name, text)| {
+            let path = dir.join(name);
+            std::fs::write(&path, text).unwrap();
+            serde_json::to_value(batch::BatchConfig::load(&path).unwrap()).unwrap()
+        })
+        .collect();
+    assert_eq!(loaded[0]["operations"][0]["params"]["b"], "b04.tif");
+    assert_eq!(loaded[0]["global"]["threads"], 2);
+    assert_eq!(loaded[1], loaded[0]);
+    assert_eq!(loaded[2], loaded[0]);
+
+    std::fs::write(dir.join("broken.yaml"), "operations: [").unwrap();
+    assert!(batch::BatchConfig::load(&dir.join("broken.yaml")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Write a single-band GeoTIFF of `width` x `height` pixels for validation tests
+fn write_grid_raster(path: &str, width: usize, height: usize) {
+    let driver = gdal::DriverManager::get_driver_by_name("GTiff").unwrap();
+    let mut dataset = driver.create_with_band_type::<u16, _>(path, width, height, 1).unwrap();
+    dataset.set_geo_transform(&[600000.0, 10.0, 0.0, 5000000.0, 0.0, -10.0]).unwrap();
+}
+
+/// Test the unknown key warnings, output collisions and grid mismatches batch validation reports
+#[test]
+fn test_batch_validation() {
+    let config = batch::BatchConfig::from_value(serde_json::json!({
+        "operations": [
+            {"type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif", "scale": 2}, "output": "ndvi.tif", "colour": "red"},
+            {"type": "ndi", "params": {"a": "b08.tif", "b": "b11.tif"}, "output": "ndvi.tif"},
+            {"type": "ndi", "params": {"a": "b08.tif", "b": "b12.tif"}, "output": "b12.tif"}
+        ]
+    }))
+    .unwrap();
+    let validation = batch::validate_config(&config, false);
+    assert!(validation.warnings.contains(&"Operation 1 (ndi): unknown key 'colour' is ignored".to_string()));
+    assert!(validation.warnings.contains(&"Operation 1 (ndi): unknown key 'params.scale' is ignored".to_string()));
+    assert_eq!(validation.warnings.len(), 2);
+    assert!(validation.errors.contains(&"Operation 2 (ndi): 'ndvi.tif' is also written by operation 1".to_string()));
+    assert!(validation.errors.contains(&"Operation 3 (ndi): 'b12.tif' is both an input and an output".to_string()));
+
+    // Types are matched without case: the class map of a `Change` with thresholds is an output too
+    let config = batch::BatchConfig::from_value(serde_json::json!({
+        "operations": [
+            {"type": "Change", "output": "dnbr.tif", "params": {"index": "ndi", "thresholds": [0.1],
+             "pre": {"a": "pre_b08.tif", "b": "pre_b12.tif"}, "post": {"a": "post_b08.tif", "b": "post_b12.tif"}}},
+            {"type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif"}, "output": "dnbr_classes.tif"}
+        ]
+    }))
+    .unwrap();
+    assert_eq!(config.operations[0].op_type, "change");
+    let validation = batch::validate_config(&config, false);
+    assert!(validation.errors.contains(&"Operation 2 (ndi): 'dnbr_classes.tif' is also written by operation 1".to_string()));
+
+    // A `Render` operation writes its rendering to its output, never an extra `_rgba.tif`
+    let config = batch::BatchConfig::from_value(serde_json::json!({
+        "operations": [
+            {"type": "Render", "params": {"input": "ndvi.tif", "colormap": "viridis"}, "output": "map.tif",
+             "render": {"colormap": "viridis"}},
+            {"type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif"}, "output": "map_rgba.tif"}
+        ]
+    }))
+    .unwrap();
+    let validation = batch::validate_config(&config, false);
+    assert!(!validation.errors.iter().any(|e| e.contains("is also written by")), "{:?}", validation.errors);
+
+    // Inputs must share the pixel grid of the first one
+    let dir = format!("/vsimem/raster_calc_validation_{}", std::process::id());
+    write_grid_raster(&format!("{}/b08.tif", dir), 4, 4);
+    write_grid_raster(&format!("{}/b04.tif", dir), 4, 4);
+    write_grid_raster(&format!("{}/b11.tif", dir), 2, 2);
+    let config = batch::BatchConfig::from_value(serde_json::json!({
+        "operations": [
+            {"type": "ndi", "params": {"a": format!("{}/b08.tif", dir), "b": format!("{}/b04.tif", dir)}, "output": "ndvi.tif"},
+            {"type": "ndi", "params": {"a": format!("{}/b08.tif", dir), "b": format!("{}/b11.tif", dir)}, "output": "ndmi.tif"}
+        ]
+    }))
+    .unwrap();
+    let validation = batch::validate_config(&config, true);
+    assert_eq!(validation.errors.len(), 1);
+    assert!(validation.errors[0].starts_with("Operation 2 (ndi):"));
+    assert!(validation.errors[0].contains("b11.tif' (2x2) is not on the pixel grid of"));
+    for name in ["b08.tif", "b04.tif", "b11.tif"] {
+        gdal::vsi::unlink_mem_file(format!("{}/{}", dir, name)).unwrap();
+    }
+}
+
+/// Test that the config schema ties the params of each operation type to its own fields
+#[test]
+fn test_batch_schema() {
+    let schema = batch::config_schema();
+    let operation = &schema["definitions"]["Operation"];
+    assert_eq!(operation["properties"]["params"]["type"], "object");
+
+    let by_type = operation["allOf"].as_array().unwrap();
+    assert_eq!(by_type.len(), 15);
+    let ndi = by_type
+        .iter()
+        .find(|rule| rule["if"]["properties"]["type"]["const"] == "ndi")
+        .unwrap();
+    assert_eq!(ndi["then"]["properties"]["params"]["$ref"], "#/definitions/NdiParams");
+    let required = &schema["definitions"]["NdiParams"]["required"];
+    assert_eq!(required, &serde_json::json!(["a", "b"]));
+    assert!(schema["definitions"]["GapfillParams"].is_object());
+}
+
+/// Test the merged settings, band counts, concurrency and JSON of a batch plan
+#[test]
+fn test_batch_plan() {
+    let mut config = batch::BatchConfig::from_value(serde_json::json!({
+        "global": {"threads": 3, "compress": "zstd", "write_stac": true, "auto_scale": false},
+        "operations": [
+            {"id": "ndvi", "type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif"}, "output": "ndvi.tif",
+             "float": true, "auto_scale": true},
+            {"id": "ndmi", "type": "ndi", "params": {"a": "b08.tif", "b": "b11.tif"}, "output": "ndmi.tif",
+             "compress": "lzw", "write_stac": false},
+            {"type": "timeseries", "params": {"inputs": ["a.tif", "b.tif"], "stats": ["mean", "slope", "count"]},
+             "output": "stats.tif"},
+            {"type": "phenology", "params": {"inputs": ["a.tif", "b.tif"]}, "output": "pheno.tif"},
+            {"type": "gapfill", "params": {"inputs": ["a.tif", "b.tif", "c.tif"]}, "output": "filled.tif"},
+            {"type": "composite", "params": {"index": "ndi", "scenes": [], "source_band": true}, "output": "best.tif"},
+            {"type": "render", "params": {"input": "@ndvi"}, "output": "ndvi_rgba.tif"},
+            {"type": "change", "params": {"index": "raster", "pre": {"input": "@ndvi"}, "post": {"input": "@ndmi"}},
+             "output": "change.tif"}
+        ]
+    }))
+    .unwrap();
+
+    let settings = batch::Settings::merge(&config.operations[0], &config.global);
+    assert!(settings.float && settings.auto_scale && settings.write_stac);
+    assert_eq!(settings.compress, "zstd");
+    let settings = batch::Settings::merge(&config.operations[1], &config.global);
+    assert!(!settings.float && !settings.auto_scale && !settings.write_stac);
+    assert_eq!(settings.compress, "lzw");
+
+    let graph = BatchGraph::build(&mut config.operations, "/vsimem/raster_calc_plan").unwrap();
+    let plan = batch::BatchPlan::new(&config, &graph);
+    let bands = |number: usize| plan.operations.iter().find(|p| p.number == number).unwrap().bands;
+    assert_eq!((bands(1), bands(3), bands(4), bands(5), bands(6), bands(7)), (1, 3, 7, 3, 2, 4));
+    // Six operations without dependencies, on three threads; render and change wait for ndvi
+    assert_eq!(plan.concurrent_operations, 3);
+    assert_eq!(plan.shared_inputs["b08.tif"], vec![1, 2]);
+
+    let json = serde_json::to_value(&plan).unwrap();
+    assert_eq!(json["threads"], 3);
+    assert_eq!(json["operations"].as_array().unwrap().len(), 8);
+    let change = json["operations"].as_array().unwrap().iter().find(|p| p["number"] == 8).unwrap();
+    assert_eq!(change["type"], "change");
+    assert_eq!(change["after"], serde_json::json!([1, 2]));
+    assert_eq!(change["outputs"], serde_json::json!(["change.tif", "change.json"]));
+    assert_eq!(change["size"], serde_json::Value::Null);
+    assert_eq!(change["settings"]["compress"], "zstd");
+}
+
+/// Test that the memory plan counts rasters processed whole at their full size
+#[test]
+fn test_batch_memory_plan() {
+    let dir = format!("/vsimem/raster_calc_memory_plan_{}", std::process::id());
+    write_grid_raster(&format!("{}/b08.tif", dir), 512, 512);
+    write_grid_raster(&format!("{}/b04.tif", dir), 512, 512);
+    let mut config = batch::BatchConfig::from_value(serde_json::json!({
+        "operations": [
+            {"type": "ndi", "params": {"a": format!("{}/b08.tif", dir), "b": format!("{}/b04.tif", dir)}, "output": "ndvi.tif"}
+        ]
+    }))
+    .unwrap();
+    let graph = BatchGraph::build(&mut config.operations, "/vsimem/raster_calc_memory_plan").unwrap();
+    let plan = || batch::BatchPlan::new(&config, &graph);
+
+    // 512x512 is never split: two inputs and one output band take 4 MiB however small the blocks
+    let whole = 512 * 512 * memory::block_bytes_per_pixel(2, 1);
+    let error = plan().with_memory_budget(32 << 20, &config.global).unwrap_err().to_string();
+    assert!(error.contains("its smallest blocks need 4.0 MiB"), "{}", error);
+    let memory = plan().with_memory_budget(64 << 20, &config.global).unwrap().memory.unwrap();
+    // GDAL cache, read-ahead of both inputs and the whole raster
+    assert_eq!(memory.peak_bytes, (16 << 20) + 512 * 512 * 4 * 2 + whole);
+    for name in ["b08.tif", "b04.tif"] {
+        gdal::vsi::unlink_mem_file(format!("{}/{}", dir, name)).unwrap();
+    }
+}
+
+/// Test that 1-based band options of batch operations reject 0 instead of using band 1
+#[test]
+fn test_batch_one_based_bands() {
+    let config = batch::BatchConfig::from_value(serde_json::json!({
+        "operations": [
+            {"type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif"}, "output": "ndvi.tif",
+             "classify": [0.2, 0.5], "class_band": 0},
+            {"type": "ndi", "params": {"a": "b08.tif", "b": "b04.tif"}, "output": "ndwi.tif",
+             "render": {"colormap": "viridis", "band": 0}}
+        ]
+    }))
+    .unwrap();
+    let validation = batch::validate_config(&config, false);
+    assert!(validation.errors.iter().any(|e| e.contains("class_band is 1-based")));
+    assert!(validation.errors.iter().any(|e| e.contains("Invalid rendering: band is 1-based")));
+}
+
+/// Test that two batches with the same intermediate output run at once, each reading its own
+#[test]
+fn test_concurrent_batches() {
+    let base = std::env::temp_dir().join(format!("raster_calc_test_concurrent_{}", std::process::id()));
+    let configs: Vec<_> = [("a", 3u16, 1u16), ("b", 1, 3)]
+        .into_iter()
+        .map(|(name, nir, red)| {
+            let dir = base.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            let driver = gdal::DriverManager::get_driver_by_name("GTiff").unwrap();
+            for (file, value) in [("b08.tif", nir), ("b04.tif", red)] {
+                let path = dir.join(file);
+                let mut dataset = driver.create_with_band_type::<u16, _>(&path, 4, 4, 1).unwrap();
+                dataset.set_geo_transform(&[600000.0, 10.0, 0.0, 5000000.0, 0.0, -10.0]).unwrap();
+                let mut buffer = Buffer::new((4, 4), vec![value; 16]);
+                dataset.rasterband(1).unwrap().write((0, 0), (4, 4), &mut buffer).unwrap();
+            }
+
+            let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+            let config = serde_json::json!({
+                "global": {"float": true, "threads": 2},
+                "operations": [
+                    {"id": "pre", "type": "ndi", "params": {"a": path("b08.tif"), "b": path("b04.tif")},
+                     "output": "nbr.tif", "intermediate": true},
+                    {"type": "ndi", "params": {"a": "@pre", "b": path("b04.tif")}, "output": path("out.tif")}
+                ]
+            });
+            let config_path = dir.join("batch.json");
+            std::fs::write(&config_path, config.to_string()).unwrap();
+            config_path
+        })
+        .collect();
+
+    let options = batch::BatchOptions::default();
+    std::thread::scope(|scope| {
+        let runs: Vec<_> = configs
+            .iter()
+            .map(|config| scope.spawn(|| batch::process_batch(config, &options)))
+            .collect();
+        for run in runs {
+            run.join().unwrap().unwrap();
+        }
+    });
+
+    // (3 - 1) / (3 + 1) = 0.5 then (0.5 - 1) / (0.5 + 1), and (1 - 3) / (1 + 3) = -0.5 then (-0.5 - 3) / (-0.5 + 3)
+    for (name, expected) in [("a", -1.0 / 3.0), ("b", -1.4)] {
+        let output = gdal::Dataset::open(base.join(name).join("out.tif")).unwrap();
+        let values = output.rasterband(1).unwrap().read_band_as::<f32>().unwrap();
+        assert!(values.data().iter().all(|v| (v - expected).abs() < 1e-5), "{}: {:?}", name, values.data());
+    }
+    std::fs::remove_dir_all(&base).unwrap();
+}